@@ -0,0 +1,146 @@
+use serde_json::{json, Map, Value};
+use crate::petri::*;
+use crate::kinetics::*;
+use crate::interchange::{delay_warnings, timing_warnings, unexported_parts, Import};
+
+// AlgebraicPetri's `LabelledReactionNet`, as written by Catlab's
+// `write_json_acset`: a table for each part, with the species in `S`
+// (`sname`, `concentration`), the transitions in `T` (`tname`, `rate`), and
+// the input and output arcs in `I` (`is`, `it`) and `O` (`os`, `ot`). Parts
+// are referenced by one-based row numbers, and an arc of weight k is k rows.
+//
+// Only mass action transitions without read or inhibitor arcs have a
+// counterpart there. Other rate laws are written as mass action, and read
+// arcs as an input and an output arc, with a warning.
+
+// Writes `petri` as a `LabelledReactionNet`, with the initial values and
+// rates of `controls`, and warnings for what could not be written
+pub fn write_acset(petri: &PetriNet, controls: &PlotControls) -> (String, Vec<String>) {
+    let mut warnings = vec![];
+    let rates = petri.effective_rates(controls);
+    let species: Vec<Value> = petri.species.iter().enumerate()
+        .map(|(s, name)| json!({ "sname": name, "concentration": controls.init_vals.get(s).cloned().unwrap_or(0.) }))
+        .collect();
+    let transitions: Vec<Value> = petri.transitions.iter().zip(rates.iter())
+        .map(|(t, rate)| json!({ "tname": t.name, "rate": rate }))
+        .collect();
+    let (mut inputs, mut outputs) = (vec![], vec![]);
+    for (i, t) in petri.transitions.iter().enumerate() {
+        for s in 0..petri.species.len() {
+            for _ in 0..t.arc(IO::Input, s) + t.arc(IO::Read, s) {
+                inputs.push(json!({ "is": s + 1, "it": i + 1 }));
+            }
+            for _ in 0..t.arc(IO::Output, s) + t.arc(IO::Read, s) {
+                outputs.push(json!({ "os": s + 1, "ot": i + 1 }));
+            }
+        }
+        if !matches!(t.rate_law, RateLaw::MassAction) {
+            warnings.push(format!("transition {}: the {} rate law is written as mass action", t.name,
+                                  RATE_LAWS[t.rate_law.to_idx()]));
+        }
+        if (0..petri.species.len()).any(|s| t.arc(IO::Read, s) > 0) {
+            warnings.push(format!("transition {}: read arcs are written as an input and an output arc", t.name));
+        }
+        if (0..petri.species.len()).any(|s| t.arc(IO::Inhibit, s) > 0) {
+            warnings.push(format!("transition {}: inhibitor arcs are not exported", t.name));
+        }
+        warnings.extend(timing_warnings(t, "not exported"));
+        warnings.extend(delay_warnings(petri, i, "not exported"));
+    }
+    warnings.extend(unexported_parts(petri));
+    let acset = json!({ "S": species, "T": transitions, "I": inputs, "O": outputs });
+    (serde_json::to_string_pretty(&acset).unwrap(), warnings)
+}
+
+// The rows of table `name`, none if it is missing
+fn table<'a>(acset: &'a Map<String, Value>, name: &str) -> Result<Vec<&'a Map<String, Value>>, String> {
+    match acset.get(name) {
+        None => Ok(vec![]),
+        Some(Value::Array(rows)) => rows.iter().enumerate()
+            .map(|(i, r)| r.as_object().ok_or_else(|| format!("row {} of table {} is not an object", i + 1, name)))
+            .collect(),
+        Some(_) => Err(format!("table {} is not an array", name)),
+    }
+}
+
+// A one-based reference to one of `n` parts, as a zero-based index
+fn part(row: &Map<String, Value>, column: &str, n: usize, table: &str, i: usize) -> Result<usize, String> {
+    match row.get(column).and_then(|v| v.as_u64()) {
+        Some(k) if k >= 1 && k as usize <= n => Ok(k as usize - 1),
+        Some(k) => Err(format!("row {} of table {}: {} = {} is out of range", i + 1, table, column, k)),
+        None => Err(format!("row {} of table {}: {} is missing", i + 1, table, column)),
+    }
+}
+
+// A label, which Catlab writes as a string, or as a number for some nets
+fn name(row: &Map<String, Value>, column: &str) -> Option<String> {
+    match row.get(column)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(x) => Some(x.to_string()),
+        _otherwise => None,
+    }
+}
+
+// Reads a `LabelledReactionNet`, or a `LabelledPetriNet` or `ReactionNet`
+// with the missing labels, rates or concentrations filled in
+pub fn read_acset(src: &str) -> Result<Import, String> {
+    let value: Value = serde_json::from_str(src).map_err(|e| e.to_string())?;
+    let acset = value.as_object().ok_or("expected an object with the tables S, T, I and O")?;
+    let mut warnings = vec![];
+    let (species, transitions) = (table(acset, "S")?, table(acset, "T")?);
+    let (inputs, outputs) = (table(acset, "I")?, table(acset, "O")?);
+    let (n, m) = (species.len(), transitions.len());
+
+    let mut names: Vec<String> = vec![];
+    let mut missing = vec![];
+    for (s, row) in species.iter().enumerate() {
+        let name = match name(row, "sname") {
+            Some(name) if !names.contains(&name) => name,
+            _otherwise => format!("S{}", s + 1),
+        };
+        names.push(name);
+    }
+    let mut petri = PetriNet::new("", names, vec![]);
+    for (i, row) in transitions.iter().enumerate() {
+        let name = match name(row, "tname") {
+            Some(name) if !petri.transitions.iter().any(|t| t.name == name) => name,
+            _otherwise => format!("T{}", i + 1),
+        };
+        petri.transitions.push(Transition::new(&name, vec![0; n], vec![0; n]));
+    }
+
+    let mut controls = PlotControls::empty();
+    controls.init_vals = species.iter()
+        .map(|row| row.get("concentration").and_then(|v| v.as_f64()).unwrap_or_else(|| {
+            missing.push("concentration");
+            0.
+        }) as f32)
+        .collect();
+    controls.rates = transitions.iter()
+        .map(|row| row.get("rate").and_then(|v| v.as_f64()).unwrap_or_else(|| {
+            missing.push("rate");
+            0.
+        }) as f32)
+        .collect();
+    missing.dedup();
+    for column in missing {
+        warnings.push(format!("some rows have no {}, which was set to 0", column));
+    }
+
+    for (i, row) in inputs.iter().enumerate() {
+        let (s, t) = (part(row, "is", n, "I", i)?, part(row, "it", m, "I", i)?);
+        let tr = &mut petri.transitions[t];
+        tr.set_arc(IO::Input, s, tr.arc(IO::Input, s) + 1);
+    }
+    for (i, row) in outputs.iter().enumerate() {
+        let (s, t) = (part(row, "os", n, "O", i)?, part(row, "ot", m, "O", i)?);
+        let tr = &mut petri.transitions[t];
+        tr.set_arc(IO::Output, s, tr.arc(IO::Output, s) + 1);
+    }
+
+    let known = ["S", "T", "I", "O"];
+    for key in acset.keys().filter(|k| !known.contains(&k.as_str())) {
+        warnings.push(format!("the table or attribute {} is not supported and was skipped", key));
+    }
+    Ok(Import::new(petri, controls, warnings))
+}
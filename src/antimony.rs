@@ -0,0 +1,491 @@
+use crate::petri::*;
+use crate::expr::*;
+use crate::kinetics::*;
+use crate::sbml::{read_mass_action, MassActionRate};
+use crate::interchange::{inline_rules, Import};
+
+// Import of the part of Antimony that maps onto nets: species, reactions
+// with their stoichiometry and rate laws, and assignments of values to
+// species and parameters, e.g.
+//
+//     model *SIR()
+//       J1: S + I => 2 I; beta * S * I
+//       J2: I => R; gamma * I
+//       S = 99; I = 1; R = 0
+//       beta = 0.003; gamma = 0.1
+//     end
+//
+// Rate laws that are a rate times the mass action term of the reactants are
+// read as mass action, and others as expressions. Boundary species (`$S` or
+// `const species S`) take part in rates through read arcs, without being
+// changed. Reversible reactions (`->`) are reported, and their rate law is
+// read as the forward rate. Assignment rules to names other than species
+// become observables, and are inlined into the rate laws that use them.
+// Events, other rules, functions, units and submodels are reported and
+// skipped.
+
+// The source with comments blanked out, keeping the line numbers
+fn strip_comments(src: &str) -> String {
+    let mut out = String::new();
+    let mut chars = src.chars().peekable();
+    let (mut block, mut line, mut quoted) = (false, false, false);
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                line = false;
+                out.push(c);
+            }
+            _ if block => {
+                if c == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    block = false;
+                }
+            }
+            _ if line => { }
+            '"' => {
+                quoted = !quoted;
+                out.push(c);
+            }
+            '/' if !quoted && chars.peek() == Some(&'/') => line = true,
+            '#' if !quoted => line = true,
+            '/' if !quoted && chars.peek() == Some(&'*') => {
+                chars.next();
+                block = true;
+            }
+            _otherwise => out.push(c),
+        }
+    }
+    out
+}
+
+fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// An Antimony expression as one of ours: `time` is `t`, `ln` is `log`, and
+// `pow(a, b)` is `a^b`
+fn translate(e: Expr) -> Expr {
+    match e {
+        Expr::Var(v, p) if v == "time" => Expr::Var("t".to_string(), p),
+        Expr::Neg(a) => Expr::Neg(Box::new(translate(*a))),
+        Expr::Bin(op, a, b) => Expr::Bin(op, Box::new(translate(*a)), Box::new(translate(*b))),
+        Expr::Call(f, p, args) => {
+            let mut args: Vec<Expr> = args.into_iter().map(translate).collect();
+            match f.as_str() {
+                "ln" => Expr::Call("log".to_string(), p, args),
+                "pow" if args.len() == 2 => {
+                    let b = args.pop().unwrap();
+                    Expr::Bin(BinOp::Pow, Box::new(args.pop().unwrap()), Box::new(b))
+                }
+                _otherwise => Expr::Call(f, p, args),
+            }
+        }
+        e => e,
+    }
+}
+
+// `e` with the variables in `values` replaced by their values
+fn substitute(e: Expr, values: &[(String, f64)]) -> Expr {
+    match e {
+        Expr::Var(v, p) => match values.iter().find(|(n, _)| *n == v) {
+            Some((_, x)) => Expr::Num(*x),
+            None => Expr::Var(v, p),
+        },
+        Expr::Neg(a) => Expr::Neg(Box::new(substitute(*a, values))),
+        Expr::Bin(op, a, b) => Expr::Bin(op, Box::new(substitute(*a, values)), Box::new(substitute(*b, values))),
+        Expr::Call(f, p, args) => Expr::Call(f, p, args.into_iter().map(|a| substitute(a, values)).collect()),
+        e => e,
+    }
+}
+
+struct Reaction {
+    name: String,
+    line: usize,
+    // (species, weight) of the reactants and products
+    reactants: Vec<(String, i32)>,
+    products: Vec<(String, i32)>,
+    law: Option<Expr>,
+}
+
+#[derive(Default)]
+struct Model {
+    warnings: Vec<String>,
+    // Names in order of declaration or first use
+    species: Vec<String>,
+    boundary: Vec<String>,
+    compartments: Vec<String>,
+    reactions: Vec<Reaction>,
+    // Initial values and parameter values, as written
+    assignments: Vec<(String, Expr, usize)>,
+    rules: Vec<(String, Expr)>,
+}
+
+impl Model {
+    fn warn(&mut self, line: usize, message: impl Into<String>) {
+        self.warnings.push(format!("line {}: {}", line, message.into()));
+    }
+
+    fn add_species(&mut self, name: &str) {
+        if !self.species.iter().any(|s| s == name) {
+            self.species.push(name.to_string());
+        }
+    }
+
+    fn expression(&mut self, src: &str, line: usize) -> Result<Expr, String> {
+        Expr::parse(src.trim()).map(translate)
+            .map_err(|e| format!("line {}: {}, at column {} of {}", line, e.message, e.pos + 1, src.trim()))
+    }
+
+    // A side of a reaction: terms `k S`, `kS` or `$S` joined by `+`
+    fn side(&mut self, src: &str, line: usize) -> Result<Vec<(String, i32)>, String> {
+        let mut terms = vec![];
+        for term in src.split('+').map(str::trim).filter(|t| !t.is_empty()) {
+            let split = term.find(|c: char| !(c.is_ascii_digit() || c == '.' || c.is_whitespace())).unwrap_or(term.len());
+            let (k, name) = (term[..split].trim(), term[split..].trim());
+            let boundary = name.starts_with('$');
+            let name = name.trim_start_matches('$');
+            if !is_name(name) {
+                return Err(format!("line {}: invalid species {}", line, term));
+            }
+            let k = match k {
+                "" => 1.,
+                k => k.parse::<f64>().map_err(|_| format!("line {}: invalid stoichiometry {}", line, k))?,
+            };
+            if k.fract() != 0. {
+                self.warn(line, format!("the stoichiometry {} of {} is rounded to a whole number", k, name));
+            }
+            if boundary && !self.boundary.iter().any(|b| b == name) {
+                self.boundary.push(name.to_string());
+            }
+            self.add_species(name);
+            terms.push((name.to_string(), k.round() as i32));
+        }
+        Ok(terms)
+    }
+
+    // A declaration such as `species S, $X = 2` or `const compartment C = 1`
+    fn declaration(&mut self, words: &[&str], rest: &str, line: usize) -> Result<(), String> {
+        let constant = words.contains(&"const");
+        let kind = words.iter().cloned().find(|w| ["species", "compartment", "formula", "parameter", "reaction"].contains(w));
+        for item in rest.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (item, value) = match item.find('=') {
+                Some(i) => (item[..i].trim(), Some(item[i + 1..].trim())),
+                None => (item, None),
+            };
+            // `S in C` places a species in a compartment
+            let name = item.split_whitespace().next().unwrap_or("");
+            let boundary = name.starts_with('$');
+            let name = name.trim_start_matches('$');
+            if !is_name(name) {
+                return Err(format!("line {}: invalid name {}", line, item));
+            }
+            match kind {
+                Some("species") => {
+                    self.add_species(name);
+                    if (boundary || constant) && !self.boundary.iter().any(|b| b == name) {
+                        self.boundary.push(name.to_string());
+                    }
+                }
+                Some("compartment") => self.compartments.push(name.to_string()),
+                _otherwise => { }
+            }
+            if let Some(value) = value {
+                let e = self.expression(value, line)?;
+                self.assignments.push((name.to_string(), e, line));
+            }
+        }
+        Ok(())
+    }
+
+    // A reaction, with `law` the statement after it if any
+    fn reaction(&mut self, name: Option<&str>, src: &str, law: Option<&str>, line: usize) -> Result<(), String> {
+        // `->` is reversible and `=>` irreversible
+        let (arrow, reversible) = match (src.find("=>"), src.find("->")) {
+            (Some(i), _) => (i, false),
+            (None, Some(i)) => (i, true),
+            (None, None) => return Err(format!("line {}: expected -> in a reaction", line)),
+        };
+        let name = match name {
+            Some(n) => n.trim().trim_start_matches('*').trim().to_string(),
+            None => format!("J{}", self.reactions.len()),
+        };
+        if reversible {
+            self.warn(line, format!("reaction {} is reversible; its rate law is read as the rate of the forward direction", name));
+        }
+        let reactants = self.side(&src[..arrow], line)?;
+        let products = self.side(&src[arrow + 2..], line)?;
+        let law = match law.map(str::trim).filter(|l| !l.is_empty()) {
+            Some(l) => Some(self.expression(l, line)?),
+            None => {
+                self.warn(line, format!("reaction {} has no rate law; using mass action with rate 0", name));
+                None
+            }
+        };
+        self.reactions.push(Reaction { name, line, reactants, products, law });
+        Ok(())
+    }
+
+    fn statement(&mut self, src: &str, next: Option<&str>, line: usize) -> Result<bool, String> {
+        // Whether `next` was taken as the rate law of a reaction
+        let src = src.trim();
+        let words: Vec<&str> = src.split_whitespace().collect();
+        let first = words.first().cloned().unwrap_or("");
+        let label = src.find(':').filter(|i| !src[*i..].starts_with(":=")).map(|i| (&src[..i], src[i + 1..].trim()));
+        if src.is_empty() {
+            return Ok(false);
+        }
+        if let Some((name, body)) = label {
+            if body.starts_with("at ") || body.starts_with("at(") {
+                self.warn(line, format!("event {} is not supported and was skipped", name.trim()));
+                return Ok(false);
+            }
+            if body.contains("->") || body.contains("=>") {
+                self.reaction(Some(name), body, next, line)?;
+                return Ok(true);
+            }
+            if body.contains("-|") || body.contains("-o") || body.contains("-(") {
+                self.warn(line, format!("interaction {} is not supported and was skipped", name.trim()));
+                return Ok(false);
+            }
+            if body.ends_with(')') {
+                self.warn(line, format!("the submodel {} is not supported and was skipped", name.trim()));
+                return Ok(false);
+            }
+        }
+        if src.contains("->") || src.contains("=>") {
+            self.reaction(None, src, next, line)?;
+            return Ok(true);
+        }
+        let modifiers = ["const", "var", "substanceOnly", "species", "compartment", "formula", "parameter"];
+        if modifiers.contains(&first) {
+            let n = words.iter().take_while(|w| modifiers.contains(w)).count();
+            let start = src.find(words.get(n).cloned().unwrap_or("")).filter(|_| n < words.len()).unwrap_or(src.len());
+            self.declaration(&words[..n], &src[start..], line)?;
+            return Ok(false);
+        }
+        match first {
+            "at" => self.warn(line, "events are not supported and were skipped"),
+            "unit" => self.warn(line, format!("the unit definition {} was skipped", words.get(1).unwrap_or(&""))),
+            "import" | "function" | "delete" | "DNA" | "operator" | "gene" =>
+                self.warn(line, format!("{} statements are not supported and were skipped", first)),
+            _otherwise => {
+                if let Some(i) = src.find(":=") {
+                    let name = src[..i].trim().to_string();
+                    let e = self.expression(&src[i + 2..], line)?;
+                    if !is_name(&name) {
+                        return Err(format!("line {}: invalid name {}", line, name));
+                    }
+                    self.rules.push((name, e));
+                } else if let Some(i) = src.find("'=").or_else(|| src.find("' =")) {
+                    self.warn(line, format!("the rate rule for {} is not supported and was skipped", src[..i].trim()));
+                } else if let Some(i) = src.find('=') {
+                    let name = src[..i].trim().trim_start_matches('$');
+                    if !is_name(name) {
+                        return Err(format!("line {}: invalid name {}", line, name));
+                    }
+                    let e = self.expression(&src[i + 1..], line)?;
+                    self.assignments.push((name.to_string(), e, line));
+                } else if words.len() >= 3 && (words[1] == "is" || words[1] == "has" || words[1] == "in") {
+                    // Annotations, units and compartments of a name
+                } else {
+                    self.warn(line, format!("the statement {} was not understood and was skipped", src));
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+// A model definition: its name, whether it is marked as the main model with
+// `*`, and its lines with their numbers
+type Definition = (String, bool, Vec<(usize, String)>);
+
+// The name and the numbered lines of the model to import: the model marked
+// with `*`, or else the last model, or the whole source if there are none
+fn main_model(src: &str, warnings: &mut Vec<String>) -> (String, Vec<(usize, String)>) {
+    let mut models: Vec<Definition> = vec![];
+    let mut top = vec![];
+    let mut current: Option<Definition> = None;
+    let mut function = false;
+    for (i, line) in src.lines().enumerate() {
+        let trimmed = line.trim();
+        let first = trimmed.split_whitespace().next().unwrap_or("");
+        if first == "function" {
+            warnings.push(format!("line {}: functions are not supported and were skipped", i + 1));
+            function = true;
+        } else if function {
+            function = first != "end";
+        } else if first == "model" || first == "module" {
+            let header = trimmed[first.len()..].trim();
+            let main = header.starts_with('*');
+            let name = header.trim_start_matches('*').split(|c: char| c == '(' || c.is_whitespace()).next().unwrap_or("").to_string();
+            current = Some((name, main, vec![]));
+        } else if first == "end" && current.is_some() {
+            models.extend(current.take());
+        } else {
+            match &mut current {
+                Some((_, _, lines)) => lines.push((i + 1, line.to_string())),
+                None => top.push((i + 1, line.to_string())),
+            }
+        }
+    }
+    models.extend(current);
+    let chosen = models.iter().position(|(_, main, _)| *main).or_else(|| models.len().checked_sub(1));
+    match chosen {
+        Some(m) => {
+            if models.len() > 1 {
+                warnings.push(format!("only the model {} was imported, and the other {} models were skipped", models[m].0, models.len() - 1));
+            }
+            let (name, _, lines) = models.swap_remove(m);
+            (name, lines)
+        }
+        None => (String::new(), top),
+    }
+}
+
+// Reads an Antimony model, reporting the constructs that could not be
+// represented in the warnings
+pub fn read_antimony(src: &str) -> Result<Import, String> {
+    let mut model = Model::default();
+    let (name, lines) = main_model(&strip_comments(src), &mut model.warnings);
+    for (number, line) in lines.iter() {
+        let statements: Vec<&str> = line.split(';').collect();
+        let mut i = 0;
+        while i < statements.len() {
+            let law = model.statement(statements[i], statements.get(i + 1).cloned(), *number)?;
+            i += if law { 2 } else { 1 };
+        }
+    }
+
+    // Values of the assignments that are constant, in any order
+    let mut values: Vec<(String, f64)> = vec![];
+    let mut pending: Vec<&(String, Expr, usize)> = model.assignments.iter().collect();
+    loop {
+        let before = pending.len();
+        pending.retain(|(name, e, _)| {
+            let known: Vec<String> = values.iter().map(|(n, _)| n.clone()).collect();
+            let resolve = |v: &str| known.iter().position(|k| k == v).map(|i| (0, i));
+            match e.compile(&resolve) {
+                Ok(f) => {
+                    let x = f(&[&values.iter().map(|(_, x)| *x).collect::<Vec<_>>()]);
+                    values.retain(|(n, _)| n != name);
+                    values.push((name.clone(), x));
+                    false
+                }
+                Err(_) => true,
+            }
+        });
+        if pending.is_empty() || pending.len() == before {
+            break;
+        }
+    }
+    for (name, e, line) in pending {
+        model.warnings.push(format!("line {}: the value {} of {} is not a constant; using 0", line, e, name));
+        values.push((name.clone(), 0.));
+    }
+    let value = |n: &str| values.iter().rev().find(|(v, _)| v == n).map(|(_, x)| *x);
+
+    let species = model.species.clone();
+    let n = species.len();
+    let mut petri = PetriNet::new(&name, species.clone(), vec![]);
+    let mut controls = PlotControls::empty();
+    controls.init_vals = species.iter().map(|s| value(s).unwrap_or(0.) as f32).collect();
+
+    // Compartments are read as having size 1, with their sizes substituted
+    // into the rate laws
+    let sizes: Vec<(String, f64)> = model.compartments.iter().map(|c| (c.clone(), value(c).unwrap_or(1.))).collect();
+    if sizes.iter().any(|(_, x)| *x != 1.) {
+        model.warnings.push("compartments are not represented: species are read as amounts, and compartment sizes are substituted into the rate laws".to_string());
+    }
+
+    // The other assigned names are parameters, except those with rules,
+    // which are observables
+    let mut parameters = vec![];
+    for (v, x) in values.iter() {
+        let taken = species.contains(v) || sizes.iter().any(|(c, _)| c == v) || model.rules.iter().any(|(r, _)| r == v)
+            || parameters.iter().any(|p: &Parameter| &p.name == v);
+        if !taken {
+            parameters.push(Parameter::new(v, *x as f32));
+        }
+    }
+    let mut rules = vec![];
+    for (v, e) in model.rules.iter() {
+        if species.contains(v) {
+            model.warnings.push(format!("the assignment rule for species {} is not supported and was skipped", v));
+        } else {
+            rules.push((v.clone(), substitute(e.clone(), &sizes)));
+        }
+    }
+    for (v, e) in rules.iter() {
+        match inline_rules(e, &rules) {
+            Ok(e) => petri.observables.push(Observable { name: v.clone(), expression: e.to_string() }),
+            Err(e) => model.warnings.push(format!("the rule for {}: {}", v, e)),
+        }
+    }
+    // Rate laws with the compartment sizes and the rules substituted, since
+    // expressions of the net cannot refer to observables
+    let mut laws = vec![];
+    for r in model.reactions.iter() {
+        laws.push(match r.law.clone().map(|l| inline_rules(&substitute(l, &sizes), &rules)) {
+            Some(Ok(l)) => Some(l),
+            Some(Err(e)) => {
+                model.warnings.push(format!("line {}: reaction {}: {}; using mass action with rate 0", r.line, r.name, e));
+                None
+            }
+            None => None,
+        });
+    }
+    // Names used in rate laws that are never given a value
+    let mut undefined = vec![];
+    for law in laws.iter() {
+        for v in law.iter().flat_map(|l| l.variables()) {
+            let known = species.contains(&v) || sizes.iter().any(|(c, _)| *c == v) || v == "t"
+                || parameters.iter().any(|p| p.name == v) || model.reactions.iter().any(|r| r.name == v);
+            if !known && !undefined.contains(&v) {
+                undefined.push(v);
+            }
+        }
+    }
+    for v in undefined {
+        model.warnings.push(format!("{} has no value; using 0", v));
+        parameters.push(Parameter::new(&v, 0.));
+    }
+
+    for (r, law) in model.reactions.iter().zip(laws.iter()) {
+        let mut t = Transition::new(&r.name, vec![0; n], vec![0; n]);
+        for (side, d) in [(&r.reactants, IO::Input), (&r.products, IO::Output)].iter() {
+            for (s, k) in side.iter() {
+                let i = species.iter().position(|x| x == s).unwrap();
+                match (d, model.boundary.contains(s)) {
+                    // Boundary species take part in the rate without being changed
+                    (IO::Input, true) => t.set_arc(IO::Read, i, t.arc(IO::Read, i) + k),
+                    (IO::Output, true) => { }
+                    (d, _) => t.set_arc(*d, i, t.arc(*d, i) + k),
+                }
+            }
+        }
+        let rate = match law {
+            Some(law) => match read_mass_action(law, &species, &parameters, &mut t) {
+                Some(MassActionRate::Value(x)) => x,
+                Some(MassActionRate::Parameter(p)) => {
+                    t.rate = Some(p.clone());
+                    parameters.iter().find(|q| q.name == p).unwrap().value
+                }
+                None => {
+                    t.rate_law = RateLaw::Expression(law.to_string());
+                    0.
+                }
+            },
+            None => 0.,
+        };
+        if petri.transitions.iter().any(|u| u.name == t.name) {
+            return Err(format!("line {}: the reaction {} is defined twice", r.line, t.name));
+        }
+        controls.rates.push(rate);
+        petri.transitions.push(t);
+    }
+    petri.parameters = parameters;
+
+    Ok(Import::new(petri, controls, model.warnings))
+}
@@ -0,0 +1,472 @@
+use ndarray::prelude::*;
+use crate::petri::*;
+use crate::kinetics::*;
+use crate::expr::*;
+use crate::xml::unique_id;
+use crate::diagram::latex_text;
+use crate::interchange::{delay_warnings, timing_warnings};
+
+// The ODEs of a net written out symbolically, as LaTeX equations and as code
+// that integrates them: a SciPy `solve_ivp` script, a DifferentialEquations.jl
+// script and a standalone Rust function. The flux of each transition is its
+// rate law times its rate constant, which is the parameter named by the
+// transition or a constant `k_<transition>` with the rate in the controls,
+// and each species changes by the net stoichiometry times the fluxes, as in
+// `PetriData::master_eq`.
+//
+// Schedules, events, output delays and firing time distributions have no
+// counterpart in plain ODEs and are left out with a warning.
+
+pub struct Odes {
+    pub name: String,
+    pub species: Vec<String>,
+    // Parameters of the net, then the rate constants of the transitions that
+    // have none, with their values
+    pub constants: Vec<(String, f64)>,
+    // The name of each transition and its flux, in terms of the species, the
+    // constants and `t`
+    pub fluxes: Vec<(String, Expr)>,
+    // Net change of each species (rows) when each transition fires (columns)
+    pub stoichiometry: Vec<Vec<i32>>,
+    pub initial: Vec<f64>,
+    pub t1: f64,
+}
+
+fn var(name: &str) -> Expr {
+    Expr::Var(name.to_string(), 0)
+}
+
+fn bin(op: BinOp, a: Expr, b: Expr) -> Expr {
+    Expr::Bin(op, Box::new(a), Box::new(b))
+}
+
+impl Odes {
+    pub fn new(petri: &PetriNet, controls: &PlotControls) -> (Self, Vec<String>) {
+        let mut warnings = vec![];
+        let n = petri.species.len();
+        let rates = petri.effective_rates(controls);
+        let mut used: Vec<String> = petri.species.iter().cloned()
+            .chain(petri.parameters.iter().map(|p| p.name.clone()))
+            .chain(std::iter::once("t".to_string()))
+            .collect();
+        let mut constants: Vec<(String, f64)> = petri.parameters.iter().map(|p| (p.name.clone(), exact(p.value))).collect();
+        let rate_names: Vec<String> = petri.transitions.iter().enumerate()
+            .map(|(i, t)| match t.rate.as_ref().filter(|r| petri.parameters.iter().any(|p| &p.name == *r)) {
+                Some(r) => r.clone(),
+                None => {
+                    let mut name = format!("k_{}", t.name);
+                    let mut k = 2;
+                    while used.contains(&name) {
+                        name = format!("k_{}_{}", t.name, k);
+                        k += 1;
+                    }
+                    used.push(name.clone());
+                    constants.push((name.clone(), exact(rates[i])));
+                    name
+                }
+            })
+            .collect();
+
+        let mut fluxes = vec![];
+        let species: Vec<Expr> = petri.species.iter().map(|s| var(s)).collect();
+        for (i, t) in petri.transitions.iter().enumerate() {
+            let participants: Array1<i32> = (0..n).map(|s| t.arc(IO::Input, s) + t.arc(IO::Read, s)).collect();
+            let inhibit: Array1<i32> = (0..n).map(|s| t.arc(IO::Inhibit, s)).collect();
+            // Transition names in expressions stand for their rates
+            let rename = |v: &str| match petri.transitions.iter().position(|t| t.name == v) {
+                Some(k) if !petri.species.iter().any(|s| s == v) && !petri.parameters.iter().any(|p| p.name == v) => var(&rate_names[k]),
+                _otherwise => var(v),
+            };
+            let flux = match t.rate_law.symbolic(participants.view(), inhibit.view(), &species, var(&rate_names[i]), &rename) {
+                Ok(flux) => flux,
+                Err(e) => {
+                    warnings.push(format!("transition {}: {}; written with flux 0", t.name, e));
+                    Expr::Num(0.)
+                }
+            };
+            fluxes.push((t.name.clone(), flux));
+
+            warnings.extend(timing_warnings(t, "left out"));
+            warnings.extend(delay_warnings(petri, i, "left out"));
+        }
+        if !petri.events.is_empty() {
+            warnings.push(format!("{} event(s) are left out", petri.events.len()));
+        }
+
+        let stoichiometry = (0..n)
+            .map(|s| petri.transitions.iter().map(|t| t.arc(IO::Output, s) - t.arc(IO::Input, s)).collect())
+            .collect();
+        let odes = Odes {
+            name: petri.name.clone(),
+            species: petri.species.clone(),
+            constants,
+            fluxes,
+            stoichiometry,
+            initial: (0..n).map(|s| exact(controls.init_vals.get(s).cloned().unwrap_or(0.))).collect(),
+            t1: exact(controls.xmax),
+        };
+        (odes, warnings)
+    }
+
+    // The right hand side for species `s`, as a sum of `flux(i)` for the
+    // transitions `i` that change it, times their net stoichiometry
+    pub fn derivative(&self, s: usize, flux: &dyn Fn(usize) -> Expr) -> Expr {
+        let mut sum: Option<Expr> = None;
+        for (i, c) in self.stoichiometry[s].iter().enumerate().filter(|(_, c)| **c != 0) {
+            let term = match c.abs() {
+                1 => flux(i),
+                k => bin(BinOp::Mul, Expr::Num(k as f64), flux(i)),
+            };
+            sum = Some(match (sum, *c > 0) {
+                (None, true) => term,
+                (None, false) => Expr::Neg(Box::new(term)),
+                (Some(a), true) => bin(BinOp::Add, a, term),
+                (Some(a), false) => bin(BinOp::Sub, a, term),
+            });
+        }
+        sum.unwrap_or(Expr::Num(0.))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Language {
+    Latex,
+    Python,
+    Julia,
+    Rust,
+}
+
+// Prints expressions in the syntax of `language`, with variables renamed by
+// `name` and only the parentheses needed. Comparisons are 1 when they hold
+// and 0 otherwise, as in `Expr::compile`.
+struct Printer<'a> {
+    language: Language,
+    name: &'a dyn Fn(&str) -> String,
+}
+
+fn comparison(op: BinOp) -> Option<&'static str> {
+    match op {
+        BinOp::Lt => Some("<"),
+        BinOp::Gt => Some(">"),
+        BinOp::Le => Some("<="),
+        BinOp::Ge => Some(">="),
+        BinOp::Eq => Some("=="),
+        BinOp::Ne => Some("!="),
+        _otherwise => None,
+    }
+}
+
+impl Printer<'_> {
+    // As `Expr::precedence`, with the constructs that are printed as atoms
+    // in some languages: fractions and Iverson brackets in LaTeX, and
+    // function calls and conditionals in Rust
+    fn precedence(&self, e: &Expr) -> u8 {
+        match (self.language, e) {
+            (Language::Latex, Expr::Bin(BinOp::Div, ..)) => 5,
+            (Language::Latex, Expr::Bin(op, ..)) | (Language::Rust, Expr::Bin(op, ..)) if comparison(*op).is_some() => 5,
+            (Language::Rust, Expr::Bin(BinOp::Pow, ..)) => 5,
+            (_, Expr::Num(x)) if *x < 0. => 3,
+            (_, Expr::Num(_)) | (_, Expr::Var(..)) | (_, Expr::Call(..)) => 5,
+            (_, Expr::Neg(_)) => 3,
+            (_, Expr::Bin(BinOp::Add, ..)) | (_, Expr::Bin(BinOp::Sub, ..)) => 1,
+            (_, Expr::Bin(BinOp::Mul, ..)) | (_, Expr::Bin(BinOp::Div, ..)) => 2,
+            (_, Expr::Bin(BinOp::Pow, ..)) => 4,
+            (_, Expr::Bin(..)) => 0,
+        }
+    }
+
+    // Whole numbers are integers but in Rust, and the others are written
+    // with `{:?}`, which switches to exponents for very small or large values
+    fn number(&self, x: f64) -> String {
+        let whole = x.fract() == 0. && x.abs() < 1e15;
+        match self.language {
+            Language::Latex => x.to_string(),
+            Language::Rust if whole => format!("{:.1}", x),
+            _otherwise if whole => format!("{}", x),
+            _otherwise => format!("{:?}", x),
+        }
+    }
+
+    fn parenthesized(&self, e: &Expr) -> String {
+        match self.language {
+            Language::Latex => format!("\\left({}\\right)", self.print(e, 0)),
+            _otherwise => format!("({})", self.print(e, 0)),
+        }
+    }
+
+    fn print(&self, e: &Expr, min: u8) -> String {
+        if self.precedence(e) < min {
+            return self.parenthesized(e);
+        }
+        let latex = self.language == Language::Latex;
+        match e {
+            Expr::Num(x) => self.number(*x),
+            Expr::Var(v, _) => (self.name)(v),
+            // -(a b) is (-a) b, so products need no parentheses
+            Expr::Neg(a) => format!("-{}", self.print(a, 2)),
+            Expr::Bin(op, a, b) => match (self.language, op, comparison(*op)) {
+                (Language::Latex, _, Some(symbol)) => {
+                    let symbol = match symbol {
+                        "<=" => "\\leq",
+                        ">=" => "\\geq",
+                        "==" => "=",
+                        "!=" => "\\neq",
+                        s => s,
+                    };
+                    format!("[{} {} {}]", self.print(a, 1), symbol, self.print(b, 1))
+                }
+                (Language::Rust, _, Some(symbol)) => {
+                    format!("(if {} {} {} {{ 1.0 }} else {{ 0.0 }})", self.print(a, 1), symbol, self.print(b, 1))
+                }
+                (_, _, Some(symbol)) => format!("{} {} {}", self.print(a, 1), symbol, self.print(b, 1)),
+                (Language::Latex, BinOp::Div, _) => format!("\\frac{{{}}}{{{}}}", self.print(a, 0), self.print(b, 0)),
+                (Language::Latex, BinOp::Pow, _) => {
+                    let base = match **a {
+                        Expr::Bin(BinOp::Div, ..) => self.parenthesized(a),
+                        _ => self.print(a, 5),
+                    };
+                    format!("{}^{{{}}}", base, self.print(b, 0))
+                }
+                (Language::Rust, BinOp::Pow, _) => match **b {
+                    Expr::Num(k) if k.fract() == 0. && k.abs() < 1e9 => format!("f64::powi({}, {})", self.print(a, 0), k),
+                    _ => format!("f64::powf({}, {})", self.print(a, 0), self.print(b, 0)),
+                },
+                (Language::Latex, BinOp::Mul, _) => {
+                    let separator = match **b {
+                        Expr::Num(_) | Expr::Neg(_) => " \\cdot ",
+                        _ => " ",
+                    };
+                    format!("{}{}{}", self.print(a, 2), separator, self.print(b, 3))
+                }
+                (language, _, _) => {
+                    let (left, right, symbol) = match op {
+                        BinOp::Add => (1, 2, " + "),
+                        BinOp::Sub => (1, 2, " - "),
+                        BinOp::Mul => (2, 3, " * "),
+                        BinOp::Div => (2, 3, " / "),
+                        _pow if language == Language::Python => (5, 3, "**"),
+                        _pow => (5, 3, "^"),
+                    };
+                    format!("{}{}{}", self.print(a, left), symbol, self.print(b, right))
+                }
+            },
+            Expr::Call(f, _, args) => {
+                let args: Vec<String> = args.iter().map(|a| self.print(a, 0)).collect();
+                match (self.language, f.as_str()) {
+                    (Language::Latex, "sqrt") => format!("\\sqrt{{{}}}", args.join(", ")),
+                    (Language::Latex, "abs") => format!("\\left|{}\\right|", args.join(", ")),
+                    (Language::Latex, "exp") | (Language::Latex, "log") | (Language::Latex, "min") | (Language::Latex, "max") =>
+                        format!("\\{}\\left({}\\right)", f, args.join(", ")),
+                    (Language::Python, "exp") | (Language::Python, "log") | (Language::Python, "sqrt") =>
+                        format!("np.{}({})", f, args.join(", ")),
+                    (Language::Rust, "log") => format!("f64::ln({})", args.join(", ")),
+                    (Language::Rust, _) => format!("f64::{}({})", f, args.join(", ")),
+                    _otherwise if latex => format!("\\operatorname{{{}}}\\left({}\\right)", latex_text(f), args.join(", ")),
+                    _otherwise => format!("{}({})", f, args.join(", ")),
+                }
+            }
+        }
+    }
+}
+
+static GREEK: [&str; 34] = ["alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa",
+                            "lambda", "mu", "nu", "xi", "pi", "rho", "sigma", "tau", "upsilon", "phi", "chi", "psi",
+                            "omega", "Gamma", "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Upsilon", "Phi",
+                            "Psi", "Omega"];
+
+// A LaTeX symbol for a name: Greek letters for their names, single letters
+// as they are and longer names upright, with what follows the first
+// underscore or the trailing digits as a subscript
+pub fn latex_symbol(name: &str) -> String {
+    let (base, subscript) = match name.find('_') {
+        Some(k) if k > 0 && k + 1 < name.len() => (&name[..k], Some(&name[k + 1..])),
+        _otherwise => {
+            let digits = name.trim_end_matches(|c: char| c.is_ascii_digit());
+            if !digits.is_empty() && digits.len() < name.len() {
+                (digits, Some(&name[digits.len()..]))
+            } else {
+                (name, None)
+            }
+        }
+    };
+    let symbol = |s: &str| {
+        if GREEK.contains(&s) {
+            format!("\\{}", s)
+        } else if s.chars().count() == 1 && s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            s.to_string()
+        } else {
+            format!("\\mathrm{{{}}}", latex_text(s).replace(' ', "\\ "))
+        }
+    };
+    match subscript {
+        Some(sub) => format!("{}_{{{}}}", symbol(base), symbol(sub)),
+        None => symbol(base),
+    }
+}
+
+// Writes the ODEs as an `align*` environment, with the values of the
+// constants and the initial values in comments
+pub fn write_latex(petri: &PetriNet, controls: &PlotControls) -> (String, Vec<String>) {
+    let (odes, warnings) = Odes::new(petri, controls);
+    let printer = Printer { language: Language::Latex, name: &latex_symbol };
+    let mut out = format!("% {}\n", odes.name);
+    out.push_str("\\begin{align*}\n");
+    let lines: Vec<String> = odes.species.iter().enumerate()
+        .map(|(s, species)| {
+            let rhs = odes.derivative(s, &|i| odes.fluxes[i].1.clone());
+            format!("  \\frac{{d{}}}{{dt}} &= {}", latex_symbol(species), printer.print(&rhs, 0))
+        })
+        .collect();
+    out.push_str(&lines.join(" \\\\\n"));
+    out.push_str("\n\\end{align*}\n");
+    for (c, value) in odes.constants.iter() {
+        out.push_str(&format!("% {} = {}\n", c, value));
+    }
+    for (s, value) in odes.species.iter().zip(odes.initial.iter()) {
+        out.push_str(&format!("% {}(0) = {}\n", s, value));
+    }
+    (out, warnings)
+}
+
+// Identifiers for the species, the constants and the transitions of `odes`,
+// distinct from each other and from `reserved`
+fn identifiers(odes: &Odes, reserved: &[&str]) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let mut used: Vec<String> = reserved.iter().map(|r| r.to_string()).collect();
+    let species = odes.species.iter().map(|s| unique_id(s, &mut used)).collect();
+    let constants = odes.constants.iter().map(|(c, _)| unique_id(c, &mut used)).collect();
+    let fluxes = odes.fluxes.iter().map(|(t, _)| unique_id(t, &mut used)).collect();
+    (species, constants, fluxes)
+}
+
+// The identifier of a variable of a flux, given the identifiers of the
+// species and constants
+fn lookup<'a>(odes: &'a Odes, species: &'a [String], constants: &'a [String]) -> impl Fn(&str) -> String + 'a {
+    move |v| {
+        odes.species.iter().position(|s| s == v).map(|s| species[s].clone())
+            .or_else(|| odes.constants.iter().position(|(c, _)| c == v).map(|c| constants[c].clone()))
+            .unwrap_or_else(|| v.to_string())
+    }
+}
+
+fn list(items: &[String]) -> String {
+    items.join(", ")
+}
+
+// Floating point literals in Python and Julia
+fn floats(xs: &[f64]) -> String {
+    xs.iter().map(|x| format!("{:?}", x)).collect::<Vec<_>>().join(", ")
+}
+
+static PYTHON_RESERVED: [&str; 47] = ["False", "None", "True", "and", "as", "assert", "async", "await", "break",
+                                      "class", "continue", "def", "del", "elif", "else", "except", "finally", "for",
+                                      "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or",
+                                      "pass", "raise", "return", "try", "while", "with", "yield", "np", "plt",
+                                      "solve_ivp", "rhs", "t", "y", "y0", "t_eval", "sol", "abs", "min", "max"];
+
+// A script that integrates the ODEs with `scipy.integrate.solve_ivp` on the
+// time span of the plot, and plots the trajectories
+pub fn write_scipy(petri: &PetriNet, controls: &PlotControls) -> (String, Vec<String>) {
+    let (odes, warnings) = Odes::new(petri, controls);
+    let (species, constants, fluxes) = identifiers(&odes, &PYTHON_RESERVED);
+    let name = lookup(&odes, &species, &constants);
+    let printer = Printer { language: Language::Python, name: &name };
+    let mut out = format!("# {}\nimport numpy as np\nfrom scipy.integrate import solve_ivp\nimport matplotlib.pyplot as plt\n\n", odes.name);
+    for (c, (_, value)) in constants.iter().zip(odes.constants.iter()) {
+        out.push_str(&format!("{} = {}\n", c, printer.number(*value)));
+    }
+    out.push_str("\n\ndef rhs(t, y):\n");
+    out.push_str(&format!("    [{}] = y\n", list(&species)));
+    for (f, (_, flux)) in fluxes.iter().zip(odes.fluxes.iter()) {
+        out.push_str(&format!("    {} = {}\n", f, printer.print(flux, 0)));
+    }
+    out.push_str("    return [\n");
+    for (s, id) in species.iter().enumerate() {
+        let rhs = odes.derivative(s, &|i| var(&fluxes[i]));
+        out.push_str(&format!("        {},  # d{}/dt\n", printer.print(&rhs, 0), id));
+    }
+    out.push_str("    ]\n\n\n");
+    out.push_str(&format!("y0 = [{}]\n", floats(&odes.initial)));
+    out.push_str(&format!("t_eval = np.linspace(0, {}, 501)\n", printer.number(odes.t1)));
+    out.push_str(&format!("sol = solve_ivp(rhs, (0, {}), y0, t_eval=t_eval, method=\"LSODA\")\n\n", printer.number(odes.t1)));
+    let labels: Vec<String> = odes.species.iter().map(|s| format!("{:?}", s)).collect();
+    out.push_str(&format!("for label, values in zip([{}], sol.y):\n", list(&labels)));
+    out.push_str("    plt.plot(sol.t, values, label=label)\n");
+    out.push_str("plt.xlabel(\"t\")\nplt.legend()\nplt.show()\n");
+    (out, warnings)
+}
+
+static JULIA_RESERVED: [&str; 45] = ["baremodule", "begin", "break", "catch", "const", "continue", "do", "else",
+                                     "elseif", "end", "export", "false", "finally", "for", "function", "global", "if",
+                                     "import", "let", "local", "macro", "module", "quote", "return", "struct", "true",
+                                     "try", "using", "while", "abstract", "mutable", "primitive", "type", "where",
+                                     "in", "isa", "du", "u", "p", "t", "u0", "tspan", "prob", "sol", "rhs!"];
+
+// A script that integrates the ODEs with DifferentialEquations.jl on the
+// time span of the plot
+pub fn write_julia(petri: &PetriNet, controls: &PlotControls) -> (String, Vec<String>) {
+    let (odes, warnings) = Odes::new(petri, controls);
+    let (species, constants, fluxes) = identifiers(&odes, &JULIA_RESERVED);
+    let name = lookup(&odes, &species, &constants);
+    let printer = Printer { language: Language::Julia, name: &name };
+    let mut out = format!("# {}\nusing DifferentialEquations\n\n", odes.name);
+    out.push_str("function rhs!(du, u, p, t)\n");
+    // A trailing comma unpacks a single value
+    for (names, v) in [(&species, "u"), (&constants, "p")].iter() {
+        match names.len() {
+            0 => { }
+            1 => out.push_str(&format!("    {}, = {}\n", names[0], v)),
+            _ => out.push_str(&format!("    {} = {}\n", list(names), v)),
+        }
+    }
+    for (f, (_, flux)) in fluxes.iter().zip(odes.fluxes.iter()) {
+        out.push_str(&format!("    {} = {}\n", f, printer.print(flux, 0)));
+    }
+    for s in 0..species.len() {
+        let rhs = odes.derivative(s, &|i| var(&fluxes[i]));
+        out.push_str(&format!("    du[{}] = {}\n", s + 1, printer.print(&rhs, 0)));
+    }
+    out.push_str("    return nothing\nend\n\n");
+    let values: Vec<f64> = odes.constants.iter().map(|(_, v)| *v).collect();
+    out.push_str(&format!("u0 = [{}]\n", floats(&odes.initial)));
+    out.push_str(&format!("p = [{}]\n", floats(&values)));
+    out.push_str(&format!("tspan = (0.0, {:?})\n", odes.t1));
+    out.push_str("prob = ODEProblem(rhs!, u0, tspan, p)\nsol = solve(prob)\n");
+    (out, warnings)
+}
+
+static RUST_RESERVED: [&str; 42] = ["as", "break", "const", "continue", "crate", "else", "enum", "extern", "false",
+                                    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+                                    "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+                                    "true", "type", "unsafe", "use", "where", "while", "async", "await", "dyn",
+                                    "try", "t", "y", "f64"];
+
+// A function from the time and the species to their derivatives, with the
+// constants inlined, that depends on nothing but the standard library
+pub fn write_rust(petri: &PetriNet, controls: &PlotControls) -> (String, Vec<String>) {
+    let (odes, warnings) = Odes::new(petri, controls);
+    let (species, constants, fluxes) = identifiers(&odes, &RUST_RESERVED);
+    let name = lookup(&odes, &species, &constants);
+    let printer = Printer { language: Language::Rust, name: &name };
+    let mut used: Vec<String> = RUST_RESERVED.iter().map(|r| r.to_string()).collect();
+    let function = unique_id(if odes.name.is_empty() { "derivatives" } else { &odes.name }, &mut used).to_lowercase();
+    let n = species.len();
+    let mut out = format!("// {}: the derivatives of [{}] at time t\n", odes.name, list(&species));
+    out.push_str("#[allow(non_snake_case, unused_variables, clippy::all)]\n");
+    out.push_str(&format!("pub fn {}(t: f64, y: &[f64; {}]) -> [f64; {}] {{\n", function, n, n));
+    for (c, (_, value)) in constants.iter().zip(odes.constants.iter()) {
+        out.push_str(&format!("    let {} = {};\n", c, printer.number(*value)));
+    }
+    for (s, id) in species.iter().enumerate() {
+        out.push_str(&format!("    let {} = y[{}];\n", id, s));
+    }
+    for (f, (_, flux)) in fluxes.iter().zip(odes.fluxes.iter()) {
+        out.push_str(&format!("    let {} = {};\n", f, printer.print(flux, 0)));
+    }
+    out.push_str("    [\n");
+    for (s, id) in species.iter().enumerate() {
+        let rhs = odes.derivative(s, &|i| var(&fluxes[i]));
+        out.push_str(&format!("        {}, // d{}/dt\n", printer.print(&rhs, 0), id));
+    }
+    out.push_str("    ]\n}\n");
+    (out, warnings)
+}
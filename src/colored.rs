@@ -0,0 +1,215 @@
+use serde::{Serialize, Deserialize};
+use crate::expr::*;
+use crate::petri::*;
+
+// Colored Petri nets, where the tokens in a place carry a color from the
+// place's color set. Transitions bind variables to colors, and fire only for
+// bindings satisfying their guard; arc expressions give the color of the
+// tokens moved along each arc.
+//
+// Guards and arc expressions are written in the expression language, where a
+// color stands for its position in its color set and a variable for the
+// position of the color it is bound to. So with ages [young, old], the guard
+// `a == old` picks out old tokens, and the arc expression `a + 1` ages a token.
+// Bindings where an arc expression falls outside the color set of its place
+// are skipped. A color can be in several color sets only at the same
+// position, so that its name stands for one index.
+//
+// `ColoredNet::unfold` compiles the net to a plain `PetriNet` with a species
+// for each place and color, named `place_color`, and a transition for each
+// binding, named `transition_color_color...`.
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ColorSet {
+    pub name: String,
+    pub colors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Place {
+    pub name: String,
+    // None for a place holding plain, uncolored tokens
+    pub color_set: Option<String>,
+    // Initial tokens of each color, or a single entry for an uncolored place
+    #[serde(default)]
+    pub initial: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ColoredArc {
+    pub place: String,
+    pub weight: i32,
+    // Ignored for uncolored places
+    pub color: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ColoredTransition {
+    pub name: String,
+    // Variables and their color sets, in the order used to name the unfolded transitions
+    pub variables: Vec<(String, String)>,
+    // Empty for no guard, otherwise the transition fires when nonzero
+    #[serde(default)]
+    pub guard: String,
+    pub input: Vec<ColoredArc>,
+    pub output: Vec<ColoredArc>,
+    // Parameter giving the rate of every unfolded transition, with mass action kinetics
+    #[serde(default)]
+    pub rate: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ColoredNet {
+    pub name: String,
+    pub color_sets: Vec<ColorSet>,
+    pub places: Vec<Place>,
+    pub transitions: Vec<ColoredTransition>,
+    #[serde(default)]
+    pub parameters: Vec<Parameter>,
+}
+
+// Every assignment of a color index to each of the given set sizes
+fn bindings(sizes: &[usize]) -> Vec<Vec<usize>> {
+    let mut out = vec![vec![]];
+    for &n in sizes.iter() {
+        out = out.into_iter()
+            .flat_map(|b| (0..n).map(move |c| {
+                let mut b = b.clone();
+                b.push(c);
+                b
+            }))
+            .collect();
+    }
+    out
+}
+
+impl ColoredNet {
+    fn color_set(&self, name: &str) -> Result<&ColorSet, String> {
+        self.color_sets.iter().find(|c| c.name == name)
+            .ok_or_else(|| format!("unknown color set {}", name))
+    }
+
+    // The colors of a place, with a single unnamed color for uncolored places
+    fn colors(&self, place: &Place) -> Result<Vec<String>, String> {
+        match &place.color_set {
+            Some(c) => Ok(self.color_set(c)?.colors.clone()),
+            None => Ok(vec!["".to_string()]),
+        }
+    }
+
+    pub fn species_name(place: &str, color: &str) -> String {
+        if color.is_empty() {
+            place.to_string()
+        } else {
+            format!("{}_{}", place, color)
+        }
+    }
+
+    // The first species of each place in the unfolded net, and the total count
+    fn offsets(&self) -> Result<(Vec<usize>, usize), String> {
+        let mut offsets = vec![];
+        let mut n = 0;
+        for p in self.places.iter() {
+            offsets.push(n);
+            n += self.colors(p)?.len();
+        }
+        Ok((offsets, n))
+    }
+
+    pub fn unfold(&self) -> Result<PetriNet, String> {
+        let mut species = vec![];
+        for p in self.places.iter() {
+            for c in self.colors(p)?.iter() {
+                species.push(Self::species_name(&p.name, c));
+            }
+        }
+        let (offsets, n) = self.offsets()?;
+        let mut constants: Vec<(String, usize, &str)> = vec![];
+        for s in self.color_sets.iter() {
+            for (i, c) in s.colors.iter().enumerate() {
+                match constants.iter().find(|(d, _, _)| d == c) {
+                    Some((_, j, other)) if *j != i =>
+                        return Err(format!("color {} is at different positions in color sets {} and {}", c, other, s.name)),
+                    Some(_) => { }
+                    None => constants.push((c.clone(), i, &s.name)),
+                }
+            }
+        }
+        let constant_values: Vec<f64> = constants.iter().map(|(_, i, _)| *i as f64).collect();
+
+        let mut transitions = vec![];
+        for t in self.transitions.iter() {
+            let sets = t.variables.iter()
+                .map(|(_, s)| self.color_set(s))
+                .collect::<Result<Vec<_>, _>>()?;
+            let variables: Vec<String> = t.variables.iter().map(|(v, _)| v.clone()).collect();
+            let resolve = |v: &str| {
+                variables.iter().position(|x| x == v).map(|i| (0, i))
+                    .or_else(|| constants.iter().position(|(c, _, _)| c == v).map(|i| (1, i)))
+            };
+            let compile_expr = |src: &str| compile(src, &resolve)
+                .map_err(|e| format!("transition {}: {}", t.name, e));
+            let guard = if t.guard.trim().is_empty() { None } else { Some(compile_expr(&t.guard)?) };
+            let arcs = |arcs: &[ColoredArc]| arcs.iter()
+                .map(|a| {
+                    let p = self.places.iter().position(|p| p.name == a.place)
+                        .ok_or_else(|| format!("transition {}: unknown place {}", t.name, a.place))?;
+                    let color = match self.places[p].color_set {
+                        Some(_) => Some(compile_expr(&a.color)?),
+                        None => None,
+                    };
+                    Ok((p, a.weight, color))
+                })
+                .collect::<Result<Vec<_>, String>>();
+            let (input, output) = (arcs(&t.input)?, arcs(&t.output)?);
+
+            let sizes: Vec<usize> = sets.iter().map(|s| s.colors.len()).collect();
+            'binding: for b in bindings(&sizes) {
+                let values: Vec<f64> = b.iter().map(|c| *c as f64).collect();
+                let env: [&[f64]; 2] = [&values, &constant_values];
+                if let Some(g) = &guard {
+                    if g(&env) == 0. {
+                        continue;
+                    }
+                }
+                let mut vectors = (vec![0; n], vec![0; n]);
+                for (arcs, v) in [(&input, &mut vectors.0), (&output, &mut vectors.1)].iter_mut() {
+                    for (p, w, color) in arcs.iter() {
+                        let c = match color {
+                            Some(f) => f(&env),
+                            None => 0.,
+                        };
+                        let size = self.colors(&self.places[*p])?.len();
+                        if c < 0. || c.fract() != 0. || c as usize >= size {
+                            continue 'binding;
+                        }
+                        v[offsets[*p] + c as usize] += w;
+                    }
+                }
+                let name = std::iter::once(t.name.clone())
+                    .chain(b.iter().zip(sets.iter()).map(|(c, s)| s.colors[*c].clone()))
+                    .collect::<Vec<_>>()
+                    .join("_");
+                let mut unfolded = Transition::new(&name, vectors.0, vectors.1);
+                unfolded.rate = t.rate.clone();
+                transitions.push(unfolded);
+            }
+        }
+
+        let mut petri = PetriNet::new(&self.name, species, transitions);
+        petri.parameters = self.parameters.clone();
+        Ok(petri)
+    }
+
+    // Initial values of the species of the unfolded net
+    pub fn initial_values(&self) -> Result<Vec<f32>, String> {
+        let (_, n) = self.offsets()?;
+        let mut out = Vec::with_capacity(n);
+        for p in self.places.iter() {
+            for i in 0..self.colors(p)?.len() {
+                out.push(p.initial.get(i).cloned().unwrap_or(0.));
+            }
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,142 @@
+use plotters::style::Color;
+use crate::petri::*;
+
+// Drawings of the net as a bipartite graph, for slides and papers: species
+// are circles and transitions boxes, with an arc for each nonzero entry of
+// the input and output vectors, labelled with its weight unless it is 1.
+// Read arcs are dashed and go both ways, and inhibitor arcs end in a circle.
+// Species are outlined and lightly filled with their color in the plot.
+//
+// Positions are taken from the layout of the net where it has them, with
+// the other species in a row above a row of the other transitions.
+
+// Layout coordinates are in pixels, with y pointing down as in PNML
+const PIXELS_PER_CM: f32 = 50.;
+
+fn hex(i: usize) -> String {
+    let (r, g, b) = get_color(i).rgb();
+    format!("{:02x}{:02x}{:02x}", r, g, b)
+}
+
+// The arcs of `petri` as (species, transition, direction, weight)
+fn arcs(petri: &PetriNet) -> Vec<(usize, usize, IO, i32)> {
+    let mut arcs = vec![];
+    for (i, t) in petri.transitions.iter().enumerate() {
+        for s in 0..petri.species.len() {
+            for d in DIRECTIONS.iter() {
+                if t.arc(*d, s) > 0 {
+                    arcs.push((s, i, *d, t.arc(*d, s)));
+                }
+            }
+        }
+    }
+    arcs
+}
+
+type Point = (f32, f32);
+
+// Positions in centimeters, with y pointing up
+fn positions(petri: &PetriNet) -> (Vec<Point>, Vec<Point>) {
+    let from_layout = |p: Point| (p.0 / PIXELS_PER_CM, -p.1 / PIXELS_PER_CM);
+    let species = petri.species.iter().enumerate()
+        .map(|(i, s)| petri.layout.species_position(s).map_or((2. * i as f32, 0.), from_layout))
+        .collect();
+    let transitions = petri.transitions.iter().enumerate()
+        .map(|(i, t)| petri.layout.transition_position(&t.name).map_or((2. * i as f32 + 1., -2.), from_layout))
+        .collect();
+    (species, transitions)
+}
+
+fn dot_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// A Graphviz digraph. Nodes of the layout have a pinned position, which
+// `neato` and `fdp` keep; `dot` ignores it and lays the graph out itself.
+pub fn write_dot(petri: &PetriNet) -> String {
+    let mut out = format!("digraph {} {{\n", dot_string(&petri.name));
+    out.push_str("  node [fontname=\"sans-serif\"];\n");
+    // Graphviz positions are in inches
+    let pin = |position: Option<Point>| match position {
+        Some((x, y)) => format!(", pos=\"{:.2},{:.2}!\"", x / PIXELS_PER_CM / 2.54, -y / PIXELS_PER_CM / 2.54),
+        None => String::new(),
+    };
+    for (i, s) in petri.species.iter().enumerate() {
+        let c = hex(i);
+        out.push_str(&format!("  s{} [label={}, shape=circle, style=filled, color=\"#{}\", fillcolor=\"#{}33\"{}];\n",
+                              i, dot_string(s), c, c, pin(petri.layout.species_position(s))));
+    }
+    for (i, t) in petri.transitions.iter().enumerate() {
+        out.push_str(&format!("  t{} [label={}, shape=box{}];\n",
+                              i, dot_string(&t.name), pin(petri.layout.transition_position(&t.name))));
+    }
+    for (s, t, d, w) in arcs(petri) {
+        let label = if w == 1 { String::new() } else { format!("label=\"{}\"", w) };
+        let attrs = match d {
+            IO::Input | IO::Output => vec![label],
+            IO::Read => vec![label, "style=dashed, dir=both".to_string()],
+            IO::Inhibit => vec![label, "arrowhead=odot".to_string()],
+        };
+        let attrs: Vec<String> = attrs.into_iter().filter(|a| !a.is_empty()).collect();
+        let attrs = if attrs.is_empty() { String::new() } else { format!(" [{}]", attrs.join(", ")) };
+        match d {
+            IO::Output => out.push_str(&format!("  t{} -> s{}{};\n", t, s, attrs)),
+            _otherwise => out.push_str(&format!("  s{} -> t{}{};\n", s, t, attrs)),
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+// Text set in LaTeX, with its special characters escaped
+pub fn latex_text(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// A `tikzpicture`, which needs `\usetikzlibrary{arrows.meta}` in the preamble
+pub fn write_tikz(petri: &PetriNet) -> String {
+    let (species, transitions) = positions(petri);
+    let mut out = String::new();
+    for i in 0..petri.species.len() {
+        out.push_str(&format!("\\definecolor{{species{}}}{{HTML}}{{{}}}\n", i, hex(i).to_uppercase()));
+    }
+    out.push_str("\\begin{tikzpicture}[\n");
+    out.push_str("    species/.style={circle, draw, thick, minimum size=1cm},\n");
+    out.push_str("    transition/.style={rectangle, draw, thick, minimum size=0.8cm},\n");
+    out.push_str("    arc/.style={-{Stealth}, thick},\n");
+    out.push_str("    weight/.style={fill=white, inner sep=1pt, font=\\small}]\n");
+    for (i, s) in petri.species.iter().enumerate() {
+        let (x, y) = species[i];
+        out.push_str(&format!("  \\node[species, draw=species{}, fill=species{}!20] (s{}) at ({:.2}, {:.2}) {{{}}};\n",
+                              i, i, i, x, y, latex_text(s)));
+    }
+    for (i, t) in petri.transitions.iter().enumerate() {
+        let (x, y) = transitions[i];
+        out.push_str(&format!("  \\node[transition] (t{}) at ({:.2}, {:.2}) {{{}}};\n", i, x, y, latex_text(&t.name)));
+    }
+    for (s, t, d, w) in arcs(petri) {
+        let label = if w == 1 { String::new() } else { format!(" node[weight] {{{}}}", w) };
+        let line = match d {
+            IO::Input => format!("  \\draw[arc] (s{}) --{} (t{});\n", s, label, t),
+            IO::Output => format!("  \\draw[arc] (t{}) --{} (s{});\n", t, label, s),
+            IO::Read => format!("  \\draw[arc, {{Stealth}}-{{Stealth}}, dashed] (s{}) --{} (t{});\n", s, label, t),
+            IO::Inhibit => format!("  \\draw[thick, -{{Circle[open]}}] (s{}) --{} (t{});\n", s, label, t),
+        };
+        out.push_str(&line);
+    }
+    out.push_str("\\end{tikzpicture}\n");
+    out
+}
@@ -0,0 +1,205 @@
+use serde::{Serialize, Deserialize};
+use rand::Rng;
+use ndarray::prelude::*;
+#[cfg(target_arch = "wasm32")]
+use plotters::prelude::*;
+use crate::math::*;
+
+// Reaction-diffusion by the method of lines: the species live on a grid of
+// cells, react in each cell by `PetriData::master_eq_with`, and diffuse
+// between neighbouring cells by the five point (three point in 1-D)
+// discretization of the Laplacian. The resulting system of ODEs is solved
+// with `rk_step`, with the time step limited for the stability of the
+// explicit scheme.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Boundary {
+    // No flux through the boundary
+    #[default]
+    Neumann,
+    // Opposite edges are neighbours
+    Periodic,
+    // Every species is held at the given value just outside the boundary
+    Dirichlet(f32),
+}
+
+pub static BOUNDARIES: [&str; 3] = ["No Flux", "Periodic", "Fixed Value"];
+
+impl Boundary {
+    pub fn to_idx(self) -> usize {
+        match self {
+            Boundary::Neumann => 0,
+            Boundary::Periodic => 1,
+            Boundary::Dirichlet(_) => 2,
+        }
+    }
+
+    pub fn from_idx(i: usize) -> Self {
+        match i {
+            1 => Boundary::Periodic,
+            2 => Boundary::Dirichlet(0.),
+            _ => Boundary::Neumann,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Diffusion {
+    pub width: usize,
+    // 1 for a 1-D grid
+    pub height: usize,
+    // Spacing of the cells
+    pub dx: f32,
+    // Diffusion coefficient of each species, missing entries are 0
+    pub coefficients: Vec<f32>,
+    pub boundary: Boundary,
+    // Relative amplitude of uniform noise on the initial values, which seeds patterns
+    #[serde(default)]
+    pub noise: f32,
+    // Extra tokens of a species in the center cell, which seeds travelling waves
+    #[serde(default)]
+    pub seed: Option<(usize, f32)>,
+}
+
+// Values on the grid at each frame, indexed by (frame, cell, species), where
+// the cell at column x and row y is y * width + x
+pub type Frames = Array<f32, Ix3>;
+
+impl Diffusion {
+    pub fn new(width: usize, height: usize) -> Self {
+        Diffusion {
+            width,
+            height,
+            dx: 1.,
+            coefficients: vec![],
+            boundary: Boundary::Neumann,
+            noise: 0.,
+            seed: None,
+        }
+    }
+
+    pub fn cells(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn coefficient(&self, s: usize) -> f32 {
+        self.coefficients.get(s).cloned().unwrap_or(0.)
+    }
+
+    // The initial values in every cell, perturbed by the noise and the seed,
+    // as (cell, species)
+    pub fn initial_state<R: Rng + ?Sized>(&self, init_vals: &[f32], rng: &mut R) -> Array2<f32> {
+        let mut y = Array2::zeros((self.cells(), init_vals.len()));
+        for mut cell in y.outer_iter_mut() {
+            for (x, v) in cell.iter_mut().zip(init_vals.iter()) {
+                *x = v * (1. + self.noise * rng.gen_range(-1., 1.));
+            }
+        }
+        if let Some((s, amount)) = self.seed {
+            let center = (self.height / 2) * self.width + self.width / 2;
+            if s < init_vals.len() {
+                y[(center, s)] += amount;
+            }
+        }
+        y
+    }
+
+    // The neighbours of a cell along one axis, at `i - 1` and `i + 1` of `n`.
+    // None stands for the value outside the boundary.
+    fn neighbours(&self, i: usize, n: usize) -> (Option<usize>, Option<usize>) {
+        match self.boundary {
+            Boundary::Periodic => (Some((i + n - 1) % n), Some((i + 1) % n)),
+            Boundary::Neumann => (Some(if i == 0 { i } else { i - 1 }), Some(if i + 1 == n { i } else { i + 1 })),
+            Boundary::Dirichlet(_) => (if i == 0 { None } else { Some(i - 1) }, if i + 1 == n { None } else { Some(i + 1) }),
+        }
+    }
+
+    // The discrete Laplacian of species `s`, with the state laid out cell by cell
+    fn laplacian(&self, y: &V, species: usize, cell: usize, s: usize) -> f32 {
+        let (x, row) = (cell % self.width, cell / self.width);
+        let outside = match self.boundary {
+            Boundary::Dirichlet(v) => v,
+            _otherwise => 0.,
+        };
+        let value = |c: Option<usize>| c.map_or(outside, |c| y[c * species + s]);
+        let here = y[cell * species + s];
+        let mut total = 0.;
+        let (l, r) = self.neighbours(x, self.width);
+        total += value(l.map(|x| row * self.width + x)) + value(r.map(|x| row * self.width + x)) - 2. * here;
+        if self.height > 1 {
+            let (u, d) = self.neighbours(row, self.height);
+            total += value(u.map(|r| r * self.width + x)) + value(d.map(|r| r * self.width + x)) - 2. * here;
+        }
+        total / (self.dx * self.dx)
+    }
+
+    fn derivative(&self, petri: &PetriData, rates: &[f64], t: f32, y: &V) -> V {
+        let n = petri.num_species();
+        let mut yp = Array::zeros(y.dim());
+        for cell in 0..self.cells() {
+            let local = y.slice(s![cell * n..(cell + 1) * n]).to_owned();
+            let reactions = petri.master_eq_with(t as f64, rates, petri.parameters(), &local);
+            for s in 0..n {
+                yp[cell * n + s] = reactions[s] + self.coefficient(s) * self.laplacian(y, n, cell, s);
+            }
+        }
+        yp
+    }
+
+    // Solves on [0, t1] from `init` (cell, species), returning `frames` frames
+    // evenly spaced in time after the initial one
+    pub fn solve(&self, petri: &PetriData, rates: &[f32], init: &Array2<f32>, t1: f32, frames: usize) -> Frames {
+        let rates: Vec<f64> = rates.iter().map(|r| *r as f64).collect();
+        let n = petri.num_species();
+        let dims = if self.height > 1 { 2. } else { 1. };
+        let d_max = (0..n).map(|s| self.coefficient(s)).fold(0f32, f32::max);
+        let frame_dt = t1 / frames as f32;
+        // Explicit diffusion is stable for dt < dx^2 / (2 * dims * D)
+        let substeps = if d_max > 0. {
+            (frame_dt * 2.5 * dims * d_max / (self.dx * self.dx)).ceil().max(1.) as usize
+        } else {
+            1
+        };
+        let dt = frame_dt / substeps as f32;
+        let mut out = Array::zeros((frames, self.cells(), n));
+        let mut y: V = init.iter().cloned().collect();
+        let mut t = 0.;
+        for mut frame in out.outer_iter_mut() {
+            for _ in 0..substeps {
+                y = rk_step(|t, v| self.derivative(petri, &rates, t, v), t, dt, &y);
+                t += dt;
+            }
+            frame.assign(&y.view().into_shape((self.cells(), n)).unwrap());
+        }
+        out
+    }
+
+    // Draws the values of species `s` at `frame` as a heatmap, with values
+    // from 0 (white) to `vmax` (the color of the species)
+    #[cfg(target_arch = "wasm32")]
+    pub fn plot_frame(&self, frames: &Frames, frame: usize, s: usize, vmax: f32, title: &str, canvas_id: &str)
+            -> DrawResult<(), CanvasBackend> {
+        let backend = CanvasBackend::new(canvas_id).expect("cannot find canvas");
+        let root = backend.into_drawing_area();
+        let font: FontDesc = ("sans-serif", 20.0).into();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, font)
+            .x_label_area_size(30)
+            .y_label_area_size(30)
+            .build_ranged(0..self.width, 0..self.height)?;
+        chart.configure_mesh().disable_mesh().draw()?;
+        let (r, g, b) = crate::petri::get_color(s).rgb();
+        let shade = |v: f32| {
+            let a = (v / vmax.max(1e-6)).max(0.).min(1.) as f64;
+            let mix = |c: u8| (255. - a * (255. - c as f64)) as u8;
+            RGBColor(mix(r), mix(g), mix(b))
+        };
+        chart.draw_series((0..self.cells()).map(|cell| {
+            let (x, y) = (cell % self.width, cell / self.width);
+            Rectangle::new([(x, y), (x + 1, y + 1)], shade(frames[(frame, cell, s)]).filled())
+        }))?;
+        root.present()?;
+        Ok(())
+    }
+}
@@ -4,6 +4,22 @@ use plotters::style::Color;
 use serde::{Serialize, Deserialize};
 use crate::plot::*;
 use crate::petri::*;
+use crate::kinetics::*;
+use crate::events::*;
+use crate::gsmp::*;
+use crate::open::*;
+use crate::patches::*;
+use crate::diffusion::*;
+use crate::stages::*;
+use crate::sbml::*;
+use crate::pnml::*;
+use crate::acset::*;
+use crate::reactions::*;
+use crate::antimony::*;
+use crate::trajectory::*;
+use crate::diagram::*;
+use crate::codegen::*;
+use crate::utils::download;
 
 trait Edit {
     type Val;
@@ -66,6 +82,13 @@ impl<S> VED<S> {
 pub enum TransitionEdit {
     NameEdit(String),
     IOEdit(IO,usize,i32),
+    RateLawEdit(RateLaw),
+    TimingEdit(Timing),
+    PolicyEdit(Policy),
+    DelayEdit(f32),
+    StagesEdit(u32),
+    RateEdit(Option<String>),
+    ScheduleEdit(Schedule),
 }
 
 impl Edit for TransitionEdit {
@@ -73,7 +96,71 @@ impl Edit for TransitionEdit {
     fn apply_edit(self, v: &mut Self::Val) {
         match self {
             Self::NameEdit(s) => { v.name = s; }
-            Self::IOEdit(d,i,e) => { v[d][i] = e; }
+            Self::IOEdit(d,i,e) => { v.set_arc(d,i,e); }
+            Self::RateLawEdit(l) => { v.rate_law = l; }
+            Self::TimingEdit(t) => { v.timing = t; }
+            Self::PolicyEdit(p) => { v.policy = p; }
+            Self::DelayEdit(d) => { v.delay = d.max(0.); }
+            Self::StagesEdit(k) => { v.stages = k; }
+            Self::RateEdit(r) => { v.rate = r; }
+            Self::ScheduleEdit(s) => { v.schedule = s; }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParameterEdit {
+    NameEdit(String),
+    ValueEdit(f32),
+    RangeEdit(f32,f32),
+    UnitEdit(String),
+}
+
+impl Edit for ParameterEdit {
+    type Val = Parameter;
+    fn apply_edit(self, v: &mut Self::Val) {
+        match self {
+            Self::NameEdit(s) => { v.name = s; }
+            Self::ValueEdit(x) => { v.value = x; }
+            Self::RangeEdit(lo,hi) => { v.min = lo; v.max = hi; }
+            Self::UnitEdit(s) => { v.unit = s; }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EventEdit {
+    TriggerEdit(Trigger),
+    ActionsEdit(VED<PlainEdit<Action>>),
+}
+
+impl Edit for EventEdit {
+    type Val = Event;
+    fn apply_edit(self, v: &mut Self::Val) {
+        match self {
+            Self::TriggerEdit(t) => { v.trigger = t; }
+            Self::ActionsEdit(e) => {
+                match e {
+                    VED::Add => { v.actions.push(Action::Scale("".to_string(), 1.)); }
+                    VED::Edit(e) => { e.apply_edit(&mut v.actions); }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ObservableEdit {
+    NameEdit(String),
+    ExpressionEdit(String),
+}
+
+impl Edit for ObservableEdit {
+    type Val = Observable;
+    fn apply_edit(self, v: &mut Self::Val) {
+        match self {
+            Self::NameEdit(s) => { v.name = s; }
+            Self::ExpressionEdit(s) => { v.expression = s; }
         }
     }
 }
@@ -82,6 +169,9 @@ impl Edit for TransitionEdit {
 pub enum PetriEdit {
     TransitionsEdit(VED<TransitionEdit>),
     SpeciesEdit(VED<PlainEdit<String>>),
+    ParametersEdit(VED<ParameterEdit>),
+    ObservablesEdit(VED<ObservableEdit>),
+    EventsEdit(VED<EventEdit>),
     NameEdit(String),
 }
 
@@ -93,11 +183,7 @@ impl Edit for PetriEdit {
                 match e {
                     VED::Add => {
                         let s = v.species.len();
-                        let t = Transition {
-                            input: Array1::zeros(s).to_vec(),
-                            output: Array1::zeros(s).to_vec(),
-                            name: "".to_string(),
-                        };
+                        let t = Transition::new("", Array1::zeros(s).to_vec(), Array1::zeros(s).to_vec());
                         v.transitions.push(t);
                     }
                     VED::Edit(e) => {
@@ -109,8 +195,7 @@ impl Edit for PetriEdit {
                 match e {
                     VED::Add => {
                         for t in v.transitions.iter_mut() {
-                            t.input.push(0);
-                            t.output.push(0);
+                            t.add_species();
                         }
                         v.species.push("".to_string());
                     }
@@ -118,8 +203,7 @@ impl Edit for PetriEdit {
                         match &e {
                             VecEdit::Remove(i) => {
                                 for t in v.transitions.iter_mut() {
-                                    t.input.remove(*i);
-                                    t.output.remove(*i);
+                                    t.remove_species(*i);
                                 }
                             }
                             _otherwise => { }
@@ -128,6 +212,48 @@ impl Edit for PetriEdit {
                     }
                 }
             }
+            Self::ParametersEdit(e) => {
+                match e {
+                    VED::Add => {
+                        v.parameters.push(Parameter::new("", 0.));
+                    }
+                    VED::Edit(e) => {
+                        // Keep the references pointing at the same parameter
+                        match &e {
+                            VecEdit::Remove(i) => {
+                                let old = v.parameters[*i].name.clone();
+                                v.rename_parameter(&old, None);
+                            }
+                            VecEdit::Edit(i,ParameterEdit::NameEdit(n)) => {
+                                let old = v.parameters[*i].name.clone();
+                                v.rename_parameter(&old, Some(n));
+                            }
+                            _otherwise => { }
+                        }
+                        e.apply_edit(&mut v.parameters);
+                    }
+                }
+            }
+            Self::ObservablesEdit(e) => {
+                match e {
+                    VED::Add => {
+                        v.observables.push(Observable { name: "".to_string(), expression: "0".to_string() });
+                    }
+                    VED::Edit(e) => {
+                        e.apply_edit(&mut v.observables);
+                    }
+                }
+            }
+            Self::EventsEdit(e) => {
+                match e {
+                    VED::Add => {
+                        v.events.push(Event { trigger: Trigger::At(0.), actions: vec![] });
+                    }
+                    VED::Edit(e) => {
+                        e.apply_edit(&mut v.events);
+                    }
+                }
+            }
             Self::NameEdit(s) => { v.name = s; }
         }
     }
@@ -136,7 +262,8 @@ impl Edit for PetriEdit {
 #[derive(Debug)]
 pub enum ControlsEdit {
     RatesEdit(VED<PlainEdit<f32>>),
-    InitValsEdit(VED<PlainEdit<f32>>)
+    InitValsEdit(VED<PlainEdit<f32>>),
+    RateRangeEdit(usize,(f32,f32))
 }
 
 impl ControlsEdit {
@@ -177,7 +304,15 @@ impl Edit for ControlsEdit {
             Self::RatesEdit(e) => {
                 match e {
                     VED::Add => { v.rates.push(0.); }
-                    VED::Edit(ve) => { ve.apply_edit(&mut v.rates); }
+                    VED::Edit(ve) => {
+                        match &ve {
+                            VecEdit::Remove(i) if *i < v.rate_ranges.len() => {
+                                v.rate_ranges.remove(*i);
+                            }
+                            _otherwise => { }
+                        }
+                        ve.apply_edit(&mut v.rates);
+                    }
                 }
             },
             Self::InitValsEdit(e) => {
@@ -186,6 +321,171 @@ impl Edit for ControlsEdit {
                     VED::Edit(ve) => { ve.apply_edit(&mut v.init_vals); }
                 }
             }
+            Self::RateRangeEdit(i,r) => {
+                v.set_rate_range(i,r);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SubmoduleEdit {
+    NameEdit(String),
+    MapEdit(usize,Option<usize>),
+    Toggle,
+}
+
+impl Edit for SubmoduleEdit {
+    type Val = Submodule;
+    fn apply_edit(self, v: &mut Self::Val) {
+        match self {
+            Self::NameEdit(s) => { v.name = s; }
+            Self::MapEdit(k,j) => {
+                if v.species_map.len() <= k {
+                    v.species_map.resize(k + 1, None);
+                }
+                v.species_map[k] = j;
+            }
+            Self::Toggle => { v.expanded = !v.expanded; }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LibraryEdit {
+    // Saves the current net as a module, replacing the module of the same name
+    SaveAsModule,
+    // Opens a module in place of the parent net, until `FinishModule`
+    EditModule(usize),
+    FinishModule,
+    RemoveModule(usize),
+    AddSubmodule(usize),
+    SubmodulesEdit(VecEdit<SubmoduleEdit>),
+}
+
+#[derive(Debug)]
+pub enum PatchesEdit {
+    Enable(bool),
+    AddPatch,
+    RemovePatch(usize),
+    NameEdit(usize,String),
+    MigrationEdit(usize,usize,f32),
+    MobilityEdit(usize,f32),
+    InitialEdit(usize,usize,f32),
+    ViewEdit(PatchView),
+}
+
+impl PatchesEdit {
+    fn apply_to(self, m: &mut Metapopulation) {
+        match self {
+            Self::AddPatch => {
+                m.patches.push(format!("P{}", m.patches.len() + 1));
+                let n = m.patches.len();
+                m.migration.resize(n, vec![]);
+                for row in m.migration.iter_mut() {
+                    row.resize(n, 0.);
+                }
+            }
+            Self::RemovePatch(p) => {
+                m.patches.remove(p);
+                m.migration.remove(p);
+                for row in m.migration.iter_mut() {
+                    row.remove(p);
+                }
+                if p < m.initial.len() {
+                    m.initial.remove(p);
+                }
+            }
+            Self::NameEdit(p,s) => { m.patches[p] = s; }
+            Self::MigrationEdit(p,q,r) => { m.migration[p][q] = r; }
+            Self::MobilityEdit(s,x) => {
+                if m.mobility.len() <= s {
+                    m.mobility.resize(s + 1, 1.);
+                }
+                m.mobility[s] = x;
+            }
+            Self::InitialEdit(p,s,x) => {
+                if m.initial.len() <= p {
+                    m.initial.resize(p + 1, vec![]);
+                }
+                if m.initial[p].len() <= s {
+                    m.initial[p].resize(s + 1, 0.);
+                }
+                m.initial[p][s] = x;
+            }
+            Self::ViewEdit(view) => { m.view = view; }
+            Self::Enable(_) => { }
+        }
+    }
+}
+
+impl Edit for PatchesEdit {
+    type Val = Option<Metapopulation>;
+    fn apply_edit(self, v: &mut Self::Val) {
+        match self {
+            Self::Enable(true) => {
+                if v.is_none() {
+                    *v = Some(Metapopulation::new(vec!["A".to_string(), "B".to_string()]));
+                }
+            }
+            Self::Enable(false) => { *v = None; }
+            e => {
+                if let Some(m) = v {
+                    e.apply_to(m);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DiffusionEdit {
+    Enable(bool),
+    SizeEdit(usize,usize),
+    SpacingEdit(f32),
+    BoundaryEdit(Boundary),
+    CoefficientEdit(usize,f32),
+    NoiseEdit(f32),
+    SeedEdit(Option<(usize,f32)>),
+}
+
+impl DiffusionEdit {
+    fn apply_to(self, d: &mut Diffusion) {
+        match self {
+            Self::SizeEdit(w,h) => {
+                d.width = w.max(1);
+                d.height = h.max(1);
+            }
+            Self::SpacingEdit(dx) => { d.dx = dx; }
+            Self::BoundaryEdit(b) => { d.boundary = b; }
+            Self::CoefficientEdit(s,x) => {
+                if d.coefficients.len() <= s {
+                    d.coefficients.resize(s + 1, 0.);
+                }
+                d.coefficients[s] = x;
+            }
+            Self::NoiseEdit(x) => { d.noise = x; }
+            Self::SeedEdit(seed) => { d.seed = seed; }
+            Self::Enable(_) => { }
+        }
+    }
+}
+
+impl Edit for DiffusionEdit {
+    type Val = Option<Diffusion>;
+    fn apply_edit(self, v: &mut Self::Val) {
+        match self {
+            Self::Enable(true) => {
+                if v.is_none() {
+                    *v = Some(Diffusion::new(20, 20));
+                }
+            }
+            Self::Enable(false) => { *v = None; }
+            e => {
+                if let Some(d) = v {
+                    e.apply_to(d);
+                }
+            }
         }
     }
 }
@@ -194,9 +494,15 @@ impl Edit for ControlsEdit {
 pub enum Msg {
     ForPetri(PetriEdit),
     ForControls(ControlsEdit),
+    ForLibrary(LibraryEdit),
+    ForPatches(PatchesEdit),
+    ForDiffusion(DiffusionEdit),
+    CollapseStages(bool),
     ViewSwitch,
     SourceUpdate(String),
+    SourceFormat(SourceFormat),
     SaveInUrl,
+    Download(Download),
 }
 
 impl Msg {
@@ -212,6 +518,18 @@ impl Msg {
         Self::ForPetri(PetriEdit::TransitionsEdit(e))
     }
 
+    fn parameters_edit(e: VED<ParameterEdit>) -> Self {
+        Self::ForPetri(PetriEdit::ParametersEdit(e))
+    }
+
+    fn observables_edit(e: VED<ObservableEdit>) -> Self {
+        Self::ForPetri(PetriEdit::ObservablesEdit(e))
+    }
+
+    fn events_edit(e: VED<EventEdit>) -> Self {
+        Self::ForPetri(PetriEdit::EventsEdit(e))
+    }
+
     fn rates_edit(i: usize, v: f32) -> Self {
         Self::ForControls(ControlsEdit::RatesEdit(VED::Edit(VecEdit::Edit(i,PlainEdit::PlainEdit(v)))))
     }
@@ -219,25 +537,150 @@ impl Msg {
     fn init_vals_edit(i: usize, v: f32) -> Self {
         Self::ForControls(ControlsEdit::InitValsEdit(VED::Edit(VecEdit::Edit(i,PlainEdit::PlainEdit(v)))))
     }
+
+    fn rate_range_edit(i: usize, r: (f32,f32)) -> Self {
+        Self::ForControls(ControlsEdit::RateRangeEdit(i,r))
+    }
+}
+
+// A saved net that can be embedded in other nets as a submodule
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Module {
+    petri_net: PetriNet,
+    controls: PlotControls,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct GE {
     petri_net: PetriNet,
     controls: PlotControls,
+    #[serde(default)]
+    library: Vec<Module>,
+    #[serde(default)]
+    submodules: Vec<Submodule>,
+    // The module being edited, which is swapped with the parent net meanwhile
+    #[serde(default)]
+    editing: Option<usize>,
+    #[serde(default)]
+    metapopulation: Option<Metapopulation>,
+    #[serde(default)]
+    diffusion: Option<Diffusion>,
+    // Whether the plot sums the stages of k-stage transitions together
+    #[serde(default)]
+    collapse_stages: bool,
 }
 
 impl Default for GE {
     fn default() -> Self {
         GE {
             petri_net: PetriNet::empty(),
-            controls: PlotControls::empty()
+            controls: PlotControls::empty(),
+            library: vec![],
+            submodules: vec![],
+            editing: None,
+            metapopulation: None,
+            diffusion: None,
+            collapse_stages: false
+        }
+    }
+}
+
+impl Edit for LibraryEdit {
+    type Val = GE;
+    fn apply_edit(self, v: &mut Self::Val) {
+        match self {
+            Self::SaveAsModule => {
+                let module = Module { petri_net: v.petri_net.clone(), controls: v.controls.clone() };
+                match v.library.iter().position(|m| m.petri_net.name == module.petri_net.name) {
+                    Some(i) => { v.library[i] = module; }
+                    None => { v.library.push(module); }
+                }
+            }
+            Self::EditModule(i) => {
+                if v.editing.is_none() {
+                    std::mem::swap(&mut v.petri_net, &mut v.library[i].petri_net);
+                    std::mem::swap(&mut v.controls, &mut v.library[i].controls);
+                    v.editing = Some(i);
+                }
+            }
+            Self::FinishModule => {
+                if let Some(i) = v.editing.take() {
+                    std::mem::swap(&mut v.petri_net, &mut v.library[i].petri_net);
+                    std::mem::swap(&mut v.controls, &mut v.library[i].controls);
+                }
+            }
+            Self::RemoveModule(i) => {
+                v.library.remove(i);
+                v.submodules.retain(|s| s.module != i);
+                for s in v.submodules.iter_mut() {
+                    if s.module > i {
+                        s.module -= 1;
+                    }
+                }
+            }
+            Self::AddSubmodule(i) => {
+                let net = &v.library[i].petri_net;
+                // Species of the same name are mapped onto each other
+                let species_map = net.species.iter()
+                    .map(|s| v.petri_net.species.iter().position(|p| p == s))
+                    .collect();
+                v.submodules.push(Submodule {
+                    name: format!("{}{}", net.name, v.submodules.len() + 1),
+                    module: i,
+                    species_map,
+                    expanded: false
+                });
+            }
+            Self::SubmodulesEdit(e) => { e.apply_edit(&mut v.submodules); }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SourceFormat {
+    Json,
+    Reactions,
+    Sbml,
+    Pnml,
+    Acset,
+    // Import only
+    Antimony,
+}
+
+pub static SOURCE_FORMATS: [&str; 6] = ["JSON", "Reactions", "SBML", "PNML", "AlgebraicPetri", "Antimony"];
+
+impl SourceFormat {
+    pub fn to_idx(self) -> usize {
+        match self {
+            SourceFormat::Json => 0,
+            SourceFormat::Reactions => 1,
+            SourceFormat::Sbml => 2,
+            SourceFormat::Pnml => 3,
+            SourceFormat::Acset => 4,
+            SourceFormat::Antimony => 5,
+        }
+    }
+
+    pub fn from_idx(i: usize) -> Self {
+        match i {
+            1 => SourceFormat::Reactions,
+            2 => SourceFormat::Sbml,
+            3 => SourceFormat::Pnml,
+            4 => SourceFormat::Acset,
+            5 => SourceFormat::Antimony,
+            _ => SourceFormat::Json,
         }
     }
 }
 
 pub struct SE {
-    code: String
+    format: SourceFormat,
+    // The graphical editor as JSON. Formats other than JSON only hold a net
+    // and its controls, which replace those of `base` when switching back.
+    base: String,
+    code: String,
+    // Warnings about the last conversion, or the error that stopped it
+    messages: Vec<String>,
 }
 
 pub enum EditorState {
@@ -249,17 +692,212 @@ pub struct Editor {
     state: EditorState
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Download {
+    // The entry shown in the menu, which downloads nothing
+    Placeholder,
+    OdeCsv,
+    OdeJson,
+    StochasticCsv,
+    StochasticJson,
+    EnsembleCsv,
+    EnsembleJson,
+    Dot,
+    Tikz,
+    Latex,
+    Scipy,
+    Julia,
+    Rust,
+}
+
+pub static DOWNLOADS: [&str; 13] = ["Download...", "ODE trajectory (CSV)", "ODE trajectory (JSON)",
+                                    "Stochastic trajectory (CSV)", "Stochastic trajectory (JSON)",
+                                    "Ensemble summary (CSV)", "Ensemble summary (JSON)",
+                                    "Net diagram (DOT)", "Net diagram (TikZ)",
+                                    "Equations (LaTeX)", "Equations (SciPy)", "Equations (Julia)", "Equations (Rust)"];
+
+impl Download {
+    pub fn to_idx(self) -> usize {
+        match self {
+            Download::Placeholder => 0,
+            Download::OdeCsv => 1,
+            Download::OdeJson => 2,
+            Download::StochasticCsv => 3,
+            Download::StochasticJson => 4,
+            Download::EnsembleCsv => 5,
+            Download::EnsembleJson => 6,
+            Download::Dot => 7,
+            Download::Tikz => 8,
+            Download::Latex => 9,
+            Download::Scipy => 10,
+            Download::Julia => 11,
+            Download::Rust => 12,
+        }
+    }
+
+    pub fn from_idx(i: usize) -> Self {
+        match i {
+            1 => Download::OdeCsv,
+            2 => Download::OdeJson,
+            3 => Download::StochasticCsv,
+            4 => Download::StochasticJson,
+            5 => Download::EnsembleCsv,
+            6 => Download::EnsembleJson,
+            7 => Download::Dot,
+            8 => Download::Tikz,
+            9 => Download::Latex,
+            10 => Download::Scipy,
+            11 => Download::Julia,
+            12 => Download::Rust,
+            _ => Download::Placeholder,
+        }
+    }
+}
+
+// Number of stochastic trajectories in the ensemble summary
+pub static ENSEMBLE_RUNS: usize = 100;
+
 fn color_style<T: Color>(c: T) -> String {
     let (r,g,b) = c.rgb();
     format!("background-color:rgba({},{},{},0.2)", r, g, b)
 }
 
 impl GE {
-    fn to_se(&self) -> SE {
-        SE {
-            code: serde_json::to_string(&self).unwrap()
+    // Keeps the species maps of the submodules pointing at the same species
+    // when species are removed from the parent net or from a module
+    fn sync_submodules(&mut self, e: &PetriEdit) {
+        if let PetriEdit::SpeciesEdit(VED::Edit(VecEdit::Remove(i))) = e {
+            let i = *i;
+            for s in self.submodules.iter_mut() {
+                match self.editing {
+                    Some(m) if s.module == m && i < s.species_map.len() => { s.species_map.remove(i); }
+                    Some(_) => { }
+                    None => {
+                        for j in s.species_map.iter_mut() {
+                            *j = match *j {
+                                Some(j) if j == i => None,
+                                Some(j) if j > i => Some(j - 1),
+                                j => j
+                            };
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Species removed from the net are removed from the patch settings
+    fn sync_patches(&mut self, e: &PetriEdit) {
+        if let (PetriEdit::SpeciesEdit(VED::Edit(VecEdit::Remove(i))), Some(m), None) = (e, &mut self.metapopulation, self.editing) {
+            if *i < m.mobility.len() {
+                m.mobility.remove(*i);
+            }
+            for v in m.initial.iter_mut().filter(|v| *i < v.len()) {
+                v.remove(*i);
+            }
+        }
+    }
+
+    // Species removed from the net are removed from the diffusion settings
+    fn sync_diffusion(&mut self, e: &PetriEdit) {
+        if let (PetriEdit::SpeciesEdit(VED::Edit(VecEdit::Remove(i))), Some(d), None) = (e, &mut self.diffusion, self.editing) {
+            if *i < d.coefficients.len() {
+                d.coefficients.remove(*i);
+            }
+            d.seed = match d.seed {
+                Some((s, _)) if s == *i => None,
+                Some((s, x)) if s > *i => Some((s - 1, x)),
+                seed => seed
+            };
+        }
+    }
+
+    // The net that is simulated: the submodules embedded, the k-stage
+    // transitions and the patches expanded, with matching controls
+    fn simulated(&self) -> (PetriNet, PlotControls) {
+        let (petri, controls) = self.flattened();
+        let (petri, controls) = if petri.has_stages() {
+            let staged = petri.expand_stages();
+            let controls = staged.expand_controls(&petri, &controls);
+            (staged.net, controls)
+        } else {
+            (petri, controls)
+        };
+        match (&self.metapopulation, self.editing) {
+            (Some(m), None) => (m.expand(&petri), m.expand_controls(&petri, &controls)),
+            _otherwise => (petri, controls)
         }
     }
+
+    // The net with the submodules embedded, and controls for it built from
+    // the controls saved with each module
+    fn flattened(&self) -> (PetriNet, PlotControls) {
+        if self.editing.is_some() {
+            return (self.petri_net.clone(), self.controls.clone());
+        }
+        let instances: Vec<(&Submodule, &PetriNet)> = self.submodules.iter()
+            .filter_map(|s| self.library.get(s.module).map(|m| (s, &m.petri_net)))
+            .collect();
+        match embed(&self.petri_net, &instances) {
+            Ok(flat) => {
+                let mut controls = self.controls.clone();
+                controls.rates.resize(self.petri_net.transitions.len(), 0.);
+                controls.init_vals.resize(self.petri_net.species.len(), 0.);
+                for (s, _) in instances.iter() {
+                    let module = &self.library[s.module];
+                    let rates = module.petri_net.transitions.iter().enumerate()
+                        .map(|(t, _)| module.controls.rates.get(t).cloned().unwrap_or(0.));
+                    controls.rates.extend(rates);
+                    let internal = module.petri_net.species.iter().enumerate()
+                        .filter(|(k, _)| s.species_map.get(*k).cloned().flatten().is_none())
+                        .map(|(k, _)| module.controls.init_vals.get(k).cloned().unwrap_or(0.));
+                    controls.init_vals.extend(internal);
+                }
+                (flat.net, controls)
+            }
+            Err(_) => (self.petri_net.clone(), self.controls.clone())
+        }
+    }
+
+    // The net that is exchanged with other formats: the submodules embedded
+    // and the k-stage transitions expanded
+    fn exchanged(&self) -> (PetriNet, PlotControls) {
+        let (petri, controls) = self.flattened();
+        if petri.has_stages() {
+            let staged = petri.expand_stages();
+            let controls = staged.expand_controls(&petri, &controls);
+            (staged.net, controls)
+        } else {
+            (petri, controls)
+        }
+    }
+
+    fn to_se(&self, format: SourceFormat) -> SE {
+        let base = serde_json::to_string(&self).unwrap();
+        let (code, messages) = match format {
+            SourceFormat::Json => (base.clone(), vec![]),
+            SourceFormat::Reactions => {
+                let (petri, controls) = self.exchanged();
+                write_reactions(&petri, &controls)
+            }
+            SourceFormat::Sbml => {
+                let (petri, controls) = self.exchanged();
+                write_sbml(&petri, &controls)
+            }
+            SourceFormat::Pnml => {
+                let (petri, controls) = self.exchanged();
+                write_pnml(&petri, &controls)
+            }
+            SourceFormat::Acset => {
+                let (petri, controls) = self.exchanged();
+                write_acset(&petri, &controls)
+            }
+            SourceFormat::Antimony => {
+                (String::new(), vec!["Paste an Antimony model here to import it; the net is kept while this is empty".to_string()])
+            }
+        };
+        SE { format, base, code, messages }
+    }
     
     fn view_matrix(&self) -> Html<Editor> {
         html!{
@@ -270,7 +908,7 @@ impl GE {
                     <th style="width:135px" rowspan="2"> </th>
                     { for self.petri_net.species.iter().enumerate().map(|(i,s)| {
                         html!{
-                            <th class="species-header" colspan="2">
+                            <th class="species-header" colspan="4">
                                 <input type="text" style={color_style(get_color(i))} class="table-form matrix-input" value={&s} oninput=|v|
                                     Msg::species_edit(VED::edit(i,PlainEdit::PlainEdit(v.value)))>
                                 </input>
@@ -279,6 +917,8 @@ impl GE {
                         }
                     })}
                     <th> <button class="square-button" onclick=|_| Msg::species_edit(VED::add())>{"+"}</button> </th>
+                    <th rowspan="2">{"Rate Law"}</th>
+                    <th rowspan="2">{"Firing Time"}</th>
                 </tr>
                 <tr>
                     { for self.petri_net.species.iter().enumerate().map(|(i,s)| {
@@ -286,6 +926,8 @@ impl GE {
                             <>
                             <td class="transition-direction">{"In"}</td>
                             <td class="transition-direction">{"Out"}</td>
+                            <td class="transition-direction" title="Required but not consumed">{"Read"}</td>
+                            <td class="transition-direction" title="Disabled at or above this count, 0 for none">{"Inh"}</td>
                             </>
                         }
                     })}
@@ -305,7 +947,7 @@ impl GE {
                             { for DIRECTIONS.iter().map(|d| {
                                 html!{
                                     <td>
-                                    <input class="transitions-counter" type="number" value={t[*d][j].to_string()} oninput=|xp| {
+                                    <input class="transitions-counter" type="number" value={t.arc(*d,j).to_string()} oninput=|xp| {
                                         Msg::transitions_edit(
                                             VED::edit(
                                                 i,TransitionEdit::IOEdit(*d,j,xp.value.parse().unwrap())
@@ -318,18 +960,457 @@ impl GE {
                         }
                     })}
                     <td> </td>
+                    <td> { self.view_rate_law(i,&t.rate_law) } </td>
+                    <td> { self.view_timing(i,t) } </td>
                     </tr>
                 }
                 })}
+                { for self.submodules.iter().enumerate().map(|(i,s)| self.view_submodule(i,s)) }
                 <tr>
                     <td> <button class="square-button" onclick=|_| Msg::transitions_edit(VED::add())>{"+"}</button> </td>
-                    { for (0..self.petri_net.species.len()+1).map(|_| { html!{ <> <td></td> <td></td> </> } } )}
+                    { for (0..self.petri_net.species.len()+1).map(|_| { html!{ <> <td></td> <td></td> <td></td> <td></td> </> } } )}
                 </tr>
             </table>
             </div>
         }
     }
 
+    // A submodule instance as a row of the matrix, followed by the transitions
+    // of its module on the parent's species when expanded
+    fn view_submodule(&self, i: usize, sub: &Submodule) -> Html<Editor> {
+        let edit = move |e: SubmoduleEdit| Msg::ForLibrary(LibraryEdit::SubmodulesEdit(VecEdit::Edit(i,e)));
+        let module = match self.library.get(sub.module) {
+            Some(m) => &m.petri_net,
+            None => return html!{ <></> }
+        };
+        let n = self.petri_net.species.len();
+        let mapped = |k: usize| sub.species_map.get(k).cloned().flatten().filter(|j| *j < n);
+        html!{
+            <>
+            <tr class="submodule-row">
+                <td class="transition-header">
+                    <button class="square-button" onclick=|_| edit(SubmoduleEdit::Toggle)>
+                        { if sub.expanded { "\u{25be}" } else { "\u{25b8}" } }
+                    </button>
+                    <input class="matrix-input" type="text" value={&sub.name} oninput=|v| edit(SubmoduleEdit::NameEdit(v.value))>
+                    </input>
+                    <button class="square-button" onclick=|_| Msg::ForLibrary(LibraryEdit::SubmodulesEdit(VecEdit::Remove(i)))>{"-"}</button>
+                </td>
+                <td colspan={(4 * n + 1).to_string()}>
+                    <label class="control-label">{ format!("instance of {}:", module.name) }</label>
+                    { for module.species.iter().enumerate().map(|(k,s)| {
+                        let selected = mapped(k);
+                        html!{
+                            <>
+                            <label class="control-label">{ format!("{} \u{2192}", s) }</label>
+                            <select onchange=|c| match c {
+                                ChangeData::Select(s) => edit(SubmoduleEdit::MapEdit(k,
+                                    s.selected_index().and_then(|j| if j == 0 { None } else { Some(j as usize - 1) }))),
+                                _otherwise => edit(SubmoduleEdit::MapEdit(k,selected))
+                            }>
+                                <option selected={selected.is_none()}>{"(internal)"}</option>
+                                { for self.petri_net.species.iter().enumerate().map(|(j,p)| {
+                                    html!{ <option selected={selected == Some(j)}>{p}</option> }
+                                })}
+                            </select>
+                            </>
+                        }
+                    })}
+                </td>
+                <td> </td>
+                <td> </td>
+            </tr>
+            { if !sub.expanded { html!{ <></> } } else { html!{
+                <>
+                { for module.transitions.iter().map(|t| {
+                    let internal: Vec<String> = module.species.iter().enumerate()
+                        .filter(|(k, _)| mapped(*k).is_none())
+                        .filter(|(k, _)| t.arc(IO::Input,*k) != 0 || t.arc(IO::Output,*k) != 0)
+                        .map(|(k, s)| format!("{}: {} \u{2192} {}", s, t.arc(IO::Input,k), t.arc(IO::Output,k)))
+                        .collect();
+                    html!{
+                        <tr class="submodule-transition">
+                            <td class="transition-header">{ format!("{}_{}", sub.name, t.name) }</td>
+                            { for (0..n).map(|j| {
+                                html!{
+                                    { for DIRECTIONS.iter().map(|d| {
+                                        let w: i32 = (0..module.species.len())
+                                            .filter(|k| mapped(*k) == Some(j))
+                                            .map(|k| t.arc(*d,k))
+                                            .sum();
+                                        html!{ <td class="transition-direction">{w}</td> }
+                                    })}
+                                }
+                            })}
+                            <td> </td>
+                            <td>{ t.rate_law.to_string() }</td>
+                            <td>{ if internal.is_empty() { "".to_string() } else { format!("internal {}", internal.join(", ")) } }</td>
+                        </tr>
+                    }
+                })}
+                </>
+            }}}
+            </>
+        }
+    }
+
+    fn view_patches(&self) -> Html<Editor> {
+        let edit = |e: PatchesEdit| Msg::ForPatches(e);
+        let enabled = self.metapopulation.is_some();
+        html!{
+            <div class="patches">
+            <label>
+                <input type="checkbox" checked={enabled} onclick=|_| edit(PatchesEdit::Enable(!enabled))> </input>
+                {" Metapopulation Patches"}
+            </label>
+            { match &self.metapopulation {
+                None => html!{ <></> },
+                Some(m) => {
+                    let initial = m.expand_controls(&self.petri_net, &self.controls).init_vals;
+                    let n = self.petri_net.species.len();
+                    html!{
+                    <>
+                    <select onchange=|c| match c {
+                        ChangeData::Select(s) => edit(PatchesEdit::ViewEdit(PatchView::from_idx(s.selected_index().unwrap_or(0) as usize))),
+                        _otherwise => edit(PatchesEdit::ViewEdit(PatchView::Each))
+                    }>
+                    { for PATCH_VIEWS.iter().enumerate().map(|(j,name)| {
+                        html!{ <option selected={j == m.view.to_idx()}>{name}</option> }
+                    })}
+                    </select>
+                    <table>
+                        <tr>
+                            <th><div class="control-label">{"Patch"}</div></th>
+                            { for m.patches.iter().map(|q| html!{ <th><div class="control-label">{ format!("to {}", q) }</div></th> }) }
+                            { for self.petri_net.species.iter().map(|s| html!{ <th><div class="control-label">{ format!("{}(0)", s) }</div></th> }) }
+                            <th> <button class="square-button" onclick=|_| edit(PatchesEdit::AddPatch)>{"+"}</button> </th>
+                        </tr>
+                        { for m.patches.iter().enumerate().map(|(p,name)| {
+                            html!{
+                                <tr class="control-row">
+                                    <td>
+                                    <input class="transitions-counter" type="text" value={name}
+                                        oninput=|v| edit(PatchesEdit::NameEdit(p,v.value))></input>
+                                    </td>
+                                    { for (0..m.patches.len()).map(|q| {
+                                        let r = m.migration_rate(p,q);
+                                        html!{
+                                            <td>
+                                            { if p == q { html!{ <></> } } else { html!{
+                                                <input class="transitions-counter" type="number" value={r.to_string()}
+                                                    oninput=|v| edit(PatchesEdit::MigrationEdit(p,q,v.value.parse().unwrap_or(r)))></input>
+                                            }}}
+                                            </td>
+                                        }
+                                    })}
+                                    { for (0..n).map(|s| {
+                                        let x = initial[p * n + s];
+                                        html!{
+                                            <td>
+                                            <input class="transitions-counter" type="number" value={x.to_string()}
+                                                oninput=|v| edit(PatchesEdit::InitialEdit(p,s,v.value.parse().unwrap_or(x)))></input>
+                                            </td>
+                                        }
+                                    })}
+                                    <td> <button class="square-button" onclick=|_| edit(PatchesEdit::RemovePatch(p))>{"-"}</button> </td>
+                                </tr>
+                            }
+                        })}
+                        <tr>
+                            <td><div class="control-label">{"Mobility"}</div></td>
+                            { for m.patches.iter().map(|_| html!{ <td></td> }) }
+                            { for self.petri_net.species.iter().enumerate().map(|(s,_)| {
+                                let x = m.mobility(s);
+                                html!{
+                                    <td>
+                                    <input class="transitions-counter" type="number" value={x.to_string()}
+                                        oninput=|v| edit(PatchesEdit::MobilityEdit(s,v.value.parse().unwrap_or(x)))></input>
+                                    </td>
+                                }
+                            })}
+                        </tr>
+                    </table>
+                    </>
+                    }
+                }
+            }}
+            </div>
+        }
+    }
+
+    // Settings for solving the net on a grid, with the species indexed as in
+    // the net with the submodules embedded. Schedules, events, output delays,
+    // stages and patches are not used by the diffusion solver.
+    fn view_diffusion(&self) -> Html<Editor> {
+        let edit = |e: DiffusionEdit| Msg::ForDiffusion(e);
+        let enabled = self.diffusion.is_some();
+        let (petri, controls) = self.flattened();
+        html!{
+            <div class="diffusion">
+            <label>
+                <input type="checkbox" checked={enabled} onclick=|_| edit(DiffusionEdit::Enable(!enabled))> </input>
+                {" Reaction-Diffusion"}
+            </label>
+            { match &self.diffusion {
+                None => html!{ <></> },
+                Some(d) => {
+                    let (w, h, dx, noise, seed) = (d.width, d.height, d.dx, d.noise, d.seed);
+                    let boundary = d.boundary;
+                    html!{
+                    <>
+                    <table>
+                        <tr class="control-row">
+                            <td><div class="control-label">{"Grid"}</div></td>
+                            <td>
+                            <input class="transitions-counter" type="number" value={w.to_string()}
+                                oninput=|v| edit(DiffusionEdit::SizeEdit(v.value.parse().unwrap_or(w),h))></input>
+                            {"\u{d7}"}
+                            <input class="transitions-counter" type="number" value={h.to_string()}
+                                oninput=|v| edit(DiffusionEdit::SizeEdit(w,v.value.parse().unwrap_or(h)))></input>
+                            </td>
+                            <td><div class="control-label">{"Spacing"}</div></td>
+                            <td>
+                            <input class="transitions-counter" type="number" value={dx.to_string()}
+                                oninput=|v| edit(DiffusionEdit::SpacingEdit(v.value.parse().unwrap_or(dx)))></input>
+                            </td>
+                        </tr>
+                        <tr class="control-row">
+                            <td><div class="control-label">{"Boundary"}</div></td>
+                            <td>
+                            <select onchange=|c| match c {
+                                ChangeData::Select(s) => edit(DiffusionEdit::BoundaryEdit(Boundary::from_idx(s.selected_index().unwrap_or(0) as usize))),
+                                _otherwise => edit(DiffusionEdit::BoundaryEdit(Boundary::Neumann))
+                            }>
+                            { for BOUNDARIES.iter().enumerate().map(|(j,name)| {
+                                html!{ <option selected={j == boundary.to_idx()}>{name}</option> }
+                            })}
+                            </select>
+                            { match boundary {
+                                Boundary::Dirichlet(x) => html!{
+                                    <input class="transitions-counter" type="number" value={x.to_string()}
+                                        oninput=|v| edit(DiffusionEdit::BoundaryEdit(Boundary::Dirichlet(v.value.parse().unwrap_or(x))))></input>
+                                },
+                                _otherwise => html!{ <></> }
+                            }}
+                            </td>
+                            <td><div class="control-label" title="Relative amplitude of random perturbations of the initial values">{"Noise"}</div></td>
+                            <td>
+                            <input class="transitions-counter" type="number" value={noise.to_string()}
+                                oninput=|v| edit(DiffusionEdit::NoiseEdit(v.value.parse().unwrap_or(noise)))></input>
+                            </td>
+                        </tr>
+                        <tr class="control-row">
+                            <td><div class="control-label" title="Tokens added in the center cell">{"Seed"}</div></td>
+                            <td>
+                            <select onchange=|c| match c {
+                                ChangeData::Select(s) => match s.selected_index() {
+                                    Some(k) if k > 0 => edit(DiffusionEdit::SeedEdit(Some((k as usize - 1, seed.map_or(1., |(_, x)| x))))),
+                                    _otherwise => edit(DiffusionEdit::SeedEdit(None))
+                                },
+                                _otherwise => edit(DiffusionEdit::SeedEdit(None))
+                            }>
+                            <option selected={seed.is_none()}>{"None"}</option>
+                            { for petri.species.iter().enumerate().map(|(k,name)| {
+                                html!{ <option selected={seed.map_or(false, |(s, _)| s == k)}>{name}</option> }
+                            })}
+                            </select>
+                            { match seed {
+                                Some((k, x)) => html!{
+                                    <input class="transitions-counter" type="number" value={x.to_string()}
+                                        oninput=|v| edit(DiffusionEdit::SeedEdit(Some((k,v.value.parse().unwrap_or(x)))))></input>
+                                },
+                                None => html!{ <></> }
+                            }}
+                            </td>
+                        </tr>
+                    </table>
+                    <table>
+                        <tr>
+                            <th><div class="control-label">{"Diffusion"}</div></th>
+                            { for petri.species.iter().map(|s| html!{ <th><div class="control-label">{s}</div></th> }) }
+                        </tr>
+                        <tr class="control-row">
+                            <td></td>
+                            { for (0..petri.species.len()).map(|s| {
+                                let x = d.coefficient(s);
+                                html!{
+                                    <td>
+                                    <input class="transitions-counter" type="number" value={x.to_string()}
+                                        oninput=|v| edit(DiffusionEdit::CoefficientEdit(s,v.value.parse().unwrap_or(x)))></input>
+                                    </td>
+                                }
+                            })}
+                        </tr>
+                    </table>
+                    <DiffusionPlot petri=petri controls=controls diffusion=d.clone() />
+                    </>
+                    }
+                }
+            }}
+            </div>
+        }
+    }
+
+    fn view_library(&self) -> Html<Editor> {
+        match self.editing {
+            Some(_) => html!{
+                <div class="library">
+                    <label>{ format!("Editing module {}; changes apply to every instance.", self.petri_net.name) }</label>
+                    <button onclick=|_| Msg::ForLibrary(LibraryEdit::FinishModule)>{"Done"}</button>
+                </div>
+            },
+            None => html!{
+                <div class="library">
+                <label>{"Module Library:"}</label>
+                <table>
+                { for self.library.iter().enumerate().map(|(i,m)| {
+                    html!{
+                        <tr class="control-row">
+                            <td>{&m.petri_net.name}</td>
+                            <td> <button onclick=|_| Msg::ForLibrary(LibraryEdit::AddSubmodule(i))>{"Insert"}</button> </td>
+                            <td> <button onclick=|_| Msg::ForLibrary(LibraryEdit::EditModule(i))>{"Edit"}</button> </td>
+                            <td> <button class="square-button" onclick=|_| Msg::ForLibrary(LibraryEdit::RemoveModule(i))>{"-"}</button> </td>
+                        </tr>
+                    }
+                })}
+                </table>
+                <button onclick=|_| Msg::ForLibrary(LibraryEdit::SaveAsModule)>{"Save as Module"}</button>
+                </div>
+            }
+        }
+    }
+
+    fn view_rate_law(&self, i: usize, law: &RateLaw) -> Html<Editor> {
+        let edit = move |l: RateLaw| Msg::transitions_edit(VED::edit(i,TransitionEdit::RateLawEdit(l)));
+        let selected = law.to_idx();
+        html!{
+            <>
+            <select onchange=|c| match c {
+                ChangeData::Select(s) => edit(RateLaw::from_idx(s.selected_index().unwrap_or(0) as usize)),
+                _otherwise => edit(RateLaw::MassAction)
+            }>
+            { for RATE_LAWS.iter().enumerate().map(|(j,name)| {
+                html!{ <option selected={j == selected}>{name}</option> }
+            })}
+            </select>
+            { match law.clone() {
+                RateLaw::MichaelisMenten { substrate, km } => html!{
+                    <>
+                    { self.view_substrate(substrate, move |s| edit(RateLaw::MichaelisMenten { substrate: s, km })) }
+                    <label class="control-label">{"Km"}</label>
+                    <input class="transitions-counter" type="number" value={km.to_string()} oninput=|v|
+                        edit(RateLaw::MichaelisMenten { substrate, km: v.value.parse().unwrap_or(km) })> </input>
+                    </>
+                },
+                RateLaw::Hill { substrate, k, n } => html!{
+                    <>
+                    { self.view_substrate(substrate, move |s| edit(RateLaw::Hill { substrate: s, k, n })) }
+                    <label class="control-label">{"K"}</label>
+                    <input class="transitions-counter" type="number" value={k.to_string()} oninput=|v|
+                        edit(RateLaw::Hill { substrate, k: v.value.parse().unwrap_or(k), n })> </input>
+                    <label class="control-label">{"n"}</label>
+                    <input class="transitions-counter" type="number" value={n.to_string()} oninput=|v|
+                        edit(RateLaw::Hill { substrate, k, n: v.value.parse().unwrap_or(n) })> </input>
+                    </>
+                },
+                RateLaw::Expression(src) => html!{
+                    <>
+                    <input class="matrix-input" type="text" value={&src} oninput=|v|
+                        edit(RateLaw::Expression(v.value))> </input>
+                    { match self.petri_net.check_rate_law(i) {
+                        Err(e) => html!{ <span class="parse-error">{e.to_string()}</span> },
+                        Ok(Some(w)) => html!{ <span class="control-label">{w}</span> },
+                        Ok(None) => html!{ <></> }
+                    }}
+                    </>
+                },
+                _otherwise => html!{ <></> }
+            }}
+            </>
+        }
+    }
+
+    fn view_timing(&self, i: usize, t: &Transition) -> Html<Editor> {
+        let (timing, policy, delay, stages) = (&t.timing, t.policy, t.delay, t.stages);
+        let edit = move |t: Timing| Msg::transitions_edit(VED::edit(i,TransitionEdit::TimingEdit(t)));
+        let selected = timing.to_idx();
+        let number = move |label: &str, x: f32, set: Box<dyn Fn(f32) -> Timing>| html!{
+            <>
+            <label class="control-label">{label}</label>
+            <input class="transitions-counter" type="number" value={x.to_string()} oninput=|v|
+                edit(set(v.value.parse().unwrap_or(x)))> </input>
+            </>
+        };
+        html!{
+            <>
+            <select onchange=|c| match c {
+                ChangeData::Select(s) => edit(Timing::from_idx(s.selected_index().unwrap_or(0) as usize)),
+                _otherwise => edit(Timing::Exponential)
+            }>
+            { for TIMINGS.iter().enumerate().map(|(j,name)| {
+                html!{ <option selected={j == selected}>{name}</option> }
+            })}
+            </select>
+            { match timing.clone() {
+                Timing::Exponential => html!{ <></> },
+                Timing::Deterministic(d) => number("delay", d, Box::new(Timing::Deterministic)),
+                Timing::Erlang { k, rate } => html!{
+                    <>
+                    { number("k", k as f32, Box::new(move |k| Timing::Erlang { k: k.max(1.) as u32, rate })) }
+                    { number("rate", rate, Box::new(move |rate| Timing::Erlang { k, rate })) }
+                    </>
+                },
+                Timing::Weibull { shape, scale } => html!{
+                    <>
+                    { number("shape", shape, Box::new(move |shape| Timing::Weibull { shape, scale })) }
+                    { number("scale", scale, Box::new(move |scale| Timing::Weibull { shape, scale })) }
+                    </>
+                },
+                Timing::LogNormal { location, scale } => html!{
+                    <>
+                    { number("location", location, Box::new(move |location| Timing::LogNormal { location, scale })) }
+                    { number("scale", scale, Box::new(move |scale| Timing::LogNormal { location, scale })) }
+                    </>
+                },
+            }}
+            { if *timing == Timing::Exponential { html!{ <></> } } else { html!{
+                <select onchange=|c| match c {
+                    ChangeData::Select(s) => Msg::transitions_edit(VED::edit(i,TransitionEdit::PolicyEdit(
+                        Policy::from_idx(s.selected_index().unwrap_or(0) as usize)))),
+                    _otherwise => Msg::transitions_edit(VED::edit(i,TransitionEdit::PolicyEdit(policy)))
+                }>
+                { for POLICIES.iter().enumerate().map(|(j,name)| {
+                    html!{ <option selected={j == policy.to_idx()}>{name}</option> }
+                })}
+                </select>
+            }}}
+            <label class="control-label" title="Time after firing at which the outputs appear">{"output delay"}</label>
+            <input class="transitions-counter" type="number" value={delay.to_string()} oninput=|v|
+                Msg::transitions_edit(VED::edit(i,TransitionEdit::DelayEdit(v.value.parse().unwrap_or(delay))))> </input>
+            <label class="control-label" title="Expand into a chain of this many stages, for Erlang distributed dwell times">{"stages"}</label>
+            <input class="transitions-counter" type="number" min="1" value={stages.max(1).to_string()} oninput=|v|
+                Msg::transitions_edit(VED::edit(i,TransitionEdit::StagesEdit(v.value.parse().unwrap_or(stages))))> </input>
+            { match self.petri_net.check_stages(i) {
+                Err(e) => html!{ <span class="parse-error">{e}</span> },
+                Ok(()) => html!{ <></> }
+            }}
+            </>
+        }
+    }
+
+    fn view_substrate(&self, substrate: usize, edit: impl Fn(usize) -> Msg + 'static) -> Html<Editor> {
+        html!{
+            <select onchange=|c| match c {
+                ChangeData::Select(s) => edit(s.selected_index().unwrap_or(0) as usize),
+                _otherwise => edit(substrate)
+            }>
+            { for self.petri_net.species.iter().enumerate().map(|(j,s)| {
+                html!{ <option selected={j == substrate}>{s}</option> }
+            })}
+            </select>
+        }
+    }
+
     fn view_controls(&self) -> Html<Editor> {
         html!{
             <div class="controls">
@@ -363,25 +1444,276 @@ impl GE {
             <table>
                 <tr>
                 <th class="control-cell"><div class="control-label">{"Transition"}</div></th>
+                <th class="control-cell"><div class="control-label">{"Source"}</div></th>
                 <th class="control-cell"><div class="control-label">{"Rate"}</div></th>
+                <th><div class="control-label">{"Range"}</div></th>
+                <th><div class="control-label">{"Schedule"}</div></th>
                 </tr>
             { for self.petri_net.transitions.iter().enumerate().map(|(i,t)| {
+                let (lo,hi) = self.controls.rate_range(i);
+                let parameters = self.petri_net.parameter_names();
+                let source = t.rate.as_ref().and_then(|r| parameters.iter().position(|p| p == r));
                 html!{
                     <tr class="control-row">
                         <td class="control-cell"><div class="control-label">{&t.name}</div></td>
                         <td class="control-cell">
-                        <input class="table-form control-slider" value={self.controls.rates[i]} type="range" min="0" max={self.controls.scale} step="0.01"
-                        oninput=|v| { Msg::rates_edit(i,v.value.parse().unwrap()) }></input>
+                        <select onchange=|c| {
+                            let rate = match c {
+                                ChangeData::Select(s) => s.selected_index()
+                                    .and_then(|j| if j == 0 { None } else { parameters.get(j as usize - 1).cloned() }),
+                                _otherwise => None
+                            };
+                            Msg::transitions_edit(VED::edit(i,TransitionEdit::RateEdit(rate)))
+                        }>
+                            <option selected={source.is_none()}>{"Slider"}</option>
+                            { for self.petri_net.parameters.iter().enumerate().map(|(j,p)| {
+                                html!{ <option selected={source == Some(j)}>{&p.name}</option> }
+                            })}
+                        </select>
                         </td>
+                        <td class="control-cell">
+                        { match source {
+                            Some(j) => html!{
+                                <div class="control-label">{self.petri_net.parameters[j].value}</div>
+                            },
+                            None => html!{
+                                <input class="table-form control-slider" value={self.controls.rates[i]} type="range" min="0" max={self.controls.scale} step="0.01"
+                                oninput=|v| { Msg::rates_edit(i,v.value.parse().unwrap()) }></input>
+                            }
+                        }}
+                        </td>
+                        <td>
+                        <input class="transitions-counter" type="number" value={lo.to_string()}
+                        oninput=|v| { Msg::rate_range_edit(i,(v.value.parse().unwrap_or(0.),hi)) }></input>
+                        <input class="transitions-counter" type="number" value={hi.to_string()}
+                        oninput=|v| { Msg::rate_range_edit(i,(lo,v.value.parse().unwrap_or(0.))) }></input>
+                        </td>
+                        <td> { self.view_schedule(i,&t.schedule) } </td>
                     </tr>
                 }
             })}
             </table>
+
+            <hr />
+            { self.view_parameters() }
+            <hr />
+            { self.view_observables() }
+            <hr />
+            { self.view_events() }
             </div>
         }
     }
 
+    fn view_schedule(&self, i: usize, schedule: &Schedule) -> Html<Editor> {
+        let edit = move |s: Schedule| Msg::transitions_edit(VED::edit(i,TransitionEdit::ScheduleEdit(s)));
+        let selected = schedule.to_idx();
+        html!{
+            <>
+            <select onchange=|c| match c {
+                ChangeData::Select(s) => edit(Schedule::from_idx(s.selected_index().unwrap_or(0) as usize)),
+                _otherwise => edit(Schedule::Constant)
+            }>
+            { for SCHEDULES.iter().enumerate().map(|(j,name)| {
+                html!{ <option selected={j == selected}>{name}</option> }
+            })}
+            </select>
+            { match schedule.clone() {
+                Schedule::Piecewise(b) => html!{
+                    <input class="matrix-input" type="text" placeholder="t:m, t:m" value={format_breakpoints(&b)} onchange=|c| match c {
+                        ChangeData::Value(v) => edit(Schedule::Piecewise(parse_breakpoints(&v).unwrap_or_else(|| b.clone()))),
+                        _otherwise => edit(Schedule::Piecewise(b.clone()))
+                    }> </input>
+                },
+                Schedule::Expression(src) => html!{
+                    <input class="matrix-input" type="text" value={&src} oninput=|v|
+                        edit(Schedule::Expression(v.value))> </input>
+                },
+                Schedule::Constant => html!{ <></> }
+            }}
+            { match self.petri_net.check_schedule(i) {
+                Err(e) => html!{ <span class="parse-error">{e}</span> },
+                Ok(()) => html!{ <></> }
+            }}
+            </>
+        }
+    }
+
+    fn view_events(&self) -> Html<Editor> {
+        html!{
+            <>
+            <label>{"Events:"}</label>
+            <table>
+                <tr>
+                <th><div class="control-label">{"Trigger"}</div></th>
+                <th><div class="control-label">{"Actions"}</div></th>
+                <th> <button class="square-button" onclick=|_| Msg::events_edit(VED::add())>{"+"}</button> </th>
+                </tr>
+            { for self.petri_net.events.iter().enumerate().map(|(i,e)| {
+                let edit = move |e: EventEdit| Msg::events_edit(VED::edit(i,e));
+                html!{
+                    <tr class="control-row">
+                        <td>
+                        <select onchange=|c| match c {
+                            ChangeData::Select(s) if s.selected_index() == Some(1) =>
+                                edit(EventEdit::TriggerEdit(Trigger::When("".to_string()))),
+                            _otherwise => edit(EventEdit::TriggerEdit(Trigger::At(0.)))
+                        }>
+                            <option selected={match e.trigger { Trigger::At(_) => true, _ => false }}>{"At"}</option>
+                            <option selected={match e.trigger { Trigger::When(_) => true, _ => false }}>{"When"}</option>
+                        </select>
+                        { match e.trigger.clone() {
+                            Trigger::At(t) => html!{
+                                <input class="transitions-counter" type="number" value={t.to_string()} oninput=|v|
+                                    edit(EventEdit::TriggerEdit(Trigger::At(v.value.parse().unwrap_or(t))))> </input>
+                            },
+                            Trigger::When(c) => html!{
+                                <input class="matrix-input" type="text" placeholder="I > 100" value={&c} oninput=|v|
+                                    edit(EventEdit::TriggerEdit(Trigger::When(v.value)))> </input>
+                            }
+                        }}
+                        { match self.petri_net.check_event(i) {
+                            Err(e) => html!{ <span class="parse-error">{e}</span> },
+                            Ok(()) => html!{ <></> }
+                        }}
+                        </td>
+                        <td>
+                        { for e.actions.iter().enumerate().map(|(j,a)| self.view_action(i,j,a)) }
+                        <button class="square-button" onclick=|_| edit(EventEdit::ActionsEdit(VED::add()))>{"+"}</button>
+                        </td>
+                        <td> <button class="square-button" onclick=|_| Msg::events_edit(VED::remove(i))>{"-"}</button> </td>
+                    </tr>
+                }
+            })}
+            </table>
+            </>
+        }
+    }
+
+    fn view_action(&self, i: usize, j: usize, action: &Action) -> Html<Editor> {
+        let edit = move |e: VED<PlainEdit<Action>>| Msg::events_edit(VED::edit(i,EventEdit::ActionsEdit(e)));
+        let set = move |a: Action| edit(VED::edit(j,PlainEdit::PlainEdit(a)));
+        let (scale, target, amount) = match action.clone() {
+            Action::Scale(t,f) => (true, t, f),
+            Action::AddTokens(s,a) => (false, s, a)
+        };
+        let make = move |scale: bool, target: String, amount: f32| {
+            if scale { Action::Scale(target,amount) } else { Action::AddTokens(target,amount) }
+        };
+        let target2 = target.clone();
+        html!{
+            <div>
+            <select onchange=|c| match c {
+                ChangeData::Select(s) => set(make(s.selected_index() != Some(1), target2.clone(), amount)),
+                _otherwise => set(make(scale, target2.clone(), amount))
+            }>
+                <option selected={scale}>{"Multiply"}</option>
+                <option selected={!scale}>{"Add to"}</option>
+            </select>
+            <input class="transitions-counter" type="text" value={&target} oninput=|v|
+                set(make(scale, v.value, amount))> </input>
+            <label class="control-label">{ if scale { "by" } else { "amount" } }</label>
+            <input class="transitions-counter" type="number" value={amount.to_string()} oninput=|v|
+                set(make(scale, target.clone(), v.value.parse().unwrap_or(amount)))> </input>
+            <button class="square-button" onclick=|_| edit(VED::remove(j))>{"-"}</button>
+            </div>
+        }
+    }
+
+    fn view_parameters(&self) -> Html<Editor> {
+        let edit = |i: usize, e: ParameterEdit| Msg::parameters_edit(VED::edit(i,e));
+        html!{
+            <>
+            <label>{"Parameters:"}</label>
+            <table>
+                <tr>
+                <th><div class="control-label">{"Name"}</div></th>
+                <th class="control-cell"><div class="control-label">{"Value"}</div></th>
+                <th><div class="control-label">{"Range"}</div></th>
+                <th><div class="control-label">{"Unit"}</div></th>
+                <th> <button class="square-button" onclick=|_| Msg::parameters_edit(VED::add())>{"+"}</button> </th>
+                </tr>
+            { for self.petri_net.parameters.iter().enumerate().map(|(i,p)| {
+                let (lo,hi,value) = (p.min,p.max,p.value);
+                html!{
+                    <tr class="control-row">
+                        <td>
+                        <input class="transitions-counter" type="text" value={&p.name}
+                        oninput=|v| edit(i,ParameterEdit::NameEdit(v.value))></input>
+                        </td>
+                        <td class="control-cell">
+                        <input class="table-form control-slider" value={p.value} type="range" min={lo} max={hi} step="0.01"
+                        oninput=|v| edit(i,ParameterEdit::ValueEdit(v.value.parse().unwrap_or(value)))></input>
+                        <div class="control-label">{p.value}</div>
+                        </td>
+                        <td>
+                        <input class="transitions-counter" type="number" value={lo.to_string()}
+                        oninput=|v| edit(i,ParameterEdit::RangeEdit(v.value.parse().unwrap_or(lo),hi))></input>
+                        <input class="transitions-counter" type="number" value={hi.to_string()}
+                        oninput=|v| edit(i,ParameterEdit::RangeEdit(lo,v.value.parse().unwrap_or(hi)))></input>
+                        </td>
+                        <td>
+                        <input class="transitions-counter" type="text" value={&p.unit}
+                        oninput=|v| edit(i,ParameterEdit::UnitEdit(v.value))></input>
+                        </td>
+                        <td> <button class="square-button" onclick=|_| Msg::parameters_edit(VED::remove(i))>{"-"}</button> </td>
+                    </tr>
+                }
+            })}
+            </table>
+            </>
+        }
+    }
+
+    fn view_observables(&self) -> Html<Editor> {
+        let n = self.petri_net.species.len();
+        html!{
+            <>
+            <label>{"Observables:"}</label>
+            <table>
+                <tr>
+                <th><div class="control-label">{"Name"}</div></th>
+                <th><div class="control-label">{"Expression"}</div></th>
+                <th> <button class="square-button" onclick=|_| Msg::observables_edit(VED::add())>{"+"}</button> </th>
+                </tr>
+            { for self.petri_net.observables.iter().enumerate().map(|(i,o)| {
+                html!{
+                    <tr class="control-row" style={color_style(get_color(n + i))}>
+                        <td>
+                        <input class="transitions-counter" type="text" value={&o.name}
+                        oninput=|v| Msg::observables_edit(VED::edit(i,ObservableEdit::NameEdit(v.value)))></input>
+                        </td>
+                        <td>
+                        <input class="matrix-input" type="text" value={&o.expression}
+                        oninput=|v| Msg::observables_edit(VED::edit(i,ObservableEdit::ExpressionEdit(v.value)))></input>
+                        { match self.petri_net.check_observable(i) {
+                            Err(e) => html!{ <span class="parse-error">{e.to_string()}</span> },
+                            Ok(()) => html!{ <></> }
+                        }}
+                        </td>
+                        <td> <button class="square-button" onclick=|_| Msg::observables_edit(VED::remove(i))>{"-"}</button> </td>
+                    </tr>
+                }
+            })}
+            </table>
+            </>
+        }
+    }
+
     fn view(&self) -> Html<Editor> {
+        let (petri, controls) = self.simulated();
+        let patches = match self.editing {
+            None => self.metapopulation.clone(),
+            Some(_) => None
+        };
+        // Stages are only collapsed in plots of a single population
+        let flat = self.flattened().0;
+        let has_stages = flat.has_stages();
+        let stages = if self.collapse_stages && has_stages && patches.is_none() {
+            Some(flat.expand_stages())
+        } else {
+            None
+        };
+        let collapse = self.collapse_stages;
         html!{
             <>
             <div class="row" style="margin-top:50px">
@@ -389,7 +1721,23 @@ impl GE {
                     { self.view_controls() }
                 </div>
                 <div class="column two-thirds">
-                  <Plot petri=self.petri_net.clone() controls=self.controls.clone() />
+                  <Plot petri=petri.clone() controls=controls.clone() patches=patches stages=stages />
+                  { if has_stages { html!{
+                      <label>
+                          <input type="checkbox" checked={collapse} onclick=|_| Msg::CollapseStages(!collapse)> </input>
+                          {" Collapse Stages"}
+                      </label>
+                  }} else { html!{ <></> } }}
+                </div>
+            </div>
+            <div class="row">
+                <div class="column">
+                    { self.view_library() }
+                </div>
+            </div>
+            <div class="row">
+                <div class="column">
+                    { self.view_patches() }
                 </div>
             </div>
             <div class="row">
@@ -400,6 +1748,24 @@ impl GE {
                     { self.view_matrix() }
                 </div>
             </div>
+            <div class="row">
+                <hr />
+            </div>
+            <div class="row">
+                <div class="column">
+                  <SensitivityPlot petri=petri.clone() controls=controls.clone() />
+                </div>
+            </div>
+            <div class="row">
+                <div class="column">
+                  <IdentifiabilityCheck petri=petri controls=controls />
+                </div>
+            </div>
+            <div class="row">
+                <div class="column">
+                    { self.view_diffusion() }
+                </div>
+            </div>
             </>
         }
     }
@@ -416,16 +1782,76 @@ impl GE {
 }
 
 impl SE {
-    fn to_ge(&self) -> Option<GE> {
-        serde_json::from_str(&self.code).ok()
+    // The graphical editor for the source, with warnings about the conversion
+    fn to_ge(&self) -> Result<(GE, Vec<String>), String> {
+        match self.format {
+            SourceFormat::Json => serde_json::from_str(&self.code)
+                .map(|ge| (ge, vec![]))
+                .map_err(|e| e.to_string()),
+            SourceFormat::Reactions => {
+                let (petri, controls) = read_reactions(&self.code).map_err(|e| e.to_string())?;
+                self.with_net(petri, controls, vec![])
+            }
+            SourceFormat::Sbml => {
+                let import = read_sbml(&self.code)?;
+                self.with_net(import.petri, import.controls, import.warnings)
+            }
+            SourceFormat::Pnml => {
+                let import = read_pnml(&self.code)?;
+                self.with_net(import.petri, import.controls, import.warnings)
+            }
+            SourceFormat::Acset => {
+                let import = read_acset(&self.code)?;
+                self.with_net(import.petri, import.controls, import.warnings)
+            }
+            SourceFormat::Antimony if self.code.trim().is_empty() => {
+                serde_json::from_str(&self.base).map(|ge| (ge, vec![])).map_err(|e| e.to_string())
+            }
+            SourceFormat::Antimony => {
+                let import = read_antimony(&self.code)?;
+                self.with_net(import.petri, import.controls, import.warnings)
+            }
+        }
+    }
+
+    // The graphical editor of `base` with its net and controls replaced
+    fn with_net(&self, petri: PetriNet, controls: PlotControls, warnings: Vec<String>) -> Result<(GE, Vec<String>), String> {
+        let mut ge: GE = serde_json::from_str(&self.base).map_err(|e| e.to_string())?;
+        // The submodules were embedded into the exported net
+        if ge.editing.is_none() {
+            ge.submodules = vec![];
+        }
+        // Formats without a name for the net keep the current one
+        let name = if petri.name.is_empty() { ge.petri_net.name.clone() } else { petri.name.clone() };
+        ge.petri_net = PetriNet { name, ..petri };
+        ge.controls = controls;
+        Ok((ge, warnings))
+    }
+
+    // Checks the source, showing what would be lost by switching back
+    fn check(&mut self) {
+        self.messages = match self.to_ge() {
+            Ok((_, warnings)) => warnings,
+            Err(e) => vec![e],
+        };
     }
 
     fn view(&self) -> Html<Editor> {
         html!{
             <div class="row" style="margin-top:50px">
-                <textarea class="sourceview" oninput=|e| {Msg::SourceUpdate(e.value)}>
-                    {&self.code}
+                <select onchange=|c| match c {
+                    ChangeData::Select(s) => Msg::SourceFormat(SourceFormat::from_idx(s.selected_index().unwrap_or(0) as usize)),
+                    _otherwise => Msg::SourceFormat(SourceFormat::Json)
+                }>
+                { for SOURCE_FORMATS.iter().enumerate().map(|(j,name)| {
+                    html!{ <option selected={j == self.format.to_idx()}>{name}</option> }
+                })}
+                </select>
+                <textarea class="sourceview" value={&self.code} oninput=|e| {Msg::SourceUpdate(e.value)}>
                 </textarea>
+                <ul class="source-messages">
+                { for self.messages.iter().map(|m| html!{ <li>{m}</li> }) }
+                </ul>
             </div>
         }
     }
@@ -455,31 +1881,105 @@ impl Component for Editor {
         match &mut self.state {
             EditorState::Graphical(ge) => match msg {
                 Msg::ForPetri(e) => {
+                    ge.sync_submodules(&e);
+                    ge.sync_patches(&e);
+                    ge.sync_diffusion(&e);
                     ControlsEdit::from_petri_edit(&e).map(|ce| ce.apply_edit(&mut ge.controls));
                     e.apply_edit(&mut ge.petri_net);
                 }
                 Msg::ForControls(e) => {
                     e.apply_edit(&mut ge.controls);
                 }
+                Msg::ForLibrary(e) => {
+                    e.apply_edit(ge);
+                }
+                Msg::ForPatches(e) => {
+                    e.apply_edit(&mut ge.metapopulation);
+                }
+                Msg::ForDiffusion(e) => {
+                    e.apply_edit(&mut ge.diffusion);
+                }
+                Msg::CollapseStages(b) => {
+                    ge.collapse_stages = b;
+                }
                 Msg::ViewSwitch => {
-                    self.state = EditorState::Source(ge.to_se())
+                    self.state = EditorState::Source(ge.to_se(SourceFormat::Json))
                 }
                 Msg::SaveInUrl => {
                     let location = web_sys::window().unwrap().location();
                     location.set_hash(&ge.to_url_hash()).unwrap();
                 }
+                Msg::Download(d @ Download::Latex) | Msg::Download(d @ Download::Scipy)
+                | Msg::Download(d @ Download::Julia) | Msg::Download(d @ Download::Rust) => {
+                    let (petri, controls) = ge.exchanged();
+                    let name = if petri.name.is_empty() { "equations" } else { petri.name.as_str() };
+                    let ((code, warnings), extension, mime, comment) = match d {
+                        Download::Latex => (write_latex(&petri, &controls), "tex", "application/x-tex", "%"),
+                        Download::Scipy => (write_scipy(&petri, &controls), "py", "text/x-python", "#"),
+                        Download::Julia => (write_julia(&petri, &controls), "jl", "text/x-julia", "#"),
+                        _otherwise => (write_rust(&petri, &controls), "rs", "text/x-rust", "//"),
+                    };
+                    // Warnings go at the top of the file, as there is nowhere else to show them
+                    let header: String = warnings.iter().map(|w| format!("{} Warning: {}\n", comment, w)).collect();
+                    download(&format!("{}.{}", name, extension), mime, &(header + &code));
+                }
+                Msg::Download(d @ Download::Dot) | Msg::Download(d @ Download::Tikz) => {
+                    let (petri, _) = ge.flattened();
+                    let name = if petri.name.is_empty() { "net" } else { petri.name.as_str() };
+                    if d == Download::Dot {
+                        download(&format!("{}.dot", name), "text/vnd.graphviz", &write_dot(&petri));
+                    } else {
+                        download(&format!("{}.tex", name), "application/x-tex", &write_tikz(&petri));
+                    }
+                }
+                Msg::Download(Download::Placeholder) => { }
+                Msg::Download(d) => {
+                    let (petri, controls) = ge.simulated();
+                    let mut rng = rand::thread_rng();
+                    let (trajectory, what) = match d {
+                        Download::OdeCsv | Download::OdeJson => (petri.trajectory(&controls, STEPS), "ode"),
+                        Download::StochasticCsv | Download::StochasticJson => (petri.stochastic_trajectory(&controls, STEPS, &mut rng), "stochastic"),
+                        _otherwise => (petri.ensemble_summary(&controls, STEPS, ENSEMBLE_RUNS, &mut rng), "ensemble"),
+                    };
+                    let name = if petri.name.is_empty() { "trajectory" } else { petri.name.as_str() };
+                    match d {
+                        Download::OdeCsv | Download::StochasticCsv | Download::EnsembleCsv => {
+                            download(&format!("{}-{}.csv", name, what), "text/csv", &trajectory.to_csv());
+                        }
+                        _otherwise => {
+                            download(&format!("{}-{}.json", name, what), "application/json", &trajectory.to_json());
+                        }
+                    }
+                }
                 _otherwise => { }
             },
             EditorState::Source(se) => match msg {
                 Msg::SourceUpdate(c) => {
                     se.code = c;
+                    if se.format != SourceFormat::Json {
+                        se.check();
+                    }
+                }
+                Msg::SourceFormat(f) => {
+                    match se.to_ge() {
+                        Ok((ge, warnings)) => {
+                            let mut next = ge.to_se(f);
+                            next.messages = warnings.into_iter().chain(next.messages).collect();
+                            *se = next;
+                        }
+                        Err(e) => {
+                            se.messages = vec![e];
+                        }
+                    }
                 }
                 Msg::ViewSwitch => {
                     match se.to_ge() {
-                        Some(ge) => {
+                        Ok((ge, _)) => {
                             self.state = EditorState::Graphical(ge);
                         }
-                        None => { }
+                        Err(e) => {
+                            se.messages = vec![e];
+                        }
                     }
                 }
                 _otherwise => { }
@@ -496,6 +1996,19 @@ impl Component for Editor {
                     <span class="menu-action"><a href="https://owenlynch.org/posts/2019-12-28-ez-petri">{"Help"}</a></span>
                     <span class="menu-action" onclick=|_| Msg::ViewSwitch>{"Toggle Source"}</span>
                     <span class="menu-action" onclick=|_| Msg::SaveInUrl>{"Save in URL"}</span>
+                    <select class="menu-action" onchange=|e| match e {
+                        ChangeData::Select(s) => {
+                            let i = s.selected_index().unwrap_or(0) as usize;
+                            // Back to the placeholder, so that the same entry can be chosen again
+                            s.set_selected_index(Some(0));
+                            Msg::Download(Download::from_idx(i))
+                        }
+                        _otherwise => Msg::Download(Download::Placeholder)
+                    }>
+                    { for DOWNLOADS.iter().enumerate().map(|(j,name)| {
+                        html!{ <option selected={j == Download::Placeholder.to_idx()}>{name}</option> }
+                    })}
+                    </select>
                 </div>
             { match &self.state {
                 EditorState::Graphical(ge) => ge.view(),
@@ -0,0 +1,359 @@
+use serde::{Serialize, Deserialize};
+use ndarray::prelude::*;
+use crate::expr::*;
+use crate::math::*;
+use crate::petri::*;
+
+// Time dependence of the rate of a transition, as a multiplier of its rate
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub enum Schedule {
+    #[default]
+    Constant,
+    // Breakpoints (t, m): the multiplier is m from time t until the next
+    // breakpoint, and 1 before the first one
+    Piecewise(Vec<(f32, f32)>),
+    // An expression in `t`, the species and the parameters
+    Expression(String),
+}
+
+pub static SCHEDULES: [&str; 3] = ["Constant", "Piecewise", "Expression"];
+
+impl Schedule {
+    pub fn to_idx(&self) -> usize {
+        match self {
+            Schedule::Constant => 0,
+            Schedule::Piecewise(_) => 1,
+            Schedule::Expression(_) => 2,
+        }
+    }
+
+    pub fn from_idx(i: usize) -> Self {
+        match i {
+            1 => Schedule::Piecewise(vec![]),
+            2 => Schedule::Expression("1".to_string()),
+            _ => Schedule::Constant,
+        }
+    }
+}
+
+// Breakpoints written as `t:m, t:m, ...`
+pub fn format_breakpoints(b: &[(f32, f32)]) -> String {
+    b.iter().map(|(t, m)| format!("{}:{}", t, m)).collect::<Vec<_>>().join(", ")
+}
+
+// None unless every time and multiplier is a finite number
+pub fn parse_breakpoints(s: &str) -> Option<Vec<(f32, f32)>> {
+    s.split(',')
+        .filter(|p| !p.trim().is_empty())
+        .map(|p| {
+            let mut it = p.split(':');
+            let t: f32 = it.next()?.trim().parse().ok()?;
+            let m: f32 = it.next()?.trim().parse().ok()?;
+            if it.next().is_some() || !t.is_finite() || !m.is_finite() { None } else { Some((t, m)) }
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Trigger {
+    // Fires once, at the given time
+    At(f32),
+    // Fires whenever the condition becomes true, e.g. `I > 100`
+    When(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Action {
+    // Multiplies a parameter, or the rate of a transition, by a factor
+    Scale(String, f32),
+    // Adds tokens to a species, or removes them if negative
+    AddTokens(String, f32),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    pub trigger: Trigger,
+    pub actions: Vec<Action>,
+}
+
+enum Multiplier {
+    Constant,
+    Piecewise(Vec<(f64, f64)>),
+    Expression(Compiled),
+}
+
+enum When {
+    At(f64),
+    Condition(Compiled),
+}
+
+enum Effect {
+    ScaleRate(usize, f64),
+    ScaleParameter(usize, f64),
+    AddTokens(usize, f64),
+}
+
+struct CompiledEvent {
+    when: When,
+    effects: Vec<Effect>,
+}
+
+// Schedules and events, compiled against a net
+pub struct Dynamics {
+    base_rates: Vec<f64>,
+    // The parameter giving the rate of each transition, if any, which events
+    // can scale
+    rate_parameters: Vec<Option<usize>>,
+    multipliers: Vec<Multiplier>,
+    events: Vec<CompiledEvent>,
+}
+
+// What the events change as a simulation runs
+#[derive(Clone, Debug)]
+pub struct EventState {
+    pub scales: Vec<f64>,
+    pub parameters: Vec<f64>,
+    fired: Vec<bool>,
+    // Value of each condition when last polled, to fire only when it becomes true
+    active: Vec<bool>,
+}
+
+fn compile_schedule(schedule: &Schedule, resolve: &dyn Fn(&str) -> Option<(usize, usize)>) -> Result<Multiplier, String> {
+    Ok(match schedule {
+        Schedule::Constant => Multiplier::Constant,
+        Schedule::Piecewise(b) => {
+            let mut b: Vec<(f64, f64)> = b.iter().map(|(t, m)| (*t as f64, *m as f64)).collect();
+            b.sort_by(|x, y| x.0.total_cmp(&y.0));
+            Multiplier::Piecewise(b)
+        }
+        Schedule::Expression(src) => Multiplier::Expression(compile(src, resolve).map_err(|e| e.to_string())?),
+    })
+}
+
+fn compile_event(petri: &PetriNet, event: &Event, resolve: &dyn Fn(&str) -> Option<(usize, usize)>) -> Result<CompiledEvent, String> {
+    let when = match &event.trigger {
+        Trigger::At(t) => When::At(*t as f64),
+        Trigger::When(src) => When::Condition(compile(src, resolve).map_err(|e| e.to_string())?),
+    };
+    let effects = event.actions.iter()
+        .map(|a| match a {
+            Action::Scale(name, factor) => {
+                petri.parameters.iter().position(|p| &p.name == name)
+                    .map(|i| Effect::ScaleParameter(i, *factor as f64))
+                    .or_else(|| petri.transitions.iter().position(|t| &t.name == name)
+                             .map(|i| Effect::ScaleRate(i, *factor as f64)))
+                    .ok_or_else(|| format!("unknown parameter or transition {}", name))
+            }
+            Action::AddTokens(name, amount) => {
+                petri.species.iter().position(|s| s == name)
+                    .map(|i| Effect::AddTokens(i, *amount as f64))
+                    .ok_or_else(|| format!("unknown species {}", name))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(CompiledEvent { when, effects })
+}
+
+impl PetriNet {
+    pub fn check_schedule(&self, t: usize) -> Result<(), String> {
+        self.with_resolver(|resolve| compile_schedule(&self.transitions[t].schedule, resolve)).map(|_| ())
+    }
+
+    pub fn check_event(&self, i: usize) -> Result<(), String> {
+        self.with_resolver(|resolve| compile_event(self, &self.events[i], resolve)).map(|_| ())
+    }
+
+    // Solves the ODE on [0, xmax], honoring schedules and events
+    pub fn simulate(&self, controls: &PlotControls, steps: usize) -> YVals {
+        let dynamics = Dynamics::new(self, controls);
+        solve_with_events(&self.get_petri_data(), &dynamics,
+                          &Array::from(controls.init_vals.clone()), controls.xmax, steps)
+    }
+}
+
+impl Dynamics {
+    // Invalid schedules are treated as constant and invalid events are
+    // dropped; both are reported in the editor.
+    pub fn new(petri: &PetriNet, controls: &PlotControls) -> Self {
+        petri.with_resolver(|resolve| {
+            Dynamics {
+                base_rates: petri.effective_rates(controls).iter().map(|r| *r as f64).collect(),
+                rate_parameters: petri.transitions.iter()
+                    .map(|t| t.rate.as_ref().and_then(|n| petri.parameters.iter().position(|p| &p.name == n)))
+                    .collect(),
+                multipliers: petri.transitions.iter()
+                    .map(|t| compile_schedule(&t.schedule, resolve).unwrap_or(Multiplier::Constant))
+                    .collect(),
+                events: petri.events.iter()
+                    .filter_map(|e| compile_event(petri, e, resolve).ok())
+                    .collect(),
+            }
+        })
+    }
+
+    pub fn initial_state(&self, parameters: &[f64]) -> EventState {
+        EventState {
+            scales: vec![1.; self.base_rates.len()],
+            parameters: parameters.to_vec(),
+            fired: vec![false; self.events.len()],
+            active: vec![false; self.events.len()],
+        }
+    }
+
+    // The rates at time `t`
+    pub fn rates_at(&self, t: f64, pops: &[f64], st: &EventState) -> Vec<f64> {
+        self.rates_in(t, t, pops, st)
+    }
+
+    // The rates at time `t`, with piecewise rates taken from the piece
+    // containing `segment`, so that an integration step ending on a
+    // breakpoint stays on one piece
+    fn rates_in(&self, t: f64, segment: f64, pops: &[f64], st: &EventState) -> Vec<f64> {
+        let base_rates: Vec<f64> = self.base_rates.iter().zip(self.rate_parameters.iter())
+            .map(|(r, p)| p.and_then(|p| st.parameters.get(p).cloned()).unwrap_or(*r))
+            .collect();
+        let env = [pops, &base_rates[..], &st.parameters[..], &[t]];
+        self.multipliers.iter().enumerate()
+            .map(|(i, m)| {
+                let factor = match m {
+                    Multiplier::Constant => 1.,
+                    Multiplier::Piecewise(b) => b.iter().rev().find(|(s, _)| *s <= segment).map(|(_, m)| *m).unwrap_or(1.),
+                    Multiplier::Expression(e) => e(&env),
+                };
+                base_rates[i] * st.scales[i] * factor
+            })
+            .collect()
+    }
+
+    // The earliest time after `t` at which an event is scheduled or a
+    // piecewise rate changes
+    pub fn next_stop(&self, t: f64, st: &EventState) -> Option<f64> {
+        let events = self.events.iter().zip(st.fired.iter())
+            .filter_map(|(e, fired)| match &e.when {
+                When::At(s) if !*fired && *s > t => Some(*s),
+                _otherwise => None,
+            });
+        let breakpoints = self.multipliers.iter()
+            .flat_map(|m| match m {
+                Multiplier::Piecewise(b) => b.iter().map(|(s, _)| *s).filter(|s| *s > t).collect(),
+                _otherwise => vec![],
+            });
+        events.chain(breakpoints).fold(None, |a: Option<f64>, s| Some(a.map_or(s, |a| a.min(s))))
+    }
+
+    // Whether rates or conditions can change while the populations stay put
+    pub fn varies_in_time(&self) -> bool {
+        self.multipliers.iter().any(|m| matches!(m, Multiplier::Expression(_)))
+            || self.events.iter().any(|e| matches!(e.when, When::Condition(_)))
+    }
+
+    fn condition(&self, k: usize, t: f64, pops: &[f64], st: &EventState) -> bool {
+        match &self.events[k].when {
+            When::Condition(c) => {
+                let rates = self.rates_at(t, pops, st);
+                c(&[pops, &rates, &st.parameters, &[t]]) != 0.
+            }
+            When::At(_) => false,
+        }
+    }
+
+    // Whether some condition that was false when last polled is true now
+    pub fn crossing(&self, t: f64, pops: &[f64], st: &EventState) -> bool {
+        (0..self.events.len()).any(|k| !st.active[k] && self.condition(k, t, pops, st))
+    }
+
+    // Fires the events due at time `t`, updating the populations in place
+    pub fn poll(&self, t: f64, pops: &mut [f64], st: &mut EventState) {
+        for k in 0..self.events.len() {
+            let due = match self.events[k].when {
+                When::At(s) => !st.fired[k] && s <= t,
+                When::Condition(_) => {
+                    let now = self.condition(k, t, pops, st);
+                    let due = now && !st.active[k];
+                    st.active[k] = now;
+                    due
+                }
+            };
+            if due {
+                st.fired[k] = true;
+                for e in self.events[k].effects.iter() {
+                    match e {
+                        Effect::ScaleRate(i, f) => { st.scales[*i] *= f; }
+                        Effect::ScaleParameter(i, f) => { st.parameters[*i] *= f; }
+                        Effect::AddTokens(i, a) => { pops[*i] = (pops[*i] + a).max(0.); }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Runge-Kutta on the same grid as `rk_solve`, stopping at scheduled events
+// and rate breakpoints, and locating condition events by bisection. Delayed
+// transitions are solved by the method of steps as in `dde_solve`, with the
+// firing rates recorded after every step.
+pub fn solve_with_events(petri: &PetriData, dynamics: &Dynamics, init_vals: &V, t1: f32, steps: usize) -> YVals {
+    let dt = t1 / (steps as f32);
+    let mut yvals = Array::zeros((steps, init_vals.len()));
+    let mut st = dynamics.initial_state(petri.parameters());
+    let mut y = init_vals.clone();
+    let mut t = 0f32;
+    let mut history = History::new();
+    poll(dynamics, t, &mut y, &mut st);
+    record(petri, dynamics, t, &y, &st, &mut history);
+    for (i, mut cell) in yvals.axis_iter_mut(Axis(0)).enumerate() {
+        let t_end = (i + 1) as f32 * dt;
+        while t < t_end {
+            let h_end = dynamics.next_stop(t as f64, &st)
+                .map_or(t_end, |s| t_end.min(s as f32).max(t));
+            let h_end = dde_step_end(petri.delays(), 0., t, h_end);
+            let f = |s: f32, v: &V| {
+                let pops: Vec<f64> = v.iter().map(|x| *x as f64).collect();
+                let rates = dynamics.rates_in(s as f64, t as f64, &pops, &st);
+                petri.delayed_eq_with(s as f64, t as f64, &rates, &st.parameters, v, &history)
+            };
+            let crosses = |s: f32, v: &V| {
+                let pops: Vec<f64> = v.iter().map(|x| *x as f64).collect();
+                dynamics.crossing(s as f64, &pops, &st)
+            };
+            let y_end = rk_step(f, t, h_end - t, &y);
+            if crosses(h_end, &y_end) {
+                let (mut lo, mut hi) = (t, h_end);
+                for _ in 0..30 {
+                    let mid = 0.5 * (lo + hi);
+                    if crosses(mid, &rk_step(f, t, mid - t, &y)) {
+                        hi = mid;
+                    } else {
+                        lo = mid;
+                    }
+                }
+                y = rk_step(f, t, hi - t, &y);
+                t = hi;
+            } else {
+                y = y_end;
+                t = if h_end >= t_end { t_end } else { h_end };
+            }
+            poll(dynamics, t, &mut y, &mut st);
+            record(petri, dynamics, t, &y, &st, &mut history);
+        }
+        cell.assign(&y);
+    }
+    yvals
+}
+
+// Records the firing rates at time `t` for the delayed outputs
+fn record(petri: &PetriData, dynamics: &Dynamics, t: f32, y: &V, st: &EventState, history: &mut History) {
+    if petri.min_delay().is_some() {
+        let pops: Vec<f64> = y.iter().map(|x| *x as f64).collect();
+        let rates = dynamics.rates_at(t as f64, &pops, st);
+        history.push(t, petri.flows(t as f64, &rates, &st.parameters, y));
+    }
+}
+
+fn poll(dynamics: &Dynamics, t: f32, y: &mut V, st: &mut EventState) {
+    let mut pops: Vec<f64> = y.iter().map(|x| *x as f64).collect();
+    dynamics.poll(t as f64, &mut pops, st);
+    for (yi, p) in y.iter_mut().zip(pops.iter()) {
+        *yi = *p as f32;
+    }
+}
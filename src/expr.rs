@@ -0,0 +1,468 @@
+use std::fmt;
+
+// A small arithmetic expression language for propensities and other
+// user-supplied quantities, e.g. `k1 * S * I / (S + I + R)`. Comparisons
+// evaluate to 1 for true and 0 for false, e.g. `I > 100`.
+//
+// Expressions are parsed once and compiled to a closure. Variables are
+// resolved at compile time to a (table, index) pair, and the compiled closure
+// is evaluated against a slice of tables, so callers decide what the tables
+// hold (species populations, rates, ...).
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    // Zero-based character offset into the source
+    pub pos: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.pos + 1, self.message)
+    }
+}
+
+fn error<T>(pos: usize, message: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError { pos, message: message.into() })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    // Variable name and its position in the source
+    Var(String, usize),
+    Neg(Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+    // Function name, position and arguments
+    Call(String, usize, Vec<Expr>),
+}
+
+pub type Compiled = Box<dyn Fn(&[&[f64]]) -> f64>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Ident(String),
+    Op(char),
+    Cmp(BinOp),
+}
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let s: String = chars[start..i].iter().collect();
+            match s.parse() {
+                Ok(x) => tokens.push((start, Token::Num(x))),
+                Err(_) => return error(start, format!("invalid number {}", s)),
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((start, Token::Ident(chars[start..i].iter().collect())));
+        } else if "+-*/^(),".contains(c) {
+            tokens.push((i, Token::Op(c)));
+            i += 1;
+        } else if "<>=!".contains(c) {
+            let eq = chars.get(i + 1) == Some(&'=');
+            let op = match (c, eq) {
+                ('<', false) => BinOp::Lt,
+                ('>', false) => BinOp::Gt,
+                ('<', true) => BinOp::Le,
+                ('>', true) => BinOp::Ge,
+                ('=', true) => BinOp::Eq,
+                ('!', true) => BinOp::Ne,
+                _otherwise => return error(i, format!("unexpected character {}", c)),
+            };
+            tokens.push((i, Token::Cmp(op)));
+            i += if eq { 2 } else { 1 };
+        } else {
+            return error(i, format!("unexpected character {}", c));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.1)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map(|t| t.0).unwrap_or(self.end)
+    }
+
+    fn eat(&mut self, op: char) -> bool {
+        if self.peek() == Some(&Token::Op(op)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, op: char) -> Result<(), ParseError> {
+        if self.eat(op) {
+            Ok(())
+        } else {
+            error(self.offset(), format!("expected {}", op))
+        }
+    }
+
+    // comparison := sum (cmp sum)?
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let e = self.sum()?;
+        match self.peek() {
+            Some(Token::Cmp(op)) => {
+                let op = *op;
+                self.pos += 1;
+                Ok(Expr::Bin(op, Box::new(e), Box::new(self.sum()?)))
+            }
+            _otherwise => Ok(e),
+        }
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<Expr, ParseError> {
+        let mut e = self.product()?;
+        loop {
+            let op = if self.eat('+') {
+                BinOp::Add
+            } else if self.eat('-') {
+                BinOp::Sub
+            } else {
+                return Ok(e);
+            };
+            e = Expr::Bin(op, Box::new(e), Box::new(self.product()?));
+        }
+    }
+
+    // product := unary (('*' | '/') unary)*
+    fn product(&mut self) -> Result<Expr, ParseError> {
+        let mut e = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                BinOp::Mul
+            } else if self.eat('/') {
+                BinOp::Div
+            } else {
+                return Ok(e);
+            };
+            e = Expr::Bin(op, Box::new(e), Box::new(self.unary()?));
+        }
+    }
+
+    // unary := '-' unary | power
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat('-') {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else {
+            self.power()
+        }
+    }
+
+    // power := atom ('^' unary)?
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            Ok(Expr::Bin(BinOp::Pow, Box::new(base), Box::new(self.unary()?)))
+        } else {
+            Ok(base)
+        }
+    }
+
+    // atom := number | ident | ident '(' args ')' | '(' comparison ')'
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        let offset = self.offset();
+        match self.peek().cloned() {
+            Some(Token::Num(x)) => {
+                self.pos += 1;
+                Ok(Expr::Num(x))
+            }
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                if self.eat('(') {
+                    let mut args = vec![];
+                    if !self.eat(')') {
+                        args.push(self.comparison()?);
+                        while self.eat(',') {
+                            args.push(self.comparison()?);
+                        }
+                        self.expect(')')?;
+                    }
+                    Ok(Expr::Call(name, offset, args))
+                } else {
+                    Ok(Expr::Var(name, offset))
+                }
+            }
+            Some(Token::Op('(')) => {
+                self.pos += 1;
+                let e = self.comparison()?;
+                self.expect(')')?;
+                Ok(e)
+            }
+            Some(Token::Op(c)) => error(offset, format!("unexpected {}", c)),
+            Some(Token::Cmp(_)) => error(offset, "unexpected comparison"),
+            None => error(offset, "unexpected end of expression"),
+        }
+    }
+}
+
+fn truth(b: bool) -> f64 {
+    if b { 1. } else { 0. }
+}
+
+type Function = fn(&[f64]) -> f64;
+
+// Built in functions and their arities
+fn function(name: &str) -> Option<(usize, Function)> {
+    Some(match name {
+        "exp" => (1, |a| a[0].exp()),
+        "log" => (1, |a| a[0].ln()),
+        "sqrt" => (1, |a| a[0].sqrt()),
+        "abs" => (1, |a| a[0].abs()),
+        "min" => (2, |a| a[0].min(a[1])),
+        "max" => (2, |a| a[0].max(a[1])),
+        _ => return None,
+    })
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Expr, ParseError> {
+        let mut p = Parser { tokens: tokenize(src)?, pos: 0, end: src.chars().count() };
+        let e = p.comparison()?;
+        match p.peek() {
+            None => Ok(e),
+            Some(_) => error(p.offset(), "unexpected input after expression"),
+        }
+    }
+
+    // Names of the variables used by the expression, in order of appearance
+    pub fn variables(&self) -> Vec<String> {
+        let mut out = vec![];
+        self.collect_variables(&mut out);
+        out
+    }
+
+    fn collect_variables(&self, out: &mut Vec<String>) {
+        match self {
+            Expr::Num(_) => { }
+            Expr::Var(v, _) => {
+                if !out.contains(v) {
+                    out.push(v.clone());
+                }
+            }
+            Expr::Neg(e) => e.collect_variables(out),
+            Expr::Bin(_, a, b) => {
+                a.collect_variables(out);
+                b.collect_variables(out);
+            }
+            Expr::Call(_, _, args) => {
+                for a in args.iter() {
+                    a.collect_variables(out);
+                }
+            }
+        }
+    }
+
+    // The expression with each variable for which `f` gives an expression
+    // replaced by it
+    pub fn substitute(&self, f: &dyn Fn(&str) -> Option<Expr>) -> Expr {
+        match self {
+            Expr::Num(x) => Expr::Num(*x),
+            Expr::Var(v, pos) => f(v).unwrap_or_else(|| Expr::Var(v.clone(), *pos)),
+            Expr::Neg(a) => Expr::Neg(Box::new(a.substitute(f))),
+            Expr::Bin(op, a, b) => Expr::Bin(*op, Box::new(a.substitute(f)), Box::new(b.substitute(f))),
+            Expr::Call(name, pos, args) => Expr::Call(name.clone(), *pos, args.iter().map(|a| a.substitute(f)).collect()),
+        }
+    }
+
+    // `resolve` maps a variable name to its (table, index)
+    pub fn compile(&self, resolve: &dyn Fn(&str) -> Option<(usize, usize)>) -> Result<Compiled, ParseError> {
+        Ok(match self {
+            Expr::Num(x) => {
+                let x = *x;
+                Box::new(move |_| x)
+            }
+            Expr::Var(v, pos) => match resolve(v) {
+                Some((table, i)) => Box::new(move |env| env[table][i]),
+                None => return error(*pos, format!("unknown variable {}", v)),
+            },
+            Expr::Neg(e) => {
+                let e = e.compile(resolve)?;
+                Box::new(move |env| -e(env))
+            }
+            Expr::Bin(op, a, b) => {
+                let (a, b) = (a.compile(resolve)?, b.compile(resolve)?);
+                match op {
+                    BinOp::Add => Box::new(move |env| a(env) + b(env)),
+                    BinOp::Sub => Box::new(move |env| a(env) - b(env)),
+                    BinOp::Mul => Box::new(move |env| a(env) * b(env)),
+                    BinOp::Div => Box::new(move |env| a(env) / b(env)),
+                    BinOp::Pow => Box::new(move |env| a(env).powf(b(env))),
+                    BinOp::Lt => Box::new(move |env| truth(a(env) < b(env))),
+                    BinOp::Gt => Box::new(move |env| truth(a(env) > b(env))),
+                    BinOp::Le => Box::new(move |env| truth(a(env) <= b(env))),
+                    BinOp::Ge => Box::new(move |env| truth(a(env) >= b(env))),
+                    BinOp::Eq => Box::new(move |env| truth(a(env) == b(env))),
+                    BinOp::Ne => Box::new(move |env| truth(a(env) != b(env))),
+                }
+            }
+            Expr::Call(name, pos, args) => {
+                let (arity, f) = match function(name) {
+                    Some(f) => f,
+                    None => return error(*pos, format!("unknown function {}", name)),
+                };
+                if args.len() != arity {
+                    return error(*pos, format!("{} takes {} argument(s)", name, arity));
+                }
+                let args = args.iter().map(|a| a.compile(resolve)).collect::<Result<Vec<_>, _>>()?;
+                Box::new(move |env| {
+                    let vals: Vec<f64> = args.iter().map(|a| a(env)).collect();
+                    f(&vals)
+                })
+            }
+        })
+    }
+}
+
+impl Expr {
+    // Binding strength, from comparisons (0) to atoms (5), as in `Parser`
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Num(x) if *x < 0. => 3,
+            Expr::Num(_) | Expr::Var(..) | Expr::Call(..) => 5,
+            Expr::Neg(_) => 3,
+            Expr::Bin(BinOp::Add, ..) | Expr::Bin(BinOp::Sub, ..) => 1,
+            Expr::Bin(BinOp::Mul, ..) | Expr::Bin(BinOp::Div, ..) => 2,
+            Expr::Bin(BinOp::Pow, ..) => 4,
+            Expr::Bin(..) => 0,
+        }
+    }
+
+    fn fmt_at(&self, f: &mut fmt::Formatter<'_>, min: u8) -> fmt::Result {
+        if self.precedence() < min {
+            write!(f, "(")?;
+            self.fmt_at(f, 0)?;
+            return write!(f, ")");
+        }
+        match self {
+            Expr::Num(x) => write!(f, "{}", x),
+            Expr::Var(v, _) => write!(f, "{}", v),
+            Expr::Neg(e) => {
+                write!(f, "-")?;
+                e.fmt_at(f, 3)
+            }
+            Expr::Bin(op, a, b) => {
+                let (left, right, symbol) = match op {
+                    BinOp::Add => (1, 2, "+"),
+                    BinOp::Sub => (1, 2, "-"),
+                    BinOp::Mul => (2, 3, "*"),
+                    BinOp::Div => (2, 3, "/"),
+                    BinOp::Pow => (5, 3, "^"),
+                    BinOp::Lt => (1, 1, "<"),
+                    BinOp::Gt => (1, 1, ">"),
+                    BinOp::Le => (1, 1, "<="),
+                    BinOp::Ge => (1, 1, ">="),
+                    BinOp::Eq => (1, 1, "=="),
+                    BinOp::Ne => (1, 1, "!="),
+                };
+                a.fmt_at(f, left)?;
+                if *op == BinOp::Pow {
+                    write!(f, "{}", symbol)?;
+                } else {
+                    write!(f, " {} ", symbol)?;
+                }
+                b.fmt_at(f, right)
+            }
+            Expr::Call(name, _, args) => {
+                write!(f, "{}(", name)?;
+                for (i, a) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    a.fmt_at(f, 0)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+// Prints the expression in the syntax accepted by `Expr::parse`, with only
+// the parentheses needed
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_at(f, 0)
+    }
+}
+
+// Renames the variables of `src` for which `rename` gives a new name, leaving
+// the rest of the text as written
+pub fn rename_variables(src: &str, rename: &dyn Fn(&str) -> Option<String>) -> Result<String, ParseError> {
+    let tokens = tokenize(src)?;
+    let chars: Vec<char> = src.chars().collect();
+    let mut out = String::new();
+    let mut last = 0;
+    for (k, (pos, token)) in tokens.iter().enumerate() {
+        let call = tokens.get(k + 1).map(|t| &t.1) == Some(&Token::Op('('));
+        if let (Token::Ident(name), false) = (token, call) {
+            if let Some(new) = rename(name) {
+                out.extend(chars[last..*pos].iter());
+                out.push_str(&new);
+                last = pos + name.chars().count();
+            }
+        }
+    }
+    out.extend(chars[last..].iter());
+    Ok(out)
+}
+
+// Parses and compiles in one step
+pub fn compile(src: &str, resolve: &dyn Fn(&str) -> Option<(usize, usize)>) -> Result<Compiled, ParseError> {
+    Expr::parse(src)?.compile(resolve)
+}
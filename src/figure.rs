@@ -0,0 +1,98 @@
+use plotters::prelude::*;
+use plotters::coord::Shift;
+#[cfg(not(target_arch = "wasm32"))]
+use plotters::drawing::backend::{BackendCoord, DrawingErrorKind};
+#[cfg(not(target_arch = "wasm32"))]
+use plotters::style::RGBAColor;
+use crate::petri::*;
+use crate::patches::*;
+use crate::stages::*;
+
+// The trajectory plot of a net, as drawn in the editor, for any plotters
+// backend: the canvas in the browser, SVG documents, and PNG files in native
+// builds.
+
+// The size of the canvas in the editor
+pub static FIGURE_SIZE: (u32, u32) = (500, 400);
+
+pub struct Figure<'a> {
+    pub petri: &'a PetriNet,
+    pub controls: &'a PlotControls,
+    // Set when `petri` is an expanded metapopulation model
+    pub patches: Option<&'a Metapopulation>,
+    // Set when `petri` has k-stage transitions expanded, which are plotted together
+    pub stages: Option<&'a Staged>,
+}
+
+impl<'a> Figure<'a> {
+    pub fn new(petri: &'a PetriNet, controls: &'a PlotControls) -> Self {
+        Figure { petri, controls, patches: None, stages: None }
+    }
+
+    pub fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> DrawResult<(), DB> {
+        match (self.patches, self.stages) {
+            (Some(m), _) if m.view != PatchView::Each => m.draw(self.petri, self.controls, root),
+            (_, Some(s)) => s.draw(self.controls, root),
+            _otherwise => self.petri.draw(self.controls, root),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn plot(&self, canvas_id: &str) -> DrawResult<(), CanvasBackend> {
+        let root = CanvasBackend::new(canvas_id).expect("cannot find canvas").into_drawing_area();
+        self.draw(&root)?;
+        root.present()?;
+        Ok(())
+    }
+
+    // An SVG document of the given size in pixels
+    pub fn to_svg(&self, size: (u32, u32)) -> Result<String, String> {
+        let mut svg = String::new();
+        {
+            let root = SVGBackend::with_string(&mut svg, size).into_drawing_area();
+            self.draw(&root).map_err(|e| e.to_string())?;
+            root.present().map_err(|e| e.to_string())?;
+        }
+        Ok(svg)
+    }
+
+    // Writes a PNG file of the given size in pixels
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_png<P: AsRef<std::path::Path> + ?Sized>(&self, path: &P, size: (u32, u32)) -> Result<(), String> {
+        let root = Pixels(BitMapBackend::new(path, size)).into_drawing_area();
+        self.draw(&root).map_err(|e| e.to_string())?;
+        root.present().map_err(|e| e.to_string())
+    }
+}
+
+// The bitmap backend of plotters 0.2 fills rows of pixels through unaligned
+// u64 pointers, which panics in debug builds, so only its drawing of single
+// pixels is used and lines, shapes and text are rasterized by the defaults
+// of `DrawingBackend`
+#[cfg(not(target_arch = "wasm32"))]
+struct Pixels<'a>(BitMapBackend<'a>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> DrawingBackend for Pixels<'a> {
+    type ErrorType = <BitMapBackend<'a> as DrawingBackend>::ErrorType;
+
+    fn get_size(&self) -> (u32, u32) {
+        self.0.get_size()
+    }
+
+    fn ensure_prepared(&mut self) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        self.0.ensure_prepared()
+    }
+
+    fn present(&mut self) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        self.0.present()
+    }
+
+    fn draw_pixel(&mut self, point: BackendCoord, color: &RGBAColor) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        let (w, h) = self.get_size();
+        if point.0 >= w as i32 || point.1 >= h as i32 {
+            return Ok(());
+        }
+        self.0.draw_pixel(point, color)
+    }
+}
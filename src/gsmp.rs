@@ -0,0 +1,190 @@
+use rand::Rng;
+use rand::distributions::Distribution;
+use serde::{Serialize, Deserialize};
+use statrs::distribution::{Exponential, Categorical, Erlang, Weibull, LogNormal};
+use ndarray::prelude::*;
+use crate::math::*;
+use crate::petri::*;
+use crate::events::*;
+use crate::ssa::PetriState;
+
+// Generalized semi-Markov simulation, where transitions may have firing times
+// that are not exponential. Each such transition has a clock, sampled when it
+// becomes enabled, and fires when its clock runs out unless another transition
+// wins the race first. Exponential transitions race as in the SSA.
+
+// Distribution of the delay between a transition becoming enabled and firing
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub enum Timing {
+    // Memoryless, with the propensity of the transition as its rate
+    #[default]
+    Exponential,
+    Deterministic(f32),
+    // The sum of `k` exponential stages, each with the given rate
+    Erlang { k: u32, rate: f32 },
+    Weibull { shape: f32, scale: f32 },
+    // The log of the delay is normal with mean `location` and deviation `scale`
+    LogNormal { location: f32, scale: f32 },
+}
+
+pub static TIMINGS: [&str; 5] = ["Exponential", "Deterministic", "Erlang", "Weibull", "Log-normal"];
+
+impl Timing {
+    pub fn to_idx(&self) -> usize {
+        match self {
+            Timing::Exponential => 0,
+            Timing::Deterministic(_) => 1,
+            Timing::Erlang { .. } => 2,
+            Timing::Weibull { .. } => 3,
+            Timing::LogNormal { .. } => 4,
+        }
+    }
+
+    pub fn from_idx(i: usize) -> Self {
+        match i {
+            1 => Timing::Deterministic(1.0),
+            2 => Timing::Erlang { k: 2, rate: 1.0 },
+            3 => Timing::Weibull { shape: 1.0, scale: 1.0 },
+            4 => Timing::LogNormal { location: 0.0, scale: 1.0 },
+            _ => Timing::Exponential,
+        }
+    }
+
+    // A sampled delay. None for exponential timing, which has no clock, and
+    // for invalid parameters, in which case the transition never fires.
+    pub fn delay<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<f64> {
+        match self {
+            Timing::Exponential => None,
+            Timing::Deterministic(d) if *d >= 0. => Some(*d as f64),
+            Timing::Deterministic(_) => None,
+            Timing::Erlang { k, rate } => Erlang::new(*k as u64, *rate as f64).ok().map(|d| d.sample(rng)),
+            Timing::Weibull { shape, scale } => Weibull::new(*shape as f64, *scale as f64).ok().map(|d| d.sample(rng)),
+            Timing::LogNormal { location, scale } => LogNormal::new(*location as f64, *scale as f64).ok().map(|d| d.sample(rng)),
+        }
+    }
+}
+
+// What happens to the clock of a transition with non-exponential timing when
+// it loses a race
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Policy {
+    // The clock keeps running while the transition stays enabled, and is
+    // discarded when the transition is disabled
+    #[default]
+    EnablingMemory,
+    // The clock is paused while the transition is disabled, and resumes when
+    // it is enabled again
+    AgeMemory,
+    // The clock is discarded whenever any transition fires
+    Resampling,
+}
+
+pub static POLICIES: [&str; 3] = ["Enabling Memory", "Age Memory", "Resampling"];
+
+impl Policy {
+    pub fn to_idx(self) -> usize {
+        match self {
+            Policy::EnablingMemory => 0,
+            Policy::AgeMemory => 1,
+            Policy::Resampling => 2,
+        }
+    }
+
+    pub fn from_idx(i: usize) -> Self {
+        match i {
+            1 => Policy::AgeMemory,
+            2 => Policy::Resampling,
+            _ => Policy::EnablingMemory,
+        }
+    }
+}
+
+// A trajectory on [0, t1] sampled on the same grid as `ssa::simulate`.
+// Transitions with non-exponential timing are enabled when `Transition::enabled`
+// holds and their rate is positive, so events can switch them off by scaling
+// the rate to zero; otherwise the rate does not affect their timing.
+pub fn simulate<R: Rng + ?Sized>(petri: &PetriNet, dynamics: &Dynamics, initial_state: &Array<i32,Ix1>,
+                                 t1: f64, steps: usize, rng: &mut R) -> YVals {
+    let data = petri.get_petri_data();
+    let n = petri.transitions.len();
+    let timed: Vec<bool> = petri.transitions.iter().map(|t| t.timing != Timing::Exponential).collect();
+    // Absolute firing times of the running clocks, and the time left on paused ones
+    let mut fire_at: Vec<Option<f64>> = vec![None; n];
+    let mut remaining: Vec<Option<f64>> = vec![None; n];
+    let mut yvals = Array::zeros((steps, initial_state.len()));
+    let mut st = dynamics.initial_state(data.parameters());
+    let mut ps = PetriState::new(initial_state.clone());
+    ps.poll(dynamics, &mut st);
+    for (i, mut cell) in yvals.axis_iter_mut(Axis(0)).enumerate() {
+        let t_out = (i + 1) as f64 / (steps as f64) * t1;
+        while ps.time_elapsed < t_out {
+            let t = ps.time_elapsed;
+            let rates = dynamics.rates_at(t, &ps.pops(), &st);
+            let marking = ps.state.to_vec();
+            for (k, trans) in petri.transitions.iter().enumerate().filter(|(k, _)| timed[*k]) {
+                let enabled = rates[k] > 0. && trans.enabled(&marking);
+                match (enabled, fire_at[k]) {
+                    (true, None) => {
+                        fire_at[k] = remaining[k].take().or_else(|| trans.timing.delay(rng)).map(|d| t + d);
+                    }
+                    (false, Some(s)) => {
+                        if trans.policy == Policy::AgeMemory {
+                            remaining[k] = Some(s - t);
+                        }
+                        fire_at[k] = None;
+                    }
+                    _otherwise => { }
+                }
+            }
+
+            let flow_out: Array1<f64> = (0..n)
+                .map(|k| if timed[k] { 0. } else { ps.transition_rate(&data, &rates, &st.parameters, k) })
+                .collect();
+            let total_flow_out = flow_out.sum();
+            let dt = if total_flow_out > 0. {
+                Exponential::new(total_flow_out).unwrap().sample(rng)
+            } else {
+                f64::INFINITY
+            };
+            let next_clock = fire_at.iter().enumerate()
+                .filter_map(|(k, s)| s.map(|s| (k, s)))
+                .fold(None, |best: Option<(usize, f64)>, x| match best {
+                    Some(b) if b.1 <= x.1 => Some(b),
+                    _otherwise => Some(x),
+                });
+            let stop = dynamics.next_stop(t, &st).into_iter().chain(ps.next_delivery())
+                .fold(t_out, f64::min);
+            let fired = match next_clock {
+                Some((k, s)) if s <= t + dt && s < stop => {
+                    ps.time_elapsed = s;
+                    Some(k)
+                }
+                _ if t + dt < stop => {
+                    ps.time_elapsed = t + dt;
+                    let probabilities = flow_out / total_flow_out;
+                    let k: f64 = Categorical::new(probabilities.as_slice().unwrap()).unwrap().sample(rng);
+                    Some(k as usize)
+                }
+                _otherwise => {
+                    ps.time_elapsed = stop;
+                    None
+                }
+            };
+            if let Some(k) = fired {
+                ps.apply_transition(&data, k);
+                fire_at[k] = None;
+                remaining[k] = None;
+                for (j, trans) in petri.transitions.iter().enumerate() {
+                    if trans.policy == Policy::Resampling {
+                        fire_at[j] = None;
+                        remaining[j] = None;
+                    }
+                }
+            }
+            ps.deliver(&data);
+            ps.poll(dynamics, &mut st);
+        }
+        cell.assign(&ps.state.mapv(|x| x as f32));
+    }
+    yvals
+}
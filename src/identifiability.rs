@@ -0,0 +1,364 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use rand::Rng;
+use ndarray::prelude::*;
+use crate::petri::*;
+use crate::math::*;
+use crate::kinetics::*;
+
+// Structural identifiability of the transition rates by the Taylor series
+// approach: the coefficients of the Taylor expansion of each observed species
+// at t = 0 are polynomials in the rates, and the rates are identifiable exactly
+// when they are determined by those coefficients.
+
+// Sparse polynomial with monomials stored as exponent vectors
+#[derive(Clone, Debug, PartialEq)]
+struct Poly {
+    nvars: usize,
+    terms: BTreeMap<Vec<u32>, f64>,
+}
+
+const EPS: f64 = 1e-9;
+
+// Largest Taylor coefficient, in terms, expanded before giving up. The
+// coefficients grow exponentially with the order, which is the number of
+// rates and species.
+pub static MAX_TERMS: usize = 20000;
+
+impl Poly {
+    fn zero(nvars: usize) -> Self {
+        Poly { nvars, terms: BTreeMap::new() }
+    }
+
+    fn var(nvars: usize, i: usize) -> Self {
+        let mut e = vec![0; nvars];
+        e[i] = 1;
+        let mut p = Self::zero(nvars);
+        p.add_term(e, 1.);
+        p
+    }
+
+    fn add_term(&mut self, e: Vec<u32>, c: f64) {
+        let entry = self.terms.entry(e).or_insert(0.);
+        *entry += c;
+    }
+
+    fn prune(mut self) -> Self {
+        self.terms.retain(|_, c| c.abs() > EPS);
+        self
+    }
+
+    fn add(&self, other: &Poly) -> Poly {
+        let mut p = self.clone();
+        for (e, c) in other.terms.iter() {
+            p.add_term(e.clone(), *c);
+        }
+        p.prune()
+    }
+
+    fn mul(&self, other: &Poly) -> Poly {
+        let mut p = Self::zero(self.nvars);
+        for (e1, c1) in self.terms.iter() {
+            for (e2, c2) in other.terms.iter() {
+                let e = e1.iter().zip(e2.iter()).map(|(a, b)| a + b).collect();
+                p.add_term(e, c1 * c2);
+            }
+        }
+        p.prune()
+    }
+
+    fn derivative(&self, i: usize) -> Poly {
+        let mut p = Self::zero(self.nvars);
+        for (e, c) in self.terms.iter() {
+            if e[i] > 0 {
+                let mut e = e.clone();
+                let k = e[i];
+                e[i] -= 1;
+                p.add_term(e, c * k as f64);
+            }
+        }
+        p
+    }
+
+    // Substitute values for the variables `vals[i] = Some(x)`
+    fn substitute(&self, vals: &[Option<f64>]) -> Poly {
+        let mut p = Self::zero(self.nvars);
+        for (e, c) in self.terms.iter() {
+            let mut e = e.clone();
+            let mut c = *c;
+            for (i, v) in vals.iter().enumerate() {
+                if let Some(x) = v {
+                    c *= x.powi(e[i] as i32);
+                    e[i] = 0;
+                }
+            }
+            p.add_term(e, c);
+        }
+        p.prune()
+    }
+
+    fn eval(&self, vals: &[f64]) -> f64 {
+        self.terms.iter()
+            .map(|(e, c)| c * e.iter().zip(vals.iter()).map(|(k, x)| x.powi(*k as i32)).product::<f64>())
+            .sum()
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        (0..self.nvars)
+            .filter(|i| self.terms.keys().any(|e| e[*i] > 0))
+            .collect()
+    }
+
+    fn is_linear(&self) -> bool {
+        self.terms.keys().all(|e| e.iter().sum::<u32>() <= 1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Identifiable {
+    Globally,
+    Locally,
+    Not,
+}
+
+impl fmt::Display for Identifiable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Identifiable::Globally => "globally identifiable",
+            Identifiable::Locally => "locally identifiable",
+            Identifiable::Not => "not identifiable",
+        })
+    }
+}
+
+pub struct IdentifiabilityReport {
+    pub parameters: Vec<(String, Identifiable)>,
+    // Identifiable combinations of the non-identifiable rates
+    pub combinations: Vec<String>,
+}
+
+impl fmt::Display for IdentifiabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, status) in self.parameters.iter() {
+            writeln!(f, "{}: {}", name, status)?;
+        }
+        for c in self.combinations.iter() {
+            writeln!(f, "identifiable combination: {}", c)?;
+        }
+        Ok(())
+    }
+}
+
+// The right hand side of `PetriData::master_eq` as polynomials in the variables
+// (rates..., species...)
+fn vector_field(petri: &PetriData) -> Vec<Poly> {
+    let d = petri.data();
+    let m = d.len_of(Axis(0));
+    let n = petri.num_species();
+    let nvars = m + n;
+    let mut f = vec![Poly::zero(nvars); n];
+    for (i, trans) in d.axis_iter(Axis(0)).enumerate() {
+        let mut e = vec![0; nvars];
+        e[i] = 1;
+        for k in 0..n {
+            e[m + k] = (trans[(0, k)] + trans[(2, k)]) as u32;
+        }
+        for (j, fj) in f.iter_mut().enumerate() {
+            let coeff = (trans[(1, j)] - trans[(0, j)]) as f64;
+            if coeff != 0. {
+                fj.add_term(e.clone(), coeff);
+            }
+        }
+    }
+    f
+}
+
+fn lie_derivative(g: &Poly, f: &[Poly], m: usize) -> Poly {
+    let mut out = Poly::zero(g.nvars);
+    for (s, fs) in f.iter().enumerate() {
+        let dg = g.derivative(m + s);
+        if !dg.terms.is_empty() {
+            out = out.add(&dg.mul(fs));
+        }
+    }
+    out
+}
+
+// Row reduces in place, returning the pivot columns
+fn row_reduce(a: &mut Array2<f64>) -> Vec<usize> {
+    let (rows, cols) = a.dim();
+    let scale = a.iter().fold(0f64, |x, y| x.max(y.abs())).max(1.);
+    let tol = 1e-8 * scale;
+    let mut pivots = vec![];
+    let mut r = 0;
+    for c in 0..cols {
+        if r == rows {
+            break;
+        }
+        let (p, max) = (r..rows)
+            .map(|i| (i, a[(i, c)].abs()))
+            .fold((r, 0.), |best, x| if x.1 > best.1 { x } else { best });
+        if max <= tol {
+            continue;
+        }
+        for k in 0..cols {
+            a.swap((r, k), (p, k));
+        }
+        let pivot = a[(r, c)];
+        for k in 0..cols {
+            a[(r, k)] /= pivot;
+        }
+        for i in 0..rows {
+            if i != r {
+                let factor = a[(i, c)];
+                for k in 0..cols {
+                    a[(i, k)] -= factor * a[(r, k)];
+                }
+            }
+        }
+        pivots.push(c);
+        r += 1;
+    }
+    pivots
+}
+
+fn format_linear(p: &Poly, names: &[String]) -> String {
+    let mut terms: Vec<(usize, f64)> = p.terms.iter()
+        .filter_map(|(e, c)| e.iter().position(|k| *k == 1).map(|i| (i, *c)))
+        .collect();
+    terms.sort_by_key(|t| t.0);
+    let lead = terms.first().map(|t| t.1).unwrap_or(1.);
+    let mut out = String::new();
+    for (j, (i, c)) in terms.iter().enumerate() {
+        let c = c / lead;
+        if j > 0 {
+            out.push_str(if c < 0. { " - " } else { " + " });
+        } else if c < 0. {
+            out.push('-');
+        }
+        if (c.abs() - 1.).abs() > EPS {
+            out.push_str(&format!("{}*", c.abs()));
+        }
+        out.push_str(&names[*i]);
+    }
+    out
+}
+
+// Only defined for mass action kinetics, where the Taylor coefficients are polynomial
+pub fn identifiability<R: Rng + ?Sized>(petri: &PetriNet, init_vals: &[f32], observed: &[usize], rng: &mut R)
+        -> Result<IdentifiabilityReport, String> {
+    if let Some(t) = petri.transitions.iter().find(|t| t.rate_law != RateLaw::MassAction) {
+        return Err(format!("transition {} uses {} kinetics, but identifiability requires mass action", t.name, t.rate_law));
+    }
+    if let Some(t) = petri.transitions.iter().find(|t| t.inhibit.iter().any(|w| *w > 0)) {
+        return Err(format!("transition {} has inhibitor arcs, which are not polynomial", t.name));
+    }
+    let pd = petri.get_petri_data();
+    let (m, n) = (petri.transitions.len(), petri.species.len());
+    let nvars = m + n;
+    let f = vector_field(&pd);
+    let y0: Vec<Option<f64>> = (0..nvars)
+        .map(|i| if i < m { None } else { init_vals.get(i - m).map(|x| *x as f64).or(Some(0.)) })
+        .collect();
+
+    // Taylor coefficients of the observed species, as polynomials in the rates
+    let order = m + n;
+    let mut coefficients = vec![];
+    for &o in observed.iter() {
+        let mut g = Poly::var(nvars, m + o);
+        for k in 0..order {
+            g = lie_derivative(&g, &f, m);
+            if g.terms.len() > MAX_TERMS {
+                return Err(format!("the Taylor coefficient of order {} of {} has more than {} terms, too many to check; \
+                                    observe more species or simplify the net", k + 1, petri.species[o], MAX_TERMS));
+            }
+            let c = g.substitute(&y0);
+            if !c.terms.is_empty() {
+                coefficients.push(c);
+            }
+        }
+    }
+
+    // Local identifiability from the rank of the Jacobian at a generic point
+    let point: Vec<f64> = (0..nvars).map(|i| if i < m { rng.gen_range(0.5, 1.5) } else { 0. }).collect();
+    let mut jacobian = Array2::zeros((coefficients.len(), m));
+    for (r, c) in coefficients.iter().enumerate() {
+        for k in 0..m {
+            jacobian[(r, k)] = c.derivative(k).eval(&point);
+        }
+    }
+    let pivots = row_reduce(&mut jacobian);
+    let locally: Vec<bool> = (0..m)
+        .map(|k| {
+            // e_k lies in the row space iff no null vector has a k component
+            (0..m).filter(|c| !pivots.contains(c)).all(|free| {
+                if k == free {
+                    return false;
+                }
+                match pivots.iter().position(|p| *p == k) {
+                    Some(r) => jacobian[(r, free)].abs() < 1e-8,
+                    None => true,
+                }
+            })
+        })
+        .collect();
+
+    // Global identifiability: rates determined by the coefficients that are
+    // linear in the remaining unknowns, repeated as rates become known. This is
+    // sufficient but not necessary, so some rates reported as locally
+    // identifiable may also be globally identifiable.
+    let mut solved: Vec<Option<f64>> = vec![None; nvars];
+    loop {
+        let linear: Vec<Poly> = coefficients.iter()
+            .map(|c| c.substitute(&solved))
+            .filter(|c| c.is_linear() && !c.variables().is_empty())
+            .collect();
+        let mut a = Array2::zeros((linear.len(), m));
+        for (r, c) in linear.iter().enumerate() {
+            for k in c.variables() {
+                a[(r, k)] = c.derivative(k).eval(&point);
+            }
+        }
+        let pivots = row_reduce(&mut a);
+        let mut progress = false;
+        for (r, &k) in pivots.iter().enumerate() {
+            let determined = (0..m)
+                .filter(|c| *c != k && solved[*c].is_none())
+                .all(|c| a[(r, c)].abs() < 1e-8);
+            if determined && solved[k].is_none() {
+                solved[k] = Some(point[k]);
+                progress = true;
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+
+    let parameters: Vec<(String, Identifiable)> = petri.transitions.iter().enumerate()
+        .map(|(k, t)| {
+            let status = if solved[k].is_some() {
+                Identifiable::Globally
+            } else if locally[k] {
+                Identifiable::Locally
+            } else {
+                Identifiable::Not
+            };
+            (t.name.clone(), status)
+        })
+        .collect();
+
+    let names: Vec<String> = petri.transitions.iter().map(|t| t.name.clone()).collect();
+    let mut combinations: Vec<String> = vec![];
+    for c in coefficients.iter() {
+        let vars = c.variables();
+        if c.is_linear() && vars.len() > 1 && vars.iter().all(|k| !locally[*k]) {
+            let s = format_linear(c, &names);
+            if !combinations.contains(&s) {
+                combinations.push(s);
+            }
+        }
+    }
+
+    Ok(IdentifiabilityReport { parameters, combinations })
+}
@@ -0,0 +1,81 @@
+use crate::petri::*;
+use crate::expr::*;
+use crate::events::*;
+use crate::gsmp::*;
+
+// What the readers and writers of other formats share: the result of an
+// import, and the warnings for the parts of a net that a format has no
+// counterpart for. `left_out` says what happens to them, e.g. "not exported".
+
+// A net read from another format, with warnings for what could not be represented
+pub struct Import {
+    pub petri: PetriNet,
+    pub controls: PlotControls,
+    pub warnings: Vec<String>,
+}
+
+impl Import {
+    pub fn new(petri: PetriNet, mut controls: PlotControls, warnings: Vec<String>) -> Self {
+        fit_initial_values(&mut controls);
+        Import { petri, controls, warnings }
+    }
+}
+
+// Makes the plot of an imported net tall enough for its initial values
+pub(crate) fn fit_initial_values(controls: &mut PlotControls) {
+    let top = controls.init_vals.iter().cloned().fold(0f32, f32::max);
+    if top > 0. {
+        controls.ymax = top * 1.1;
+    }
+}
+
+// Warnings for the schedule and firing time distribution of `t`
+pub(crate) fn timing_warnings(t: &Transition, left_out: &str) -> Vec<String> {
+    let mut warnings = vec![];
+    if t.schedule != Schedule::Constant {
+        warnings.push(format!("transition {}: the rate schedule is {}", t.name, left_out));
+    }
+    if t.timing != Timing::Exponential {
+        warnings.push(format!("transition {}: the {} firing time is {}, only the rate is used", t.name,
+                              TIMINGS[t.timing.to_idx()], left_out));
+    }
+    warnings
+}
+
+// Warnings for the output delay and the stages of transition `i`
+pub(crate) fn delay_warnings(petri: &PetriNet, i: usize, left_out: &str) -> Vec<String> {
+    let t = &petri.transitions[i];
+    let mut warnings = vec![];
+    if t.delay > 0. {
+        warnings.push(format!("transition {}: the output delay is {}", t.name, left_out));
+    }
+    if let Err(e) = petri.check_stages(i) {
+        warnings.push(format!("transition {}: {}", t.name, e));
+    } else if t.stages > 1 {
+        warnings.push(format!("transition {}: the {} stages are {}; expand the stages first", t.name, t.stages, left_out));
+    }
+    warnings
+}
+
+// Warnings for the parameters, observables and events of `petri`, for
+// formats that have none
+pub(crate) fn unexported_parts(petri: &PetriNet) -> Vec<String> {
+    let what = [(petri.parameters.len(), "parameter(s)"), (petri.observables.len(), "observable(s)"), (petri.events.len(), "event(s)")];
+    what.iter().filter(|(n, _)| *n > 0)
+        .map(|(n, what)| format!("{} {} are not exported", n, what))
+        .collect()
+}
+
+// `e` with the variables assigned by `rules` replaced by their expressions,
+// which may refer to other rules but not in a cycle
+pub(crate) fn inline_rules(e: &Expr, rules: &[(String, Expr)]) -> Result<Expr, String> {
+    let assigned = |e: &Expr| e.variables().into_iter().find(|v| rules.iter().any(|(r, _)| r == v));
+    let mut e = e.clone();
+    for _ in 0..=rules.len() {
+        if assigned(&e).is_none() {
+            return Ok(e);
+        }
+        e = e.substitute(&|v| rules.iter().find(|(r, _)| r == v).map(|(_, x)| x.clone()));
+    }
+    Err(format!("the rule for {} refers to itself", assigned(&e).unwrap()))
+}
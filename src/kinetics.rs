@@ -0,0 +1,214 @@
+use ndarray::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use crate::expr::*;
+
+// How the propensity of a transition depends on the populations of its inputs.
+// The propensity is always the transition rate times `RateLaw::term`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub enum RateLaw {
+    // Product over inputs of pop^input
+    #[default]
+    MassAction,
+    // Mass action in the other inputs, times S / (km + S) for the substrate S
+    MichaelisMenten { substrate: usize, km: f32 },
+    // Mass action in the other inputs, times S^n / (k^n + S^n) for the substrate S
+    Hill { substrate: usize, k: f32, n: f32 },
+    // Mass action divided by N^(order - 1), where N is the total population
+    FrequencyDependent,
+    // The whole propensity (not multiplied by the rate), in terms of species
+    // populations and the rates of transitions, referred to by name
+    Expression(String),
+}
+
+pub static RATE_LAWS: [&str; 5] = ["Mass Action", "Michaelis-Menten", "Hill", "Frequency Dependent", "Expression"];
+
+impl fmt::Display for RateLaw {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", RATE_LAWS[self.to_idx()])
+    }
+}
+
+impl RateLaw {
+    pub fn to_idx(&self) -> usize {
+        match self {
+            RateLaw::MassAction => 0,
+            RateLaw::MichaelisMenten { .. } => 1,
+            RateLaw::Hill { .. } => 2,
+            RateLaw::FrequencyDependent => 3,
+            RateLaw::Expression(_) => 4,
+        }
+    }
+
+    // The law with index `i` in `RATE_LAWS`, with default parameters
+    pub fn from_idx(i: usize) -> Self {
+        match i {
+            1 => RateLaw::MichaelisMenten { substrate: 0, km: 1.0 },
+            2 => RateLaw::Hill { substrate: 0, k: 1.0, n: 2.0 },
+            3 => RateLaw::FrequencyDependent,
+            4 => RateLaw::Expression("0".to_string()),
+            _ => RateLaw::MassAction,
+        }
+    }
+
+    pub fn substrate(&self) -> Option<usize> {
+        match self {
+            RateLaw::MichaelisMenten { substrate, .. } | RateLaw::Hill { substrate, .. } => Some(*substrate),
+            _otherwise => None,
+        }
+    }
+
+    // Keeps the substrate index pointing at the same species when species `i` is
+    // removed, falling back to mass action if the substrate itself is removed
+    pub fn remove_species(&mut self, i: usize) {
+        match self {
+            RateLaw::MichaelisMenten { substrate, .. } | RateLaw::Hill { substrate, .. } => {
+                if *substrate == i {
+                    *self = RateLaw::MassAction;
+                } else if *substrate > i {
+                    *substrate -= 1;
+                }
+            }
+            _otherwise => { }
+        }
+    }
+
+    // The propensity divided by the rate. Not defined for expressions, which
+    // are evaluated through `Propensity`.
+    //
+    // `factor(pop, k)` is the mass action contribution of k copies of a species
+    // with population `pop`: pop^k for the ODE and a falling factorial for the SSA.
+    pub fn term(&self, input: ArrayView1<i32>, pops: &[f64], factor: impl Fn(f64, i32) -> f64) -> f64 {
+        let mass_action = |skip: Option<usize>| -> f64 {
+            input.iter().zip(pops.iter()).enumerate()
+                .filter(|(k, _)| Some(*k) != skip)
+                .map(|(_, (a, x))| factor(*x, *a))
+                .product()
+        };
+        match self {
+            RateLaw::MassAction => mass_action(None),
+            RateLaw::MichaelisMenten { substrate, km } => {
+                let x = pops.get(*substrate).cloned().unwrap_or(0.).max(0.);
+                mass_action(Some(*substrate)) * x / (*km as f64 + x)
+            }
+            RateLaw::Hill { substrate, k, n } => {
+                let x = pops.get(*substrate).cloned().unwrap_or(0.).max(0.).powf(*n as f64);
+                mass_action(Some(*substrate)) * x / ((*k as f64).powf(*n as f64) + x)
+            }
+            RateLaw::FrequencyDependent => {
+                let total: f64 = pops.iter().sum();
+                let order: i32 = input.sum();
+                if total <= 0. {
+                    0.
+                } else {
+                    mass_action(None) / total.powi((order - 1).max(0))
+                }
+            }
+            RateLaw::Expression(_) => 0.,
+        }
+    }
+
+    // The propensity written out as an expression, for export. `species` and
+    // `rate` stand for the populations and the rate, and `var` renames the
+    // variables of an expression law. A species with a nonzero entry in
+    // `inhibit` switches the transition off at or above that threshold.
+    pub fn symbolic(&self, input: ArrayView1<i32>, inhibit: ArrayView1<i32>, species: &[Expr], rate: Expr,
+                    var: &dyn Fn(&str) -> Expr) -> Result<Expr, ParseError> {
+        let bin = |op: BinOp, a: Expr, b: Expr| Expr::Bin(op, Box::new(a), Box::new(b));
+        let power = |x: Expr, k: f64| if k == 1. { x } else { bin(BinOp::Pow, x, Expr::Num(k)) };
+        let product = |factors: Vec<Expr>| {
+            let mut factors = factors.into_iter();
+            match factors.next() {
+                Some(first) => factors.fold(first, |p, f| bin(BinOp::Mul, p, f)),
+                None => Expr::Num(1.),
+            }
+        };
+        let mass_action = |skip: Option<usize>| -> Vec<Expr> {
+            input.iter().zip(species.iter()).enumerate()
+                .filter(|(k, (a, _))| **a > 0 && Some(*k) != skip)
+                .map(|(_, (a, x))| power(x.clone(), *a as f64))
+                .collect()
+        };
+        let saturated = |substrate: usize, saturation: Expr| {
+            product(std::iter::once(rate.clone()).chain(mass_action(Some(substrate))).chain(std::iter::once(saturation)).collect())
+        };
+        let law = match self {
+            RateLaw::MassAction => product(std::iter::once(rate.clone()).chain(mass_action(None)).collect()),
+            RateLaw::MichaelisMenten { substrate, km } => {
+                let x = species[*substrate].clone();
+                saturated(*substrate, bin(BinOp::Div, x.clone(), bin(BinOp::Add, Expr::Num(exact(*km)), x)))
+            }
+            RateLaw::Hill { substrate, k, n } => {
+                let xn = power(species[*substrate].clone(), exact(*n));
+                let kn = power(Expr::Num(exact(*k)), exact(*n));
+                saturated(*substrate, bin(BinOp::Div, xn.clone(), bin(BinOp::Add, kn, xn)))
+            }
+            RateLaw::FrequencyDependent => {
+                let order: i32 = input.sum();
+                let numerator = product(std::iter::once(rate.clone()).chain(mass_action(None)).collect());
+                match species.split_first() {
+                    Some((first, rest)) if order > 1 => {
+                        let total = rest.iter().fold(first.clone(), |sum, x| bin(BinOp::Add, sum, x.clone()));
+                        bin(BinOp::Div, numerator, power(total, (order - 1) as f64))
+                    }
+                    _otherwise => numerator,
+                }
+            }
+            RateLaw::Expression(src) => Expr::parse(src)?.substitute(&|v| Some(var(v))),
+        };
+        Ok(inhibit.iter().zip(species.iter()).filter(|(k, _)| **k > 0)
+            .fold(law, |law, (k, x)| bin(BinOp::Mul, law, bin(BinOp::Lt, x.clone(), Expr::Num(*k as f64)))))
+    }
+}
+
+// The value of an f32 as written, rather than widened: 0.1 and not 0.10000000149011612
+pub(crate) fn exact(x: f32) -> f64 {
+    x.to_string().parse().unwrap_or(x as f64)
+}
+
+// Tables of the environment that propensity expressions are evaluated in
+pub const SPECIES_TABLE: usize = 0;
+pub const RATES_TABLE: usize = 1;
+pub const PARAMETERS_TABLE: usize = 2;
+// Holds the single entry `t`, the simulation time
+pub const TIME_TABLE: usize = 3;
+
+// Resolves species names, parameter names, transition names (standing for
+// their rates) and `t`, in that order of precedence
+pub fn resolver<'a>(species: &'a [String], parameters: &'a [String], transitions: &'a [String])
+        -> impl Fn(&str) -> Option<(usize, usize)> + 'a {
+    move |v| {
+        species.iter().position(|s| s == v).map(|i| (SPECIES_TABLE, i))
+            .or_else(|| parameters.iter().position(|p| p == v).map(|i| (PARAMETERS_TABLE, i)))
+            .or_else(|| transitions.iter().position(|t| t == v).map(|i| (RATES_TABLE, i)))
+            .or_else(|| if v == "t" { Some((TIME_TABLE, 0)) } else { None })
+    }
+}
+
+// A rate law ready for evaluation, with expressions compiled
+pub enum Propensity {
+    Law(RateLaw),
+    Expression(Compiled),
+}
+
+impl Propensity {
+    pub fn compile(law: &RateLaw, resolve: &dyn Fn(&str) -> Option<(usize, usize)>) -> Result<Self, ParseError> {
+        match law {
+            RateLaw::Expression(src) => Ok(Propensity::Expression(compile(src, resolve)?)),
+            _otherwise => Ok(Propensity::Law(law.clone())),
+        }
+    }
+
+    // Propensity of transition `t` in the environment `env`, laid out as
+    // described by the `*_TABLE` constants. Expressions that are negative or
+    // NaN give 0.
+    pub fn eval(&self, t: usize, input: ArrayView1<i32>, env: &[&[f64]], factor: impl Fn(f64, i32) -> f64) -> f64 {
+        match self {
+            Propensity::Law(l) => env[RATES_TABLE][t] * l.term(input, env[SPECIES_TABLE], factor),
+            Propensity::Expression(e) => {
+                let x = e(env);
+                if x > 0. { x } else { 0. }
+            }
+        }
+    }
+}
@@ -1,11 +1,37 @@
 #![recursion_limit = "1024"]
-mod math;
-mod petri;
+pub mod math;
+pub mod kinetics;
+pub mod expr;
+pub mod events;
+pub mod petri;
+#[cfg(target_arch = "wasm32")]
 mod plot;
 #[cfg(target_arch = "wasm32")]
 mod editor;
+#[cfg(target_arch = "wasm32")]
 mod utils;
-mod ssa;
+pub mod ssa;
+pub mod gsmp;
+pub mod sensitivity;
+pub mod identifiability;
+pub mod reachability;
+pub mod colored;
+pub mod typed;
+pub mod open;
+pub mod patches;
+pub mod diffusion;
+pub mod stages;
+pub mod xml;
+pub mod interchange;
+pub mod sbml;
+pub mod pnml;
+pub mod acset;
+pub mod reactions;
+pub mod antimony;
+pub mod trajectory;
+pub mod figure;
+pub mod diagram;
+pub mod codegen;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
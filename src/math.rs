@@ -1,9 +1,11 @@
 use ndarray::prelude::*;
+use crate::kinetics::*;
+use crate::petri::IO;
 
 pub type V = Array<f32, Ix1>;
 pub type YVals = Array<f32, Ix2>;
 
-fn rk_step(f: impl Fn(f32, &V) -> V, t: f32, dt: f32, y: &V) -> V {
+pub fn rk_step(f: impl Fn(f32, &V) -> V, t: f32, dt: f32, y: &V) -> V {
     let k1: V = f(t, y);
     let k2 = f(t + 0.5 * dt, &(y + &(&k1 * dt * 0.5)));
     let k3 = f(t + 0.5 * dt, &(y + &(&k2 * dt * 0.5)));
@@ -23,38 +25,198 @@ pub fn rk_solve(f: impl Fn(f32, &V) -> V, t0: f32, t1: f32, steps: usize, y0: &V
     yvals
 }
 
-pub struct PetriData(pub Array<i32, Ix3>);
+// Values recorded at increasing times and interpolated linearly in between,
+// for the lagged terms of delay differential equations
+#[derive(Default)]
+pub struct History {
+    times: Vec<f32>,
+    values: Vec<V>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Records `v` at time `t`, which must not be earlier than the last time
+    // recorded. A second value at the same time replaces the first.
+    pub fn push(&mut self, t: f32, v: V) {
+        if self.times.last() == Some(&t) {
+            self.times.pop();
+            self.values.pop();
+        }
+        self.times.push(t);
+        self.values.push(v);
+    }
+
+    pub fn start(&self) -> Option<f32> {
+        self.times.first().cloned()
+    }
+
+    // The value at time `t`, None before the first time recorded and the last
+    // value after the last time
+    pub fn at(&self, t: f32) -> Option<V> {
+        let i = self.times.partition_point(|s| *s <= t);
+        if i == 0 {
+            return None;
+        }
+        if i == self.times.len() {
+            return self.values.last().cloned();
+        }
+        let (t0, t1) = (self.times[i - 1], self.times[i]);
+        let a = (t - t0) / (t1 - t0);
+        Some(&self.values[i - 1] * (1. - a) + &self.values[i] * a)
+    }
+}
+
+// The end of a method of steps step from `t`: at most the shortest of the
+// positive `delays`, so the lagged times of every Runge-Kutta stage fall
+// within the history already computed, and stopping at `t0 + delay`, where
+// the lagged terms switch on
+pub fn dde_step_end(delays: &[f32], t0: f32, t: f32, t_end: f32) -> f32 {
+    delays.iter().filter(|d| **d > 0.)
+        .flat_map(|d| vec![t + d, t0 + d])
+        .filter(|s| *s > t)
+        .fold(t_end, f32::min)
+}
+
+// The method of steps for a delay differential equation
+// y' = f(t, segment, y, history), where the lagged terms of `f` are looked up
+// in the history of g(t, y), and `segment` is the start of the current step,
+// as in `PetriData::delayed_eq_with`. Sampled on the same grid as `rk_solve`.
+pub fn dde_solve(f: impl Fn(f32, f32, &V, &History) -> V, g: impl Fn(f32, &V) -> V, delays: &[f32],
+                 t0: f32, t1: f32, steps: usize, y0: &V) -> YVals {
+    let mut yvals = Array::zeros((steps, y0.len()));
+    let mut history = History::new();
+    let mut y = y0.clone();
+    let mut t = t0;
+    history.push(t, g(t, &y));
+    for (i, mut cell) in yvals.axis_iter_mut(Axis(0)).enumerate() {
+        let t_end = t0 + (i + 1) as f32 / (steps as f32) * (t1 - t0);
+        while t < t_end {
+            let h_end = dde_step_end(delays, t0, t, t_end);
+            y = rk_step(|s, v| f(s, t, v, &history), t, h_end - t, &y);
+            t = h_end;
+            history.push(t, g(t, &y));
+        }
+        cell.assign(&y);
+    }
+    yvals
+}
+
+// Stoichiometry, propensities, parameter values and output delays
+pub struct PetriData(pub Array<i32, Ix3>, pub Vec<Propensity>, pub Vec<f64>, pub Vec<f32>);
 
 impl PetriData {
     pub fn data(&self) -> &Array<i32, Ix3> {
         match self {
-            PetriData(d) => d,
+            PetriData(d, _, _, _) => d,
         }
     }
 
+    pub fn propensities(&self) -> &[Propensity] {
+        match self {
+            PetriData(_, l, _, _) => l,
+        }
+    }
+
+    pub fn parameters(&self) -> &[f64] {
+        match self {
+            PetriData(_, _, p, _) => p,
+        }
+    }
+
+    // How long after firing each transition's outputs appear, 0 for at once
+    pub fn delays(&self) -> &[f32] {
+        match self {
+            PetriData(_, _, _, d) => d,
+        }
+    }
+
+    // The shortest positive delay, None if no transition is delayed
+    pub fn min_delay(&self) -> Option<f32> {
+        self.delays().iter().cloned().filter(|d| *d > 0.).fold(None, |a: Option<f32>, d| Some(a.map_or(d, |a| a.min(d))))
+    }
+
     pub fn num_species(&self) -> usize {
         self.data().len_of(Axis(2))
     }
 
-    fn master_eq(&self, rates: &V, y: &V) -> V {
+    // The propensity of transition `i` in the environment `env`. Read arcs
+    // take part in the rate law like inputs that are not consumed, so a read
+    // arc of weight w contributes pop^w to a mass action term (a catalyst).
+    // Inhibitor arcs switch the transition off while the population is at or
+    // above their threshold, which in the ODE is a step function of the
+    // population rather than a smooth repression.
+    pub fn propensity(&self, i: usize, env: &[&[f64]], factor: impl Fn(f64, i32) -> f64) -> f64 {
+        let trans = self.data().index_axis(Axis(0), i);
+        let inhibited = trans.row(IO::Inhibit.to_idx()).iter().zip(env[SPECIES_TABLE].iter())
+            .any(|(w, x)| *w > 0 && *x >= *w as f64);
+        if inhibited {
+            return 0.;
+        }
+        let participants = &trans.row(IO::Input.to_idx()) + &trans.row(IO::Read.to_idx());
+        self.propensities()[i].eval(i, participants.view(), env, factor)
+    }
+
+    fn master_eq(&self, t: f32, rates: &V, y: &V) -> V {
+        let rates: Vec<f64> = rates.iter().map(|r| *r as f64).collect();
+        self.master_eq_with(t as f64, &rates, self.parameters(), y)
+    }
+
+    // The master equation with the given rates and parameter values at time `t`
+    pub fn master_eq_with(&self, t: f64, rates: &[f64], parameters: &[f64], y: &V) -> V {
         let d = self.data();
         let mut yp = Array::zeros(y.dim());
-        let n = self.num_species();
+        let flows = self.flows(t, rates, parameters, y);
+        for (trans, flow) in d.axis_iter(Axis(0)).zip(flows.iter()) {
+            yp += &((&trans.row(1) - &trans.row(0)).mapv(|c| c as f32) * *flow);
+        }
+        yp
+    }
+
+    // The firing rate of each transition
+    pub fn flows(&self, t: f64, rates: &[f64], parameters: &[f64], y: &V) -> V {
+        let pops: Vec<f64> = y.iter().map(|x| *x as f64).collect();
+        (0..self.propensities().len())
+            .map(|i| self.propensity(i, &[&pops, rates, parameters, &[t]], |pop, k| pop.powi(k)) as f32)
+            .collect()
+    }
+
+    // The master equation when some transitions are delayed: inputs are
+    // consumed as a transition fires, and its outputs are produced at the rate
+    // it fired one delay earlier, looked up in a history of `flows`. Nothing
+    // fired before the history starts; whether the outputs of a transition
+    // have switched on is decided at `segment`, the start of the integration
+    // step, so that a step ending on the switch stays on one side of it.
+    pub fn delayed_eq_with(&self, t: f64, segment: f64, rates: &[f64], parameters: &[f64], y: &V,
+                           history: &History) -> V {
+        let d = self.data();
+        let mut yp = Array::zeros(y.dim());
+        let flows = self.flows(t, rates, parameters, y);
         for (i, trans) in d.axis_iter(Axis(0)).enumerate() {
-            let r = rates[i];
-            for j in 0..n {
-                let coeff = r * ((trans[(1, j)] - trans[(0, j)]) as f32);
-                let mut term: f32 = 1.;
-                for (k, pop) in y.iter().enumerate() {
-                    term *= pop.powi(trans[(0, k)]);
-                }
-                yp[j] += coeff * term;
-            }
+            let delay = self.delays().get(i).cloned().unwrap_or(0.);
+            let produced = if delay <= 0. {
+                flows[i]
+            } else if history.start().is_some_and(|s| segment as f32 - delay >= s) {
+                history.at(t as f32 - delay).map_or(0., |h| h[i])
+            } else {
+                0.
+            };
+            yp += &(trans.row(1).mapv(|c| c as f32) * produced - trans.row(0).mapv(|c| c as f32) * flows[i]);
         }
         yp
     }
 
     pub fn solve(&self, rates: &V, init_vals: &V, t1: f32, steps: usize) -> YVals {
-        rk_solve(|_, v| self.master_eq(rates, v), 0.0, t1, steps, init_vals)
+        match self.min_delay() {
+            None => rk_solve(|t, v| self.master_eq(t, rates, v), 0.0, t1, steps, init_vals),
+            Some(_) => {
+                let rates: Vec<f64> = rates.iter().map(|r| *r as f64).collect();
+                dde_solve(|t, s, v, h| self.delayed_eq_with(t as f64, s as f64, &rates, self.parameters(), v, h),
+                          |t, v| self.flows(t as f64, &rates, self.parameters(), v),
+                          self.delays(), 0.0, t1, steps, init_vals)
+            }
+        }
     }
 }
@@ -0,0 +1,227 @@
+use serde::{Serialize, Deserialize};
+use crate::petri::*;
+use crate::expr::*;
+use crate::kinetics::*;
+use crate::events::*;
+
+// Open Petri nets, whose boundary species can be glued to those of other nets,
+// composed along an undirected wiring diagram: each instance of a module wires
+// its boundary species to junctions, and species wired to the same junction
+// become one species of the composite.
+//
+// In the flattened net the junctions keep their names, and the other species,
+// the transitions and the observables of an instance `m` are named `m_name`.
+// Parameters are shared between instances by name, with the first value
+// taken. Expressions and events are rewritten to the new names.
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OpenPetriNet {
+    pub net: PetriNet,
+    // Species that can be glued to other nets
+    pub boundary: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Instance {
+    pub name: String,
+    pub module: OpenPetriNet,
+    // The junction each boundary species is wired to
+    pub wires: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Composition {
+    pub name: String,
+    pub junctions: Vec<String>,
+    pub instances: Vec<Instance>,
+    // Junctions left open in the composite, so it can be composed further
+    #[serde(default)]
+    pub outer: Vec<usize>,
+}
+
+// A composite net, with the (instance, name) each species and transition came from
+pub struct Flattened {
+    pub net: PetriNet,
+    pub boundary: Vec<usize>,
+    pub species_origins: Vec<Vec<(String, String)>>,
+    pub transition_origins: Vec<(String, String)>,
+}
+
+impl Flattened {
+    pub fn open(&self) -> OpenPetriNet {
+        OpenPetriNet { net: self.net.clone(), boundary: self.boundary.clone() }
+    }
+}
+
+// Moves the arcs of `t` to the species `species_map[k]` of a net with `n` species
+fn relabel(t: &Transition, species_map: &[usize], n: usize) -> Transition {
+    let mut out = t.clone();
+    for d in DIRECTIONS.iter() {
+        let arcs = &mut out[*d];
+        if !arcs.is_empty() {
+            *arcs = vec![0; n];
+            for (k, s) in species_map.iter().enumerate() {
+                arcs[*s] += t.arc(*d, k);
+            }
+        }
+    }
+    match &mut out.rate_law {
+        RateLaw::MichaelisMenten { substrate, .. } | RateLaw::Hill { substrate, .. } => {
+            *substrate = species_map[*substrate];
+        }
+        _otherwise => { }
+    }
+    out
+}
+
+// Rewrites the names in an expression, leaving it as is if it does not parse
+fn rewrite(src: &str, rename: &dyn Fn(&str) -> Option<String>) -> String {
+    rename_variables(src, rename).unwrap_or_else(|_| src.to_string())
+}
+
+// Checks that the substrates of the rate laws of `net` are species of `net`,
+// which a net read from a file need not satisfy
+fn check_substrates(name: &str, net: &PetriNet) -> Result<(), String> {
+    match net.transitions.iter().find(|t| t.rate_law.substrate().is_some_and(|s| s >= net.species.len())) {
+        Some(t) => Err(format!("instance {}: transition {} has a substrate that is not a species", name, t.name)),
+        None => Ok(()),
+    }
+}
+
+// Adds the transitions, observables, events and parameters of `net` to
+// `petri`, with species `k` of `net` mapped to species `map[k]` of `petri`
+pub(crate) fn add_instance(petri: &mut PetriNet, name: &str, net: &PetriNet, map: &[usize],
+                transition_origins: &mut Vec<(String, String)>) {
+    let species = petri.species.clone();
+    let rename = |v: &str| -> Option<String> {
+        net.species.iter().position(|s| s == v).map(|k| species[map[k]].clone())
+            .or_else(|| if net.parameters.iter().any(|p| p.name == v) { None } else {
+                net.transitions.iter().find(|t| t.name == v).map(|t| format!("{}_{}", name, t.name))
+            })
+    };
+    for p in net.parameters.iter() {
+        if !petri.parameters.iter().any(|q| q.name == p.name) {
+            petri.parameters.push(p.clone());
+        }
+    }
+    for original in net.transitions.iter() {
+        let mut t = relabel(original, map, petri.species.len());
+        t.name = format!("{}_{}", name, original.name);
+        if let RateLaw::Expression(src) = &t.rate_law {
+            t.rate_law = RateLaw::Expression(rewrite(src, &rename));
+        }
+        if let Schedule::Expression(src) = &t.schedule {
+            t.schedule = Schedule::Expression(rewrite(src, &rename));
+        }
+        transition_origins.push((name.to_string(), original.name.clone()));
+        petri.transitions.push(t);
+    }
+    for o in net.observables.iter() {
+        petri.observables.push(Observable {
+            name: format!("{}_{}", name, o.name),
+            expression: rewrite(&o.expression, &rename),
+        });
+    }
+    for e in net.events.iter() {
+        let trigger = match &e.trigger {
+            Trigger::When(c) => Trigger::When(rewrite(c, &rename)),
+            t => t.clone(),
+        };
+        let actions = e.actions.iter()
+            .map(|a| match a {
+                Action::Scale(n, f) => Action::Scale(rename(n).unwrap_or_else(|| n.clone()), *f),
+                Action::AddTokens(n, x) => Action::AddTokens(rename(n).unwrap_or_else(|| n.clone()), *x),
+            })
+            .collect();
+        petri.events.push(Event { trigger, actions });
+    }
+}
+
+impl Composition {
+    pub fn flatten(&self) -> Result<Flattened, String> {
+        let mut species = self.junctions.clone();
+        let mut species_origins = vec![vec![]; species.len()];
+        let mut maps = vec![];
+        for inst in self.instances.iter() {
+            let net = &inst.module.net;
+            check_substrates(&inst.name, net)?;
+            if inst.wires.len() != inst.module.boundary.len() {
+                return Err(format!("instance {}: every boundary species must be wired to a junction", inst.name));
+            }
+            let mut map = vec![];
+            for (k, s) in net.species.iter().enumerate() {
+                let j = match inst.module.boundary.iter().position(|b| *b == k) {
+                    Some(p) => match inst.wires.get(p).filter(|j| **j < self.junctions.len()) {
+                        Some(j) => *j,
+                        None => return Err(format!("instance {}: species {} is wired to a missing junction", inst.name, s)),
+                    },
+                    None => {
+                        species.push(format!("{}_{}", inst.name, s));
+                        species_origins.push(vec![]);
+                        species.len() - 1
+                    }
+                };
+                species_origins[j].push((inst.name.clone(), s.clone()));
+                map.push(j);
+            }
+            maps.push(map);
+        }
+
+        let mut petri = PetriNet::new(&self.name, species, vec![]);
+        let mut transition_origins = vec![];
+        for (inst, map) in self.instances.iter().zip(maps.iter()) {
+            add_instance(&mut petri, &inst.name, &inst.module.net, map, &mut transition_origins);
+        }
+
+        Ok(Flattened { net: petri, boundary: self.outer.clone(), species_origins, transition_origins })
+    }
+}
+
+// A net from a library embedded in a parent net, as in the editor. Species of
+// the module are mapped onto species of the parent, or kept internal to the
+// instance if unmapped.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Submodule {
+    pub name: String,
+    // Index of the module in the library
+    pub module: usize,
+    pub species_map: Vec<Option<usize>>,
+    // Whether the editor shows the transitions of the instance
+    #[serde(default)]
+    pub expanded: bool,
+}
+
+// The parent net with the instances added after its own species and
+// transitions. The parent's own species and transitions have the origin
+// ("", name).
+pub fn embed(parent: &PetriNet, instances: &[(&Submodule, &PetriNet)]) -> Result<Flattened, String> {
+    let mut petri = parent.clone();
+    let mut species_origins: Vec<Vec<(String, String)>> = parent.species.iter()
+        .map(|s| vec![("".to_string(), s.clone())])
+        .collect();
+    let mut transition_origins: Vec<(String, String)> = parent.transitions.iter()
+        .map(|t| ("".to_string(), t.name.clone()))
+        .collect();
+    for (sub, net) in instances.iter() {
+        check_substrates(&sub.name, net)?;
+        let mut map = vec![];
+        for (k, s) in net.species.iter().enumerate() {
+            let j = match sub.species_map.get(k).cloned().flatten() {
+                Some(j) if j < parent.species.len() => j,
+                Some(_) => return Err(format!("instance {}: species {} is mapped to a missing species", sub.name, s)),
+                None => {
+                    petri.species.push(format!("{}_{}", sub.name, s));
+                    species_origins.push(vec![]);
+                    for t in petri.transitions.iter_mut() {
+                        t.add_species();
+                    }
+                    petri.species.len() - 1
+                }
+            };
+            species_origins[j].push((sub.name.clone(), s.clone()));
+            map.push(j);
+        }
+        add_instance(&mut petri, &sub.name, net, &map, &mut transition_origins);
+    }
+    Ok(Flattened { net: petri, boundary: vec![], species_origins, transition_origins })
+}
@@ -0,0 +1,212 @@
+use serde::{Serialize, Deserialize};
+use ndarray::prelude::*;
+use plotters::prelude::*;
+use plotters::coord::Shift;
+use crate::math::*;
+use crate::petri::*;
+use crate::open::*;
+
+// Metapopulation models: a copy of a net in each patch, with migration
+// transitions moving each species between patches. The expanded net is an
+// ordinary `PetriNet`, with the species of patch `p` named `p_species` and
+// laid out patch by patch, so the species `s` of patch `p` has index
+// `p * n + s` for a net with `n` species.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum PatchView {
+    // Every species of every patch
+    #[default]
+    Each,
+    // Each species summed over the patches
+    Aggregate,
+    // A chart for each patch
+    Facet,
+}
+
+pub static PATCH_VIEWS: [&str; 3] = ["Each Patch", "Aggregate", "Facet"];
+
+impl PatchView {
+    pub fn to_idx(self) -> usize {
+        match self {
+            PatchView::Each => 0,
+            PatchView::Aggregate => 1,
+            PatchView::Facet => 2,
+        }
+    }
+
+    pub fn from_idx(i: usize) -> Self {
+        match i {
+            1 => PatchView::Aggregate,
+            2 => PatchView::Facet,
+            _ => PatchView::Each,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Metapopulation {
+    pub patches: Vec<String>,
+    // migration[p][q] is the per capita rate of moving from patch p to patch q
+    pub migration: Vec<Vec<f32>>,
+    // Factor on the migration rates of each species, so 0 keeps a species in
+    // place. Missing entries are 1.
+    #[serde(default)]
+    pub mobility: Vec<f32>,
+    // Initial values in each patch. A patch without an entry, or with an empty
+    // one, starts with the initial values of the net if it is the first patch,
+    // and empty otherwise.
+    #[serde(default)]
+    pub initial: Vec<Vec<f32>>,
+    #[serde(default)]
+    pub view: PatchView,
+}
+
+impl Metapopulation {
+    pub fn new(patches: Vec<String>) -> Self {
+        let n = patches.len();
+        Metapopulation {
+            patches,
+            migration: vec![vec![0.; n]; n],
+            mobility: vec![],
+            initial: vec![],
+            view: PatchView::Each,
+        }
+    }
+
+    pub fn migration_rate(&self, p: usize, q: usize) -> f32 {
+        self.migration.get(p).and_then(|r| r.get(q)).cloned().unwrap_or(0.)
+    }
+
+    pub fn mobility(&self, s: usize) -> f32 {
+        self.mobility.get(s).cloned().unwrap_or(1.)
+    }
+
+    // (species, from, to, rate) of each migration transition
+    fn migrations(&self, n: usize) -> Vec<(usize, usize, usize, f32)> {
+        let m = self.patches.len();
+        let mut out = vec![];
+        for s in 0..n {
+            for p in 0..m {
+                for q in 0..m {
+                    let rate = self.migration_rate(p, q) * self.mobility(s);
+                    if p != q && rate > 0. {
+                        out.push((s, p, q, rate));
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    pub fn expand(&self, petri: &PetriNet) -> PetriNet {
+        let n = petri.species.len();
+        let species = self.patches.iter()
+            .flat_map(|p| petri.species.iter().map(move |s| format!("{}_{}", p, s)))
+            .collect();
+        let mut out = PetriNet::new(&petri.name, species, vec![]);
+        let mut origins = vec![];
+        for (p, name) in self.patches.iter().enumerate() {
+            let map: Vec<usize> = (0..n).map(|s| p * n + s).collect();
+            add_instance(&mut out, name, petri, &map, &mut origins);
+        }
+        let total = out.species.len();
+        for (s, p, q, _) in self.migrations(n) {
+            let (mut input, mut output) = (vec![0; total], vec![0; total]);
+            input[p * n + s] = 1;
+            output[q * n + s] = 1;
+            let name = format!("migrate_{}_{}_{}", petri.species[s], self.patches[p], self.patches[q]);
+            out.transitions.push(Transition::new(&name, input, output));
+        }
+        out
+    }
+
+    // Controls for the expanded net, with the rates of `controls` in every
+    // patch followed by the migration rates
+    pub fn expand_controls(&self, petri: &PetriNet, controls: &PlotControls) -> PlotControls {
+        let n = petri.species.len();
+        let mut out = controls.clone();
+        let rates = petri.effective_rates(controls);
+        out.rates = self.patches.iter().flat_map(|_| rates.iter().cloned()).collect();
+        out.rates.extend(self.migrations(n).iter().map(|m| m.3));
+        out.rate_ranges = vec![];
+        out.init_vals = (0..self.patches.len())
+            .flat_map(|p| {
+                let initial = match self.initial.get(p).filter(|v| !v.is_empty()) {
+                    Some(v) => v.clone(),
+                    None if p == 0 => controls.init_vals.clone(),
+                    None => vec![],
+                };
+                (0..n).map(move |s| initial.get(s).cloned().unwrap_or(0.))
+            })
+            .collect();
+        out
+    }
+
+    // Each species summed over the patches, from a trajectory of the expanded net
+    pub fn aggregate(&self, yvals: &YVals) -> YVals {
+        let n = yvals.ncols() / self.patches.len().max(1);
+        let mut out = Array::zeros((yvals.nrows(), n));
+        for p in 0..self.patches.len() {
+            out += &self.patch(yvals, p);
+        }
+        out
+    }
+
+    // The species of patch `p`, from a trajectory of the expanded net
+    pub fn patch(&self, yvals: &YVals, p: usize) -> YVals {
+        let n = yvals.ncols() / self.patches.len().max(1);
+        yvals.slice(s![.., p * n..(p + 1) * n]).to_owned()
+    }
+
+    // Plots a trajectory of the expanded net `petri` as chosen by `view`,
+    // naming the species as in the first patch
+    pub fn draw<DB: DrawingBackend>(&self, petri: &PetriNet, controls: &PlotControls, root: &DrawingArea<DB, Shift>)
+            -> DrawResult<(), DB> {
+        let yvals = petri.simulate(controls, STEPS);
+        let n = petri.species.len() / self.patches.len().max(1);
+        let prefix = format!("{}_", self.patches.first().cloned().unwrap_or_default());
+        let names: Vec<String> = petri.species.iter().take(n)
+            .map(|s| s.trim_start_matches(&prefix).to_string())
+            .collect();
+
+        root.fill(&WHITE)?;
+        match self.view {
+            PatchView::Facet => {
+                let cols = (self.patches.len() as f64).sqrt().ceil().max(1.) as usize;
+                let rows = (self.patches.len() + cols - 1) / cols.max(1);
+                let areas = root.split_evenly((rows.max(1), cols));
+                for (p, area) in areas.iter().enumerate().take(self.patches.len()) {
+                    draw_lines(area, &self.patches[p], &names, &self.patch(&yvals, p), controls, 12.0)?;
+                }
+            }
+            _otherwise => {
+                draw_lines(root, &petri.name, &names, &self.aggregate(&yvals), controls, 20.0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn draw_lines<DB: DrawingBackend>(area: &DrawingArea<DB, Shift>, caption: &str, names: &[String],
+              yvals: &YVals, controls: &PlotControls, font_size: f64) -> DrawResult<(), DB> {
+    let font: FontDesc = ("sans-serif", font_size).into();
+    let steps_per_unit = yvals.nrows() as f32 / controls.xmax;
+    let mut chart = ChartBuilder::on(area)
+        .caption(caption, font)
+        .x_label_area_size(30)
+        .y_label_area_size(30)
+        .build_ranged(0.0..controls.xmax, 0.0..controls.ymax)?;
+    chart.configure_mesh().disable_mesh().draw()?;
+    for (i, s) in names.iter().enumerate() {
+        let c = get_color(i);
+        chart
+            .draw_series(LineSeries::new(
+                (0..yvals.nrows()).map(|x| (x as f32 / steps_per_unit, yvals[(x, i)])),
+                &c,
+            ))?
+            .label(s)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &c));
+    }
+    chart.configure_series_labels().border_style(&BLACK).draw()?;
+    Ok(())
+}
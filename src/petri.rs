@@ -3,26 +3,161 @@ use serde::{Serialize,Deserialize};
 use std::ops::{Index,IndexMut};
 use std::fmt;
 use plotters::prelude::*;
+use plotters::coord::Shift;
 use crate::math::*;
+use crate::kinetics::*;
+use crate::expr::*;
+use crate::events::*;
+use crate::gsmp::*;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Transition {
     pub name: String,
     pub input: Vec<i32>,
-    pub output: Vec<i32>
+    pub output: Vec<i32>,
+    // Read arcs: tokens required for the transition to fire, but not consumed
+    #[serde(default)]
+    pub read: Vec<i32>,
+    // Inhibitor arcs: the transition is disabled while a species has at least
+    // this many tokens. Zero means no arc.
+    #[serde(default)]
+    pub inhibit: Vec<i32>,
+    #[serde(default)]
+    pub rate_law: RateLaw,
+    // Name of the parameter giving the rate, overriding the rate in `PlotControls`
+    #[serde(default)]
+    pub rate: Option<String>,
+    #[serde(default)]
+    pub schedule: Schedule,
+    #[serde(default)]
+    pub timing: Timing,
+    #[serde(default)]
+    pub policy: Policy,
+    // Time after firing at which the outputs appear, with the inputs consumed
+    // at once. Zero for no delay.
+    #[serde(default)]
+    pub delay: f32,
+    // Number of stages of the linear chain the transition is expanded to by
+    // `PetriNet::expand_stages`. 0 or 1 for none.
+    #[serde(default)]
+    pub stages: u32
+}
+
+impl Transition {
+    pub fn new(name: &str, input: Vec<i32>, output: Vec<i32>) -> Self {
+        Transition {
+            name: name.to_string(),
+            input,
+            output,
+            read: vec![],
+            inhibit: vec![],
+            rate_law: RateLaw::MassAction,
+            rate: None,
+            schedule: Schedule::Constant,
+            timing: Timing::Exponential,
+            policy: Policy::EnablingMemory,
+            delay: 0.,
+            stages: 0
+        }
+    }
+
+    // The weight of the arc to species `k`. Read and inhibitor arcs are either
+    // empty (none at all) or have an entry for every species.
+    pub fn arc(&self, d: IO, k: usize) -> i32 {
+        self[d].get(k).cloned().unwrap_or(0)
+    }
+
+    pub fn set_arc(&mut self, d: IO, k: usize, w: i32) {
+        let n = self.input.len();
+        let arcs = &mut self[d];
+        if arcs.len() < n {
+            arcs.resize(n, 0);
+        }
+        arcs[k] = w;
+    }
+
+    pub fn add_species(&mut self) {
+        for d in DIRECTIONS.iter() {
+            let arcs = &mut self[*d];
+            match d {
+                IO::Input | IO::Output => arcs.push(0),
+                _ if !arcs.is_empty() => arcs.push(0),
+                _otherwise => { }
+            }
+        }
+    }
+
+    pub fn remove_species(&mut self, k: usize) {
+        for d in DIRECTIONS.iter() {
+            let arcs = &mut self[*d];
+            if k < arcs.len() {
+                arcs.remove(k);
+            }
+        }
+        self.rate_law.remove_species(k);
+    }
+
+    // Whether the transition can fire in `marking`: enough tokens on the input
+    // and read arcs, and fewer than the threshold on the inhibitor arcs
+    pub fn enabled(&self, marking: &[i32]) -> bool {
+        marking.iter().enumerate().all(|(k, m)| {
+            let inhibit = self.arc(IO::Inhibit, k);
+            *m >= self.arc(IO::Input, k) + self.arc(IO::Read, k) && (inhibit <= 0 || *m < inhibit)
+        })
+    }
+
+    // The marking after firing, which must be enabled
+    pub fn fire(&self, marking: &[i32]) -> Vec<i32> {
+        marking.iter().enumerate()
+            .map(|(k, m)| m - self.arc(IO::Input, k) + self.arc(IO::Output, k))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+    pub unit: String
+}
+
+impl Parameter {
+    pub fn new(name: &str, value: f32) -> Self {
+        Parameter {
+            name: name.to_string(),
+            value,
+            min: 0.0,
+            max: 1.0_f32.max(value),
+            unit: "".to_string()
+        }
+    }
+}
+
+// A derived quantity, plotted alongside the species
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Observable {
+    pub name: String,
+    pub expression: String
 }
 
 #[derive(Copy,Clone,Debug)]
 pub enum IO {
     Input,
-    Output
+    Output,
+    Read,
+    Inhibit
 }
 
 impl IO {
-    fn to_idx(self) -> usize {
+    // Index along the second axis of `PetriData::data`
+    pub fn to_idx(self) -> usize {
         match self {
             IO::Input => 0,
-            IO::Output => 1
+            IO::Output => 1,
+            IO::Read => 2,
+            IO::Inhibit => 3
         }
     }
 }
@@ -31,12 +166,14 @@ impl fmt::Display for IO {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", match *self {
             IO::Input => "Input",
-            IO::Output => "Output"
+            IO::Output => "Output",
+            IO::Read => "Read",
+            IO::Inhibit => "Inhibit"
         })
     }
 }
 
-pub static DIRECTIONS: [IO; 2] = [IO::Input, IO::Output];
+pub static DIRECTIONS: [IO; 4] = [IO::Input, IO::Output, IO::Read, IO::Inhibit];
 
 impl Index<IO> for Transition {
     type Output = Vec<i32>;
@@ -44,7 +181,9 @@ impl Index<IO> for Transition {
     fn index(&self, index: IO) -> &Self::Output {
         match index {
             IO::Input => &self.input,
-            IO::Output => &self.output
+            IO::Output => &self.output,
+            IO::Read => &self.read,
+            IO::Inhibit => &self.inhibit
         }
     }
 }
@@ -53,7 +192,9 @@ impl IndexMut<IO> for Transition {
     fn index_mut(&mut self, index: IO) -> &mut Self::Output {
         match index {
             IO::Input => &mut self.input,
-            IO::Output => &mut self.output
+            IO::Output => &mut self.output,
+            IO::Read => &mut self.read,
+            IO::Inhibit => &mut self.inhibit
         }
     }
 }
@@ -64,7 +205,9 @@ pub struct PlotControls {
     pub rates: Vec<f32>,
     pub xmax: f32,
     pub ymax: f32,
-    pub scale: f32
+    pub scale: f32,
+    #[serde(default)]
+    pub rate_ranges: Vec<(f32, f32)>
 }
 
 impl PlotControls {
@@ -74,8 +217,22 @@ impl PlotControls {
             rates: vec![],
             xmax: 10.0,
             ymax: 5.0,
-            scale: 5.0
+            scale: 5.0,
+            rate_ranges: vec![]
+        }
+    }
+
+    // Range explored by the sensitivity analysis, defaulting to the slider range
+    pub fn rate_range(&self, i: usize) -> (f32, f32) {
+        self.rate_ranges.get(i).cloned().unwrap_or((0.0, self.scale))
+    }
+
+    pub fn set_rate_range(&mut self, i: usize, range: (f32, f32)) {
+        while self.rate_ranges.len() <= i {
+            let r = self.rate_range(self.rate_ranges.len());
+            self.rate_ranges.push(r);
         }
+        self.rate_ranges[i] = range;
     }
 }
 
@@ -83,10 +240,36 @@ impl PlotControls {
 pub struct PetriNet {
     pub name: String,
     pub transitions: Vec<Transition>,
-    pub species: Vec<String>
+    pub species: Vec<String>,
+    #[serde(default)]
+    pub parameters: Vec<Parameter>,
+    #[serde(default)]
+    pub observables: Vec<Observable>,
+    #[serde(default)]
+    pub events: Vec<Event>,
+    #[serde(default)]
+    pub layout: Layout
 }
 
-static STEPS: usize = 500;
+// Diagram coordinates of species and transitions by name, kept from imported
+// files that have them
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Layout {
+    pub species: Vec<(String, (f32, f32))>,
+    pub transitions: Vec<(String, (f32, f32))>,
+}
+
+impl Layout {
+    pub fn species_position(&self, name: &str) -> Option<(f32, f32)> {
+        self.species.iter().find(|(n, _)| n == name).map(|(_, p)| *p)
+    }
+
+    pub fn transition_position(&self, name: &str) -> Option<(f32, f32)> {
+        self.transitions.iter().find(|(n, _)| n == name).map(|(_, p)| *p)
+    }
+}
+
+pub static STEPS: usize = 500;
 
 pub fn get_color(i: usize) -> PaletteColor<Palette99> {
     PaletteColor::<Palette99>::pick(i)
@@ -94,45 +277,176 @@ pub fn get_color(i: usize) -> PaletteColor<Palette99> {
 
 impl PetriNet {
     pub fn empty() -> Self {
+        PetriNet::new("", vec![], vec![])
+    }
+
+    pub fn new(name: &str, species: Vec<String>, transitions: Vec<Transition>) -> Self {
         PetriNet {
-            name: "".to_string(),
-            transitions: vec![],
-            species: vec![]
+            name: name.to_string(),
+            transitions,
+            species,
+            parameters: vec![],
+            observables: vec![],
+            events: vec![],
+            layout: Layout::default()
         }
     }
-    
-    fn get_petri_data(&self) -> PetriData {
+
+    pub fn get_petri_data(&self) -> PetriData {
         let (t,s) = (self.transitions.len(), self.species.len());
-        let mut pd = Array::zeros((t,2,s));
+        let mut pd = Array::zeros((t,DIRECTIONS.len(),s));
         for (i,t) in self.transitions.iter().enumerate() {
             for d in DIRECTIONS.iter() {
                 let j = d.to_idx();
                 for k in 0..s {
-                    pd[(i,j,k)] = t[*d][k];
+                    pd[(i,j,k)] = t.arc(*d,k);
+                }
+            }
+        }
+        // Invalid expressions are reported in the editor, and contribute nothing
+        let propensities = self.transitions.iter()
+            .map(|t| self.with_resolver(|resolve| Propensity::compile(&t.rate_law, resolve))
+                 .unwrap_or_else(|_| Propensity::Expression(Box::new(|_| 0.))))
+            .collect();
+        let parameters = self.parameters.iter().map(|p| p.value as f64).collect();
+        let delays = self.transitions.iter().map(|t| t.delay.max(0.)).collect();
+        PetriData(pd, propensities, parameters, delays)
+    }
+
+    pub fn transition_names(&self) -> Vec<String> {
+        self.transitions.iter().map(|t| t.name.clone()).collect()
+    }
+
+    pub fn parameter_names(&self) -> Vec<String> {
+        self.parameters.iter().map(|p| p.name.clone()).collect()
+    }
+
+    // Points the references to parameter `old` in the rates, expression rate
+    // laws, schedules, observables and events at `new`, or drops the rates
+    // bound to it if `new` is None. References in expressions are left as
+    // they are unless `new` is a name, so that they show as errors rather
+    // than vanish while the name is edited; a species named `old` shadows the
+    // parameter in expressions, so those references are kept too.
+    pub fn rename_parameter(&mut self, old: &str, new: Option<&str>) {
+        for t in self.transitions.iter_mut() {
+            if t.rate.as_deref() == Some(old) {
+                t.rate = new.map(|n| n.to_string());
+            }
+        }
+        let new = match new {
+            Some(n) if n.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                && n.chars().all(|c| c.is_alphanumeric() || c == '_') => n.to_string(),
+            _otherwise => return,
+        };
+        let shadowed = self.species.iter().any(|s| s == old);
+        let rename = |v: &str| if v == old && !shadowed { Some(new.clone()) } else { None };
+        let rewrite = |src: &mut String| {
+            if let Ok(renamed) = rename_variables(src, &rename) {
+                *src = renamed;
+            }
+        };
+        for t in self.transitions.iter_mut() {
+            if let RateLaw::Expression(src) = &mut t.rate_law {
+                rewrite(src);
+            }
+            if let Schedule::Expression(src) = &mut t.schedule {
+                rewrite(src);
+            }
+        }
+        for o in self.observables.iter_mut() {
+            rewrite(&mut o.expression);
+        }
+        for e in self.events.iter_mut() {
+            if let Trigger::When(c) = &mut e.trigger {
+                rewrite(c);
+            }
+            for a in e.actions.iter_mut() {
+                if let Action::Scale(n, _) = a {
+                    if n == old {
+                        *n = new.clone();
+                    }
                 }
             }
         }
-        PetriData(pd)
+    }
+
+    // Runs `f` with a resolver for the names in this net
+    pub fn with_resolver<T>(&self, f: impl FnOnce(&dyn Fn(&str) -> Option<(usize, usize)>) -> T) -> T {
+        let (parameters, transitions) = (self.parameter_names(), self.transition_names());
+        let resolve = resolver(&self.species, &parameters, &transitions);
+        f(&resolve)
+    }
+
+    // Checks that the rate law of transition `t` compiles against this net,
+    // with a warning for expression laws, whose negative and NaN values are
+    // clamped to 0
+    pub fn check_rate_law(&self, t: usize) -> Result<Option<String>, ParseError> {
+        self.with_resolver(|resolve| Propensity::compile(&self.transitions[t].rate_law, resolve))?;
+        match &self.transitions[t].rate_law {
+            RateLaw::Expression(_) => Ok(Some("negative and NaN values are taken as 0".to_string())),
+            _otherwise => Ok(None),
+        }
+    }
+
+    pub fn check_observable(&self, i: usize) -> Result<(), ParseError> {
+        self.with_resolver(|resolve| compile(&self.observables[i].expression, resolve)).map(|_| ())
+    }
+
+    // The rates of the transitions, taken from the parameters they reference if any
+    pub fn effective_rates(&self, controls: &PlotControls) -> Vec<f32> {
+        self.transitions.iter().enumerate()
+            .map(|(i, t)| {
+                t.rate.as_ref()
+                    .and_then(|n| self.parameters.iter().find(|p| &p.name == n))
+                    .map(|p| p.value)
+                    .unwrap_or_else(|| controls.rates.get(i).cloned().unwrap_or(0.))
+            })
+            .collect()
+    }
+
+    // Values of the observables along a trajectory on [0, t1], one column per
+    // observable, with row i at time (i + 1) t1 / rows as in `rk_solve`.
+    // Invalid expressions give NaN.
+    pub fn observable_values(&self, yvals: &YVals, rates: &[f32], t1: f32) -> YVals {
+        let rates: Vec<f64> = rates.iter().map(|r| *r as f64).collect();
+        let parameters: Vec<f64> = self.parameters.iter().map(|p| p.value as f64).collect();
+        let mut out = Array::zeros((yvals.nrows(), self.observables.len()));
+        for (k, o) in self.observables.iter().enumerate() {
+            let f: Compiled = self.with_resolver(|resolve| compile(&o.expression, resolve))
+                .unwrap_or_else(|_| Box::new(|_| f64::NAN));
+            for (row, y) in yvals.outer_iter().enumerate() {
+                let pops: Vec<f64> = y.iter().map(|x| *x as f64).collect();
+                let t = ((row + 1) as f64) * (t1 as f64) / (yvals.nrows() as f64);
+                out[(row, k)] = f(&[&pops, &rates, &parameters, &[t]]) as f32;
+            }
+        }
+        out
     }
 
     #[cfg(target_arch = "wasm32")]
     pub fn plot(&self, controls: &PlotControls, canvas_id: &str)
             -> DrawResult<(),CanvasBackend> {
-        let steps_per_unit = STEPS as f32 / controls.xmax;
-        let yvals = self
-            .get_petri_data()
-            .solve(&Array::from(controls.rates.clone()),
-                   &Array::from(controls.init_vals.clone()),
-                   controls.xmax,
-                   STEPS);
-
         let backend = CanvasBackend::new(canvas_id).expect("cannot find canvas");
         let root = backend.into_drawing_area();
+        self.draw(controls, &root)?;
+        root.present()?;
+        Ok(())
+    }
+
+    // Draws the species and observables on `root`, which can be a canvas, an
+    // SVG document or a bitmap
+    pub fn draw<DB: DrawingBackend>(&self, controls: &PlotControls, root: &DrawingArea<DB, Shift>)
+            -> DrawResult<(),DB> {
+        let steps_per_unit = STEPS as f32 / controls.xmax;
+        let rates = self.effective_rates(controls);
+        let yvals = self.simulate(controls, STEPS);
+        let observables = self.observable_values(&yvals, &rates, controls.xmax);
+
         let font: FontDesc = ("sans-serif", 20.0).into();
 
         root.fill(&WHITE)?;
 
-        let mut chart = ChartBuilder::on(&root)
+        let mut chart = ChartBuilder::on(root)
             .caption(&self.name, font)
             .x_label_area_size(30)
             .y_label_area_size(30)
@@ -150,7 +464,7 @@ impl PetriNet {
                     (0..STEPS).map(|x| {
                         (
                             x as f32 / steps_per_unit,
-                            *yvals.get((x, i)).unwrap() as f32,
+                            *yvals.get((x, i)).unwrap(),
                         )
                     }),
                     &c,
@@ -159,12 +473,28 @@ impl PetriNet {
                 .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &c));
         }
 
+        let n = self.species.len();
+        for (k,o) in self.observables.iter().enumerate() {
+            let c = get_color(n + k);
+            chart
+                .draw_series(LineSeries::new(
+                    (0..STEPS).map(|x| {
+                        (
+                            x as f32 / steps_per_unit,
+                            observables[(x, k)],
+                        )
+                    }),
+                    &c,
+                ))?
+                .label(&o.name)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &c));
+        }
+
         chart
             .configure_series_labels()
             .border_style(&BLACK)
             .draw()?;
 
-        root.present()?;
         Ok(())
     }
 }
@@ -1,6 +1,17 @@
 use serde::{Deserialize, Serialize};
 use yew::prelude::*;
 use crate::petri::*;
+use crate::sensitivity::*;
+use crate::identifiability::*;
+use crate::patches::*;
+use crate::diffusion::*;
+use crate::stages::*;
+use crate::figure::*;
+use crate::utils::{download, download_url, canvas_png};
+use ndarray::s;
+use std::time::Duration;
+use yew::services::IntervalService;
+use yew::services::interval::IntervalTask;
 
 #[derive(Serialize, Deserialize, Properties)]
 pub struct PlotProps {
@@ -9,6 +20,13 @@ pub struct PlotProps {
 
     #[props(required)]
     pub controls: PlotControls,
+
+    // Set when `petri` is an expanded metapopulation model
+    pub patches: Option<Metapopulation>,
+
+    // Set when `petri` has k-stage transitions expanded, and their stages
+    // are plotted together
+    pub stages: Option<Staged>,
 }
 
 pub struct Plot {
@@ -17,9 +35,27 @@ pub struct Plot {
     mounted: bool
 }
 
+impl Plot {
+    fn figure(&self) -> Figure<'_> {
+        let PlotProps { petri, controls, patches, stages } = &self.props;
+        Figure { petri, controls, patches: patches.as_ref(), stages: stages.as_ref() }
+    }
+
+    fn draw(&self) {
+        self.figure().plot(CANVAS_ID).ok().unwrap()
+    }
+
+    fn file_name(&self, extension: &str) -> String {
+        let name = if self.props.petri.name.is_empty() { "plot" } else { self.props.petri.name.as_str() };
+        format!("{}.{}", name, extension)
+    }
+}
+
 pub enum PlotMsg {
     Draw,
-    LiveUpdating
+    LiveUpdating,
+    DownloadSvg,
+    DownloadPng,
 }
 
 const CANVAS_ID: &'static str = "CANVAS_ID";
@@ -40,19 +76,34 @@ impl Component for Plot {
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
             PlotMsg::Draw => {
-                self.props.petri.plot(&self.props.controls, CANVAS_ID).ok().unwrap();
+                self.draw();
                 false
             }
             PlotMsg::LiveUpdating => {
                 self.live_updating ^= true;
                 true
             }
+            PlotMsg::DownloadSvg => {
+                if let Ok(svg) = self.figure().to_svg(FIGURE_SIZE) {
+                    download(&self.file_name("svg"), "image/svg+xml", &svg);
+                }
+                false
+            }
+            PlotMsg::DownloadPng => {
+                // The canvas has the same picture, and the bitmap backend
+                // is not available in the browser
+                self.draw();
+                if let Some(url) = canvas_png(CANVAS_ID) {
+                    download_url(&self.file_name("png"), &url);
+                }
+                false
+            }
         }
     }
 
     fn view(&self) -> Html<Self> {
         if self.mounted && self.live_updating {
-            self.props.petri.plot(&self.props.controls, CANVAS_ID).ok().unwrap();
+            self.draw();
         }
         html! {
             <>
@@ -69,6 +120,8 @@ impl Component for Plot {
                     </>
                 }
             }}
+                <button style="width:100px;margin-right:30px" onclick=|_| PlotMsg::DownloadSvg>{"SVG"}</button>
+                <button style="width:100px;margin-right:30px" onclick=|_| PlotMsg::DownloadPng>{"PNG"}</button>
             </>
         }
     }
@@ -80,7 +133,294 @@ impl Component for Plot {
 
     fn mounted(&mut self) -> ShouldRender {
         self.mounted = true;
-        self.props.petri.plot(&self.props.controls, CANVAS_ID).ok().unwrap();
+        self.draw();
         false
     }
 }
+
+pub struct SensitivityPlot {
+    props: PlotProps,
+    results: Option<(SobolIndices, MorrisEffects)>,
+    species: usize
+}
+
+pub enum SensitivityMsg {
+    Run,
+    SelectSpecies(usize)
+}
+
+const SOBOL_CANVAS_ID: &str = "SOBOL_CANVAS_ID";
+const MORRIS_CANVAS_ID: &str = "MORRIS_CANVAS_ID";
+const SOBOL_SAMPLES: usize = 64;
+const MORRIS_TRAJECTORIES: usize = 20;
+const MORRIS_LEVELS: usize = 4;
+
+fn csv_data_uri(csv: &str) -> String {
+    format!("data:text/csv;base64,{}", base64::encode(csv))
+}
+
+impl SensitivityPlot {
+    fn draw(&self) {
+        if let Some((sobol, morris)) = &self.results {
+            let petri = &self.props.petri;
+            plot_bars(petri, &sobol.factors, &[("first order", &sobol.first_order), ("total", &sobol.total)],
+                      self.species, SOBOL_CANVAS_ID).ok().unwrap();
+            plot_bars(petri, &morris.factors, &[("mu*", &morris.mu_star), ("sigma", &morris.sigma)],
+                      self.species, MORRIS_CANVAS_ID).ok().unwrap();
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Component for SensitivityPlot {
+    type Message = SensitivityMsg;
+    type Properties = PlotProps;
+
+    fn create(p: Self::Properties, _: ComponentLink<Self>) -> Self {
+        SensitivityPlot {
+            props: p,
+            results: None,
+            species: 0
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            SensitivityMsg::Run => {
+                let mut rng = rand::thread_rng();
+                let (petri, controls) = (&self.props.petri, &self.props.controls);
+                self.results = Some((
+                    sobol_indices(petri, controls, SOBOL_SAMPLES, &mut rng),
+                    morris_effects(petri, controls, MORRIS_TRAJECTORIES, MORRIS_LEVELS, &mut rng)
+                ));
+            }
+            SensitivityMsg::SelectSpecies(i) => {
+                self.species = i;
+            }
+        }
+        self.draw();
+        true
+    }
+
+    fn view(&self) -> Html<Self> {
+        html! {
+            <>
+            <label>{"Sensitivity Analysis:"}</label>
+            <button style="width:200px;margin-right:30px" onclick=|_| SensitivityMsg::Run>{"Run Sensitivity"}</button>
+            <select onchange=|c| match c {
+                ChangeData::Select(s) => SensitivityMsg::SelectSpecies(s.selected_index().unwrap_or(0) as usize),
+                _otherwise => SensitivityMsg::SelectSpecies(0)
+            }>
+            { for self.props.petri.species.iter().enumerate().map(|(i,s)| {
+                html!{ <option selected={i == self.species}>{s}</option> }
+            })}
+            </select>
+            { match &self.results {
+                Some((sobol, morris)) => html!{
+                    <>
+                    <a class="menu-action" href={csv_data_uri(&sobol.to_csv(&self.props.petri))} download="sobol.csv">{"Download Sobol"}</a>
+                    <a class="menu-action" href={csv_data_uri(&morris.to_csv(&self.props.petri))} download="morris.csv">{"Download Morris"}</a>
+                    </>
+                },
+                None => html!{ <></> }
+            }}
+            <canvas height="300px" width="500px" class="plot" id={SOBOL_CANVAS_ID}> </canvas>
+            <canvas height="300px" width="500px" class="plot" id={MORRIS_CANVAS_ID}> </canvas>
+            </>
+        }
+    }
+
+    fn change(&mut self, p: Self::Properties) -> ShouldRender {
+        self.props = p;
+        self.results = None;
+        if self.species >= self.props.petri.species.len() {
+            self.species = 0;
+        }
+        true
+    }
+}
+
+pub struct IdentifiabilityCheck {
+    props: PlotProps,
+    observed: Vec<bool>,
+    report: Option<String>
+}
+
+pub enum IdentifiabilityMsg {
+    ToggleObserved(usize),
+    Check
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Component for IdentifiabilityCheck {
+    type Message = IdentifiabilityMsg;
+    type Properties = PlotProps;
+
+    fn create(p: Self::Properties, _: ComponentLink<Self>) -> Self {
+        IdentifiabilityCheck {
+            observed: vec![false; p.petri.species.len()],
+            props: p,
+            report: None
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            IdentifiabilityMsg::ToggleObserved(i) => {
+                self.observed[i] ^= true;
+            }
+            IdentifiabilityMsg::Check => {
+                let observed: Vec<usize> = (0..self.observed.len()).filter(|i| self.observed[*i]).collect();
+                let report = identifiability(&self.props.petri, &self.props.controls.init_vals,
+                                             &observed, &mut rand::thread_rng());
+                self.report = Some(match report {
+                    Ok(r) => r.to_string(),
+                    Err(e) => e
+                });
+            }
+        }
+        true
+    }
+
+    fn view(&self) -> Html<Self> {
+        html! {
+            <>
+            <label>{"Identifiability:"}</label>
+            { for self.props.petri.species.iter().enumerate().map(|(i,s)| {
+                html!{
+                    <label class="control-label">
+                        <input type="checkbox" checked={self.observed[i]}
+                            onclick=|_| IdentifiabilityMsg::ToggleObserved(i)> </input>
+                        {s}
+                    </label>
+                }
+            })}
+            <button style="width:200px;margin-right:30px" onclick=|_| IdentifiabilityMsg::Check>{"Check Identifiability"}</button>
+            { match &self.report {
+                Some(r) => html!{ <pre>{r}</pre> },
+                None => html!{ <></> }
+            }}
+            </>
+        }
+    }
+
+    fn change(&mut self, p: Self::Properties) -> ShouldRender {
+        self.observed.resize(p.petri.species.len(), false);
+        self.props = p;
+        self.report = None;
+        true
+    }
+}
+
+#[derive(Properties)]
+pub struct DiffusionProps {
+    #[props(required)]
+    pub petri: PetriNet,
+
+    #[props(required)]
+    pub controls: PlotControls,
+
+    #[props(required)]
+    pub diffusion: Diffusion,
+}
+
+pub struct DiffusionPlot {
+    props: DiffusionProps,
+    link: ComponentLink<DiffusionPlot>,
+    frames: Option<Frames>,
+    frame: usize,
+    species: usize,
+    task: Option<IntervalTask>
+}
+
+pub enum DiffusionMsg {
+    Run,
+    Tick,
+    SelectSpecies(usize)
+}
+
+const DIFFUSION_CANVAS_ID: &str = "DIFFUSION_CANVAS_ID";
+const DIFFUSION_FRAMES: usize = 60;
+const FRAME_MILLIS: u64 = 100;
+
+impl DiffusionPlot {
+    fn draw(&self) {
+        if let Some(frames) = &self.frames {
+            let vmax = frames.slice(s![.., .., self.species]).fold(0f32, |m, x| m.max(*x));
+            let t = (self.frame + 1) as f32 * self.props.controls.xmax / DIFFUSION_FRAMES as f32;
+            let title = format!("{} at t = {:.2}", self.props.petri.species[self.species], t);
+            self.props.diffusion.plot_frame(frames, self.frame, self.species, vmax, &title, DIFFUSION_CANVAS_ID)
+                .ok().unwrap();
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Component for DiffusionPlot {
+    type Message = DiffusionMsg;
+    type Properties = DiffusionProps;
+
+    fn create(p: Self::Properties, link: ComponentLink<Self>) -> Self {
+        DiffusionPlot {
+            props: p,
+            link,
+            frames: None,
+            frame: 0,
+            species: 0,
+            task: None
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            DiffusionMsg::Run => {
+                let (petri, controls) = (&self.props.petri, &self.props.controls);
+                let init = self.props.diffusion.initial_state(&controls.init_vals, &mut rand::thread_rng());
+                let rates = petri.effective_rates(controls);
+                self.frames = Some(self.props.diffusion.solve(&petri.get_petri_data(), &rates, &init,
+                                                              controls.xmax, DIFFUSION_FRAMES));
+                self.frame = 0;
+                let tick = self.link.send_back(|_| DiffusionMsg::Tick);
+                self.task = Some(IntervalService::new().spawn(Duration::from_millis(FRAME_MILLIS), tick));
+            }
+            DiffusionMsg::Tick => {
+                self.frame = (self.frame + 1) % DIFFUSION_FRAMES;
+                self.draw();
+                return false;
+            }
+            DiffusionMsg::SelectSpecies(i) => {
+                self.species = i;
+            }
+        }
+        self.draw();
+        true
+    }
+
+    fn view(&self) -> Html<Self> {
+        html! {
+            <>
+            <label>{"Reaction-Diffusion:"}</label>
+            <button style="width:200px;margin-right:30px" onclick=|_| DiffusionMsg::Run>{"Run Diffusion"}</button>
+            <select onchange=|c| match c {
+                ChangeData::Select(s) => DiffusionMsg::SelectSpecies(s.selected_index().unwrap_or(0) as usize),
+                _otherwise => DiffusionMsg::SelectSpecies(0)
+            }>
+            { for self.props.petri.species.iter().enumerate().map(|(i,s)| {
+                html!{ <option selected={i == self.species}>{s}</option> }
+            })}
+            </select>
+            <canvas height="400px" width="500px" class="plot" id={DIFFUSION_CANVAS_ID}> </canvas>
+            </>
+        }
+    }
+
+    fn change(&mut self, p: Self::Properties) -> ShouldRender {
+        self.props = p;
+        self.frames = None;
+        self.task = None;
+        if self.species >= self.props.petri.species.len() {
+            self.species = 0;
+        }
+        true
+    }
+}
@@ -0,0 +1,256 @@
+use crate::petri::*;
+use crate::kinetics::*;
+use crate::xml::*;
+use crate::interchange::{delay_warnings, timing_warnings, unexported_parts, Import};
+
+// PNML (ISO/IEC 15909-2) place/transition nets. Species are places, with
+// their initial values as the initial marking, and transitions are
+// transitions, with an arc for each nonzero entry of the input and output
+// vectors, weighted by an inscription. Coordinates in the layout of the net
+// are written as graphics, and the graphics of imported files are kept in it.
+//
+// Rates are not part of place/transition nets, so they are written in a
+// `<toolspecific>` element of each transition, which other tools ignore.
+// Inhibitor arcs are written with the `<type value="inhibitor"/>` used by
+// PIPE. Read arcs have no counterpart and are written as a pair of arcs to
+// and from the place, which is how other tools draw them but changes the
+// input and output vectors read back.
+
+const PNML_NAMESPACE: &str = "http://www.pnml.org/version-2009/grammar/pnml";
+const PT_NET: &str = "http://www.pnml.org/version-2009/grammar/ptnet";
+const TOOL: &str = "ez-petri";
+const TOOL_VERSION: &str = "1.0";
+
+fn name(text: &str) -> Element {
+    Element::new("name").with_child(Element::new("text").with_text(text))
+}
+
+fn graphics(position: Option<(f32, f32)>) -> Option<Element> {
+    position.map(|(x, y)| Element::new("graphics").with_child(Element::new("position").with_attr("x", x).with_attr("y", y)))
+}
+
+fn with_optional(e: Element, child: Option<Element>) -> Element {
+    match child {
+        Some(c) => e.with_child(c),
+        None => e,
+    }
+}
+
+// Writes `petri` as a PNML place/transition net, with the initial values and
+// rates of `controls`, and warnings for what could not be written
+pub fn write_pnml(petri: &PetriNet, controls: &PlotControls) -> (String, Vec<String>) {
+    let mut warnings = vec![];
+    let mut used = vec![];
+    let place_ids: Vec<String> = petri.species.iter().map(|s| unique_id(s, &mut used)).collect();
+    let transition_ids: Vec<String> = petri.transitions.iter().map(|t| unique_id(t.name.as_str(), &mut used)).collect();
+    let rates = petri.effective_rates(controls);
+
+    let mut page = Element::new("page").with_attr("id", unique_id("page", &mut used));
+    for (s, species) in petri.species.iter().enumerate() {
+        let initial = controls.init_vals.get(s).cloned().unwrap_or(0.);
+        if initial.fract() != 0. || initial < 0. {
+            warnings.push(format!("the initial value of {} is rounded to a whole number", species));
+        }
+        let mut place = Element::new("place").with_attr("id", &place_ids[s]).with_child(name(species));
+        place = with_optional(place, graphics(petri.layout.species_position(species)));
+        let marking = initial.round().max(0.) as i64;
+        if marking != 0 {
+            place = place.with_child(Element::new("initialMarking").with_child(Element::new("text").with_text(&marking.to_string())));
+        }
+        page = page.with_child(place);
+    }
+    for (i, t) in petri.transitions.iter().enumerate() {
+        let mut transition = Element::new("transition").with_attr("id", &transition_ids[i]).with_child(name(&t.name));
+        transition = with_optional(transition, graphics(petri.layout.transition_position(&t.name)));
+        let tool = Element::new("toolspecific").with_attr("tool", TOOL).with_attr("version", TOOL_VERSION)
+            .with_child(Element::new("rate").with_text(&rates[i].to_string()));
+        page = page.with_child(transition.with_child(tool));
+
+        if !matches!(t.rate_law, RateLaw::MassAction) {
+            warnings.push(format!("transition {}: only the rate is exported, not the {} rate law", t.name,
+                                  RATE_LAWS[t.rate_law.to_idx()]));
+        }
+        if (0..petri.species.len()).any(|s| t.arc(IO::Read, s) > 0) {
+            warnings.push(format!("transition {}: read arcs are exported as arcs to and from the place", t.name));
+        }
+        warnings.extend(timing_warnings(t, "not exported"));
+        warnings.extend(delay_warnings(petri, i, "not exported"));
+    }
+
+    let mut arcs = vec![];
+    for (i, t) in petri.transitions.iter().enumerate() {
+        for (s, place) in place_ids.iter().enumerate() {
+            let (input, output) = (t.arc(IO::Input, s) + t.arc(IO::Read, s), t.arc(IO::Output, s) + t.arc(IO::Read, s));
+            if input > 0 {
+                arcs.push((place, &transition_ids[i], input, false));
+            }
+            if output > 0 {
+                arcs.push((&transition_ids[i], place, output, false));
+            }
+            if t.arc(IO::Inhibit, s) > 0 {
+                arcs.push((place, &transition_ids[i], t.arc(IO::Inhibit, s), true));
+            }
+        }
+    }
+    for (source, target, weight, inhibitor) in arcs {
+        let id = unique_id(&format!("{}_{}", source, target), &mut used);
+        let mut arc = Element::new("arc").with_attr("id", id).with_attr("source", source).with_attr("target", target);
+        if weight != 1 {
+            arc = arc.with_child(Element::new("inscription").with_child(Element::new("text").with_text(&weight.to_string())));
+        }
+        if inhibitor {
+            arc = arc.with_child(Element::new("type").with_attr("value", "inhibitor"));
+        }
+        page = page.with_child(arc);
+    }
+
+    warnings.extend(unexported_parts(petri));
+
+    let net = Element::new("net")
+        .with_attr("id", unique_id(&petri.name, &mut used))
+        .with_attr("type", PT_NET)
+        .with_child(name(&petri.name))
+        .with_child(page);
+    (Element::new("pnml").with_attr("xmlns", PNML_NAMESPACE).with_child(net).to_document(), warnings)
+}
+
+// The text of a label such as `<name>` or `<inscription>`. PIPE writes
+// values as `<value>Default,3</value>` rather than `<text>3</text>`.
+fn label(e: &Element, label: &str) -> Option<String> {
+    let l = e.child(label)?;
+    match (l.child("text"), l.child("value")) {
+        (Some(t), _) => Some(t.text()),
+        (None, Some(v)) => v.text().rsplit(',').next().map(|v| v.trim().to_string()),
+        (None, None) => None,
+    }
+}
+
+fn position(e: &Element) -> Option<(f32, f32)> {
+    let p = e.child("graphics")?.child("position")?;
+    Some((p.attr("x")?.trim().parse().ok()?, p.attr("y")?.trim().parse().ok()?))
+}
+
+// The places, transitions and arcs of a page and the pages inside it
+fn collect<'a>(page: &'a Element, nodes: &mut Vec<&'a Element>, warnings: &mut Vec<String>) {
+    for e in page.elements() {
+        match e.local_name() {
+            "place" | "transition" | "arc" => nodes.push(e),
+            "page" => collect(e, nodes, warnings),
+            "referencePlace" | "referenceTransition" => {
+                warnings.push(format!("<{}> {} is not supported and was skipped", e.local_name(), e.attr("id").unwrap_or("")));
+            }
+            _otherwise => { }
+        }
+    }
+}
+
+// Reads a PNML document, reporting the constructs that could not be
+// represented in the warnings. Only the first net of the document is read.
+pub fn read_pnml(src: &str) -> Result<Import, String> {
+    let root = parse(src).map_err(|e| e.to_string())?;
+    if root.local_name() != "pnml" {
+        return Err(format!("expected a <pnml> document, found <{}>", root.name));
+    }
+    let mut warnings = vec![];
+    let net = root.child("net").ok_or("the document has no <net>")?;
+    if root.children_named("net").count() > 1 {
+        warnings.push("only the first of the nets in the document was read".to_string());
+    }
+    match net.attr("type") {
+        Some(t) if t.trim_end_matches('#').ends_with("ptnet") => { }
+        Some(t) => warnings.push(format!("nets of type {} are read as place/transition nets", t)),
+        None => warnings.push("the net has no type, and is read as a place/transition net".to_string()),
+    }
+
+    let mut nodes = vec![];
+    collect(net, &mut nodes, &mut warnings);
+
+    // Distinct names for the places and for the transitions, from their
+    // names if they have one and their ids otherwise
+    let named = |kind: &str| -> Vec<(String, String, &Element)> {
+        let mut taken: Vec<String> = vec![];
+        nodes.iter().filter(|e| e.local_name() == kind)
+            .filter_map(|e| {
+                let id = e.attr("id")?.to_string();
+                let name = label(e, "name").filter(|n| !n.is_empty() && !taken.contains(n)).unwrap_or_else(|| id.clone());
+                taken.push(name.clone());
+                Some((id, name, *e))
+            })
+            .collect()
+    };
+    let places = named("place");
+    let transitions = named("transition");
+    if nodes.iter().any(|e| e.local_name() != "arc" && e.attr("id").is_none()) {
+        warnings.push("places or transitions without an id were skipped".to_string());
+    }
+
+    let species: Vec<String> = places.iter().map(|(_, n, _)| n.clone()).collect();
+    let n = species.len();
+    let mut petri = PetriNet::new(&label(net, "name").unwrap_or_else(|| net.attr("id").unwrap_or("").to_string()), species.clone(), vec![]);
+    let mut controls = PlotControls::empty();
+
+    let parse_count = |what: String, text: Option<String>, warnings: &mut Vec<String>| -> i32 {
+        match text.map(|t| t.trim().parse::<i32>()) {
+            None => 0,
+            Some(Ok(k)) if k >= 0 => k,
+            Some(_) => {
+                warnings.push(format!("{} is not a whole number, and was read as 0", what));
+                0
+            }
+        }
+    };
+    for (_, name, place) in places.iter() {
+        let marking = parse_count(format!("the initial marking of {}", name), label(place, "initialMarking"), &mut warnings);
+        controls.init_vals.push(marking as f32);
+        if let Some(p) = position(place) {
+            petri.layout.species.push((name.clone(), p));
+        }
+        if place.child("capacity").is_some() {
+            warnings.push(format!("the capacity of place {} is not supported", name));
+        }
+    }
+    for (_, name, transition) in transitions.iter() {
+        petri.transitions.push(Transition::new(name, vec![0; n], vec![0; n]));
+        let rate = transition.children_named("toolspecific")
+            .filter(|t| t.attr("tool") == Some(TOOL))
+            .filter_map(|t| t.child("rate"))
+            .chain(transition.child("rate"))
+            .filter_map(|r| label(r, "value").or_else(|| Some(r.text())))
+            .find_map(|r| r.parse::<f32>().ok());
+        controls.rates.push(rate.unwrap_or(1.));
+        if let Some(p) = position(transition) {
+            petri.layout.transitions.push((name.clone(), p));
+        }
+    }
+
+    for arc in nodes.iter().filter(|e| e.local_name() == "arc") {
+        let place = |id: Option<&str>| places.iter().position(|(p, _, _)| Some(p.as_str()) == id);
+        let transition = |id: Option<&str>| transitions.iter().position(|(t, _, _)| Some(t.as_str()) == id);
+        let (source, target) = (arc.attr("source"), arc.attr("target"));
+        let id = arc.attr("id").unwrap_or("");
+        let weight = match label(arc, "inscription") {
+            Some(w) => parse_count(format!("the weight of arc {}", id), Some(w), &mut warnings),
+            None => 1,
+        };
+        let kind = arc.child("type").and_then(|t| t.attr("value")).unwrap_or("normal");
+        let (d, s, t) = match (place(source), transition(target), transition(source), place(target)) {
+            (Some(s), Some(t), _, _) => match kind {
+                "inhibitor" => (IO::Inhibit, s, t),
+                "normal" => (IO::Input, s, t),
+                other => {
+                    warnings.push(format!("arc {} of type {} was read as an ordinary arc", id, other));
+                    (IO::Input, s, t)
+                }
+            },
+            (_, _, Some(t), Some(s)) => (IO::Output, s, t),
+            _otherwise => {
+                warnings.push(format!("arc {} does not connect a place and a transition, and was skipped", id));
+                continue;
+            }
+        };
+        let tr = &mut petri.transitions[t];
+        tr.set_arc(d, s, tr.arc(d, s) + weight);
+    }
+
+    Ok(Import::new(petri, controls, warnings))
+}
@@ -0,0 +1,58 @@
+use std::collections::{HashMap, VecDeque};
+use crate::petri::*;
+
+// The markings reachable from an initial marking under the firing rule of
+// `Transition::enabled` and `Transition::fire`, so read arcs and inhibitor
+// arcs restrict which transitions can fire without changing their effect.
+pub struct ReachabilityGraph {
+    pub markings: Vec<Vec<i32>>,
+    // (from, transition, to), as indices into `markings` and the transitions
+    pub edges: Vec<(usize, usize, usize)>,
+    // False if the search stopped at the state limit before exhausting the graph
+    pub complete: bool,
+}
+
+impl ReachabilityGraph {
+    // Reachable markings in which no transition is enabled
+    pub fn deadlocks(&self, petri: &PetriNet) -> Vec<&[i32]> {
+        self.markings.iter()
+            .filter(|m| !petri.transitions.iter().any(|t| t.enabled(m)))
+            .map(|m| &m[..])
+            .collect()
+    }
+}
+
+// Breadth first search, keeping at most `max_states` markings. Edges to
+// markings beyond the limit are dropped.
+pub fn reachability_graph(petri: &PetriNet, initial: &[i32], max_states: usize) -> ReachabilityGraph {
+    let mut markings = vec![initial.to_vec()];
+    let mut index: HashMap<Vec<i32>, usize> = HashMap::new();
+    index.insert(initial.to_vec(), 0);
+    let mut edges = vec![];
+    let mut queue = VecDeque::new();
+    queue.push_back(0);
+    let mut complete = true;
+    while let Some(i) = queue.pop_front() {
+        for (t, trans) in petri.transitions.iter().enumerate() {
+            if !trans.enabled(&markings[i]) {
+                continue;
+            }
+            let next = trans.fire(&markings[i]);
+            let j = match index.get(&next) {
+                Some(j) => *j,
+                None if markings.len() < max_states => {
+                    index.insert(next.clone(), markings.len());
+                    markings.push(next);
+                    queue.push_back(markings.len() - 1);
+                    markings.len() - 1
+                }
+                None => {
+                    complete = false;
+                    continue;
+                }
+            };
+            edges.push((i, t, j));
+        }
+    }
+    ReachabilityGraph { markings, edges, complete }
+}
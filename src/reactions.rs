@@ -0,0 +1,686 @@
+use std::fmt;
+use crate::petri::*;
+use crate::kinetics::*;
+use crate::expr::*;
+use crate::interchange::{fit_initial_values, timing_warnings};
+
+// A plain text format for nets, one declaration per line, e.g.
+//
+//     model SIR
+//     species S, I, R
+//     S = 99
+//     I = 1
+//     param beta = 0.003 in [0, 0.01] unit "1/day"
+//     infection: S + I -> 2 I @ beta
+//     recovery: I -> R @ 0.1
+//     treatment: I + [E] -> R @ 0.05 michaelis(I, 20) unless R >= 30
+//     observe prevalence = I / (S + I + R)
+//
+// A reaction has a name, the inputs and outputs with their weights, with
+// `0` or nothing for none and read arcs in brackets, and optionally:
+//
+// - `@` and a rate, either a number or a parameter, followed by a rate law
+//   other than mass action: `frequency`, `michaelis(S, km)`, `hill(S, k, n)`
+//   or an expression in braces, e.g. `@ {k * S / (1 + S)}`
+// - `unless S >= k`, for inhibitor arcs, separated by commas
+// - `after d` for an output delay and `stages k` for k stages
+//
+// A parameter may be followed by its range for sensitivity analysis, `in
+// [min, max]`, and by its unit in double quotes after `unit`.
+//
+// Species are declared by the `species` line or by their first use, in that
+// order. Names that are not identifiers are written in double quotes, and
+// `#` starts a comment. Events, schedules and firing time distributions have
+// no syntax here, and are reported when writing.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DslError {
+    // One-based line and column in the source
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for DslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+const KEYWORDS: [&str; 10] = ["model", "species", "param", "observe", "unless", "after", "stages", "frequency", "michaelis", "hill"];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    // A name in double quotes, which is never a keyword
+    Quoted(String),
+    Num(f64),
+    Sym(&'static str),
+    // The source between braces, and the position after the opening brace
+    Braced(String, usize),
+}
+
+const SYMBOLS: [&str; 10] = ["->", ">=", ":", "+", "@", "=", ",", "[", "]", "("];
+
+// The line up to a comment, which starts at a `#` outside quotes and braces
+fn strip_comment(line: &str) -> &str {
+    let (mut quoted, mut braces) = (false, 0);
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '{' if !quoted => braces += 1,
+            '}' if !quoted => braces -= 1,
+            '#' if !quoted && braces <= 0 => return &line[..i],
+            _otherwise => { }
+        }
+    }
+    line
+}
+
+struct Line {
+    number: usize,
+    tokens: Vec<(usize, Token)>,
+    // Position of the end of the line, for errors about missing tokens
+    end: usize,
+    i: usize,
+}
+
+impl Line {
+    fn error<T>(&self, pos: usize, message: impl Into<String>) -> Result<T, DslError> {
+        Err(DslError { line: self.number, column: pos + 1, message: message.into() })
+    }
+
+    fn lex(number: usize, src: &str) -> Result<Self, DslError> {
+        let chars: Vec<char> = src.chars().collect();
+        let mut line = Line { number, tokens: vec![], end: chars.len(), i: 0 };
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let start = i;
+            if c.is_whitespace() {
+                i += 1;
+                continue;
+            } else if c.is_alphabetic() || c == '_' {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                line.tokens.push((start, Token::Name(chars[start..i].iter().collect())));
+            } else if c.is_ascii_digit() || c == '.' || (c == '-' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit() || *d == '.')) {
+                if c == '-' {
+                    i += 1;
+                }
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                // An exponent, if the `e` is followed by digits
+                if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                    let sign = if i + 1 < chars.len() && (chars[i + 1] == '+' || chars[i + 1] == '-') { 1 } else { 0 };
+                    if i + 1 + sign < chars.len() && chars[i + 1 + sign].is_ascii_digit() {
+                        i += 1 + sign;
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                let text: String = chars[start..i].iter().collect();
+                match text.parse() {
+                    Ok(x) => line.tokens.push((start, Token::Num(x))),
+                    Err(_) => return line.error(start, format!("invalid number {}", text)),
+                }
+            } else if c == '"' {
+                match chars[i + 1..].iter().position(|c| *c == '"') {
+                    Some(len) => {
+                        line.tokens.push((start, Token::Quoted(chars[i + 1..i + 1 + len].iter().collect())));
+                        i += len + 2;
+                    }
+                    None => return line.error(start, "unterminated name"),
+                }
+            } else if c == '{' {
+                let mut depth = 0;
+                let close = chars[i..].iter().position(|c| {
+                    depth += match c { '{' => 1, '}' => -1, _ => 0 };
+                    depth == 0
+                });
+                match close {
+                    Some(len) => {
+                        line.tokens.push((start, Token::Braced(chars[i + 1..i + len].iter().collect(), i + 1)));
+                        i += len + 1;
+                    }
+                    None => return line.error(start, "unterminated {"),
+                }
+            } else if c == ')' {
+                line.tokens.push((start, Token::Sym(")")));
+                i += 1;
+            } else if c == '∅' {
+                line.tokens.push((start, Token::Num(0.)));
+                i += 1;
+            } else {
+                let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+                match SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+                    Some(s) => {
+                        line.tokens.push((start, Token::Sym(s)));
+                        i += s.chars().count();
+                    }
+                    None => return line.error(start, format!("unexpected {}", c)),
+                }
+            }
+        }
+        Ok(line)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.i).map(|(_, t)| t)
+    }
+
+    fn pos(&self) -> usize {
+        self.tokens.get(self.i).map(|(p, _)| *p).unwrap_or(self.end)
+    }
+
+    fn at_end(&self) -> bool {
+        self.i >= self.tokens.len()
+    }
+
+    fn at_sym(&self, s: &str) -> bool {
+        matches!(self.peek(), Some(Token::Sym(t)) if *t == s)
+    }
+
+    fn at_keyword(&self, k: &str) -> bool {
+        matches!(self.peek(), Some(Token::Name(n)) if n == k)
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            None => "the end of the line".to_string(),
+            Some(Token::Name(n)) | Some(Token::Quoted(n)) => n.clone(),
+            Some(Token::Num(x)) => x.to_string(),
+            Some(Token::Sym(s)) => s.to_string(),
+            Some(Token::Braced(_, _)) => "{".to_string(),
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), DslError> {
+        if self.at_sym(s) {
+            self.i += 1;
+            Ok(())
+        } else {
+            self.error(self.pos(), format!("expected {}, found {}", s, self.found()))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, DslError> {
+        match self.peek().cloned() {
+            Some(Token::Name(n)) | Some(Token::Quoted(n)) => {
+                self.i += 1;
+                Ok(n)
+            }
+            _otherwise => self.error(self.pos(), format!("expected a name, found {}", self.found())),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, DslError> {
+        match self.peek() {
+            Some(Token::Num(x)) => {
+                let x = *x;
+                self.i += 1;
+                Ok(x)
+            }
+            _otherwise => self.error(self.pos(), format!("expected a number, found {}", self.found())),
+        }
+    }
+
+    fn count(&mut self) -> Result<i32, DslError> {
+        let pos = self.pos();
+        let x = self.number()?;
+        if x.fract() != 0. || x < 0. || x > i32::MAX as f64 {
+            return self.error(pos, format!("expected a whole number, found {}", x));
+        }
+        Ok(x as i32)
+    }
+
+    fn finish(&self) -> Result<(), DslError> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            self.error(self.pos(), format!("unexpected {}", self.found()))
+        }
+    }
+}
+
+enum Rate {
+    Value(f32),
+    Parameter(String, usize),
+}
+
+struct Reaction {
+    name: String,
+    line: usize,
+    // (species, weight) for the input, output, read and inhibitor arcs
+    arcs: Vec<(IO, usize, i32)>,
+    rate: Option<Rate>,
+    law: RateLaw,
+    delay: f32,
+    stages: u32,
+}
+
+#[derive(Default)]
+struct Model {
+    name: String,
+    species: Vec<String>,
+    init_vals: Vec<Option<f32>>,
+    parameters: Vec<Parameter>,
+    observables: Vec<Observable>,
+    reactions: Vec<Reaction>,
+}
+
+impl Model {
+    fn species(&mut self, name: &str) -> usize {
+        match self.species.iter().position(|s| s == name) {
+            Some(s) => s,
+            None => {
+                self.species.push(name.to_string());
+                self.init_vals.push(None);
+                self.species.len() - 1
+            }
+        }
+    }
+
+    // A side of a reaction: `0`, nothing, or terms `k S` and `[k S]` joined by `+`
+    fn side(&mut self, l: &mut Line, d: IO, arcs: &mut Vec<(IO, usize, i32)>) -> Result<(), DslError> {
+        if matches!(l.peek(), Some(Token::Num(x)) if *x == 0.) {
+            l.i += 1;
+            return Ok(());
+        }
+        if l.at_end() || l.at_sym("->") || l.at_sym("@") {
+            return Ok(());
+        }
+        loop {
+            let read = l.at_sym("[");
+            if read {
+                l.i += 1;
+            }
+            let k = if matches!(l.peek(), Some(Token::Num(_))) { l.count()? } else { 1 };
+            let s = self.species(&l.name()?);
+            if read {
+                l.expect("]")?;
+                if let IO::Output = d {
+                    return l.error(l.pos(), "read arcs go on the left of ->");
+                }
+            }
+            arcs.push((if read { IO::Read } else { d }, s, k));
+            if !l.at_sym("+") {
+                return Ok(());
+            }
+            l.i += 1;
+        }
+    }
+
+    // An expression in braces, checked for syntax
+    fn expression(&self, l: &mut Line) -> Result<String, DslError> {
+        match l.peek().cloned() {
+            Some(Token::Braced(src, start)) => {
+                l.i += 1;
+                match Expr::parse(&src) {
+                    Ok(_) => Ok(src.trim().to_string()),
+                    Err(e) => l.error(start + e.pos, e.message),
+                }
+            }
+            _otherwise => l.error(l.pos(), format!("expected {{, found {}", l.found())),
+        }
+    }
+
+    fn reaction(&mut self, l: &mut Line) -> Result<(), DslError> {
+        let pos = l.pos();
+        let name = l.name()?;
+        if self.reactions.iter().any(|r| r.name == name) {
+            return l.error(pos, format!("the reaction {} is already defined", name));
+        }
+        l.expect(":")?;
+        let mut arcs = vec![];
+        self.side(l, IO::Input, &mut arcs)?;
+        l.expect("->")?;
+        self.side(l, IO::Output, &mut arcs)?;
+        let mut reaction = Reaction { name, line: l.number, arcs, rate: None, law: RateLaw::MassAction, delay: 0., stages: 0 };
+        if l.at_sym("@") {
+            l.i += 1;
+            reaction.rate = match l.peek().cloned() {
+                Some(Token::Num(x)) => {
+                    l.i += 1;
+                    Some(Rate::Value(x as f32))
+                }
+                Some(Token::Name(n)) if !KEYWORDS.contains(&n.as_str()) => {
+                    l.i += 1;
+                    Some(Rate::Parameter(n, l.tokens[l.i - 1].0))
+                }
+                Some(Token::Quoted(n)) => {
+                    l.i += 1;
+                    Some(Rate::Parameter(n, l.tokens[l.i - 1].0))
+                }
+                _otherwise => None,
+            };
+            reaction.law = if l.at_keyword("frequency") {
+                l.i += 1;
+                RateLaw::FrequencyDependent
+            } else if l.at_keyword("michaelis") || l.at_keyword("hill") {
+                let hill = l.at_keyword("hill");
+                l.i += 1;
+                l.expect("(")?;
+                let substrate = self.species(&l.name()?);
+                l.expect(",")?;
+                let k = l.number()? as f32;
+                let n = if hill {
+                    l.expect(",")?;
+                    Some(l.number()? as f32)
+                } else {
+                    None
+                };
+                l.expect(")")?;
+                match n {
+                    Some(n) => RateLaw::Hill { substrate, k, n },
+                    None => RateLaw::MichaelisMenten { substrate, km: k },
+                }
+            } else if let Some(Token::Braced(_, _)) = l.peek() {
+                RateLaw::Expression(self.expression(l)?)
+            } else if reaction.rate.is_none() {
+                return l.error(l.pos(), format!("expected a rate or a rate law, found {}", l.found()));
+            } else {
+                RateLaw::MassAction
+            };
+        }
+        while !l.at_end() {
+            if l.at_keyword("unless") {
+                l.i += 1;
+                loop {
+                    let s = self.species(&l.name()?);
+                    l.expect(">=")?;
+                    let pos = l.pos();
+                    let k = l.count()?;
+                    if k == 0 {
+                        return l.error(pos, "the threshold of an inhibitor arc must be at least 1");
+                    }
+                    reaction.arcs.push((IO::Inhibit, s, k));
+                    if !l.at_sym(",") {
+                        break;
+                    }
+                    l.i += 1;
+                }
+            } else if l.at_keyword("after") {
+                l.i += 1;
+                reaction.delay = l.number()? as f32;
+            } else if l.at_keyword("stages") {
+                l.i += 1;
+                reaction.stages = l.count()? as u32;
+            } else {
+                return l.error(l.pos(), format!("expected unless, after or stages, found {}", l.found()));
+            }
+        }
+        self.reactions.push(reaction);
+        Ok(())
+    }
+
+    // The keyword `model` or `observe` starting `src`, and the rest of the
+    // line, which is not made of tokens
+    fn raw_keyword(src: &str) -> Option<(&'static str, &str, usize)> {
+        let text = src.trim_start();
+        let indent = src.chars().count() - text.chars().count();
+        ["model", "observe"].iter()
+            .find(|k| text.starts_with(*k) && (text.len() == k.len() || text[k.len()..].starts_with(char::is_whitespace)))
+            .filter(|k| !text[k.len()..].trim_start().starts_with([':', '=']))
+            .map(|k| (*k, &text[k.len()..], indent + k.len()))
+    }
+
+    fn line(&mut self, number: usize, src: &str) -> Result<(), DslError> {
+        match Model::raw_keyword(src) {
+            Some(("model", rest, _)) => {
+                self.name = rest.trim().to_string();
+                return Ok(());
+            }
+            Some((_, rest, offset)) => {
+                // A name and an expression after `=`
+                let (head, expression) = match rest.find('=') {
+                    Some(i) => (&rest[..i], &rest[i + 1..]),
+                    None => (rest, ""),
+                };
+                let mut l = Line::lex(number, head)?;
+                let name = l.name().map_err(|e| DslError { column: e.column + offset, ..e })?;
+                if !l.at_end() || expression.is_empty() {
+                    let pos = offset + l.pos();
+                    return Err(DslError { line: number, column: pos + 1, message: format!("expected =, found {}", l.found()) });
+                }
+                let start = offset + head.chars().count() + 1;
+                if let Err(e) = Expr::parse(expression) {
+                    return Err(DslError { line: number, column: start + e.pos + 1, message: e.message });
+                }
+                self.observables.push(Observable { name, expression: expression.trim().to_string() });
+                return Ok(());
+            }
+            None => { }
+        }
+        let mut l = Line::lex(number, src)?;
+        if l.at_end() {
+            return Ok(());
+        }
+        let second = l.tokens.get(1).map(|(_, t)| t.clone());
+        let keyword = match (l.peek(), &second) {
+            (Some(Token::Name(n)), second) if !matches!(second, Some(Token::Sym(":")) | Some(Token::Sym("="))) => n.clone(),
+            _otherwise => String::new(),
+        };
+        match keyword.as_str() {
+            "species" => {
+                l.i += 1;
+                loop {
+                    let name = l.name()?;
+                    self.species(&name);
+                    if l.at_end() {
+                        break;
+                    }
+                    l.expect(",")?;
+                }
+            }
+            "param" => {
+                l.i += 1;
+                let pos = l.pos();
+                let name = l.name()?;
+                if self.parameters.iter().any(|p| p.name == name) {
+                    return l.error(pos, format!("the parameter {} is already defined", name));
+                }
+                l.expect("=")?;
+                let mut parameter = Parameter::new(&name, l.number()? as f32);
+                if l.at_keyword("in") {
+                    l.i += 1;
+                    l.expect("[")?;
+                    parameter.min = l.number()? as f32;
+                    l.expect(",")?;
+                    let pos = l.pos();
+                    parameter.max = l.number()? as f32;
+                    l.expect("]")?;
+                    if parameter.max < parameter.min {
+                        return l.error(pos, format!("the range of {} is empty", name));
+                    }
+                }
+                if l.at_keyword("unit") {
+                    l.i += 1;
+                    match l.peek().cloned() {
+                        Some(Token::Quoted(unit)) => {
+                            l.i += 1;
+                            parameter.unit = unit;
+                        }
+                        _otherwise => return l.error(l.pos(), format!("expected a quoted unit, found {}", l.found())),
+                    }
+                }
+                l.finish()?;
+                self.parameters.push(parameter);
+            }
+            _otherwise => match second {
+                Some(Token::Sym("=")) => {
+                    let pos = l.pos();
+                    let s = self.species(&l.name()?);
+                    l.expect("=")?;
+                    if self.init_vals[s].is_some() {
+                        return l.error(pos, format!("the initial value of {} is already given", self.species[s]));
+                    }
+                    self.init_vals[s] = Some(l.number()? as f32);
+                    l.finish()?;
+                }
+                Some(Token::Sym(":")) => self.reaction(&mut l)?,
+                _otherwise => {
+                    return l.error(l.pos(), format!("expected a reaction, an initial value or a declaration, found {}", l.found()));
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+// Reads a net and its controls from the text format
+pub fn read_reactions(src: &str) -> Result<(PetriNet, PlotControls), DslError> {
+    let mut model = Model::default();
+    for (i, text) in src.lines().enumerate() {
+        model.line(i + 1, strip_comment(text))?;
+    }
+
+    let n = model.species.len();
+    let mut petri = PetriNet::new(&model.name, model.species.clone(), vec![]);
+    let mut controls = PlotControls::empty();
+    for r in model.reactions.iter() {
+        let mut t = Transition::new(&r.name, vec![0; n], vec![0; n]);
+        for (d, s, k) in r.arcs.iter() {
+            let k = match d {
+                IO::Inhibit => *k,
+                _otherwise => t.arc(*d, *s) + k,
+            };
+            t.set_arc(*d, *s, k);
+        }
+        t.rate_law = r.law.clone();
+        t.delay = r.delay;
+        t.stages = r.stages;
+        let rate = match &r.rate {
+            Some(Rate::Value(x)) => *x,
+            Some(Rate::Parameter(p, pos)) => match model.parameters.iter().find(|q| &q.name == p) {
+                Some(q) => {
+                    t.rate = Some(p.clone());
+                    q.value
+                }
+                None => return Err(DslError { line: r.line, column: pos + 1, message: format!("unknown parameter {}", p) }),
+            },
+            None => 0.,
+        };
+        controls.rates.push(rate);
+        petri.transitions.push(t);
+    }
+    petri.parameters = model.parameters;
+    petri.observables = model.observables;
+    controls.init_vals = model.init_vals.iter().map(|x| x.unwrap_or(0.)).collect();
+    fit_initial_values(&mut controls);
+    Ok((petri, controls))
+}
+
+// A name as written: as it is if it is an identifier, and quoted otherwise
+fn quote(name: &str) -> String {
+    let identifier = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if identifier && !KEYWORDS.contains(&name) {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "'"))
+    }
+}
+
+// Writes a net and its controls in the text format, with warnings for what
+// could not be written
+pub fn write_reactions(petri: &PetriNet, controls: &PlotControls) -> (String, Vec<String>) {
+    let mut warnings = vec![];
+    let mut out = String::new();
+    let names = petri.species.iter().chain(petri.transitions.iter().map(|t| &t.name))
+        .chain(petri.parameters.iter().flat_map(|p| vec![&p.name, &p.unit]));
+    for name in names.filter(|n| n.contains('"')) {
+        warnings.push(format!("the double quotes in {} are written as single quotes", name));
+    }
+    if !petri.name.is_empty() {
+        out.push_str(&format!("model {}\n\n", petri.name));
+    }
+    if !petri.species.is_empty() {
+        out.push_str(&format!("species {}\n", petri.species.iter().map(|s| quote(s)).collect::<Vec<_>>().join(", ")));
+    }
+    for (s, name) in petri.species.iter().enumerate() {
+        match controls.init_vals.get(s) {
+            Some(x) if *x != 0. => out.push_str(&format!("{} = {}\n", quote(name), x)),
+            _otherwise => { }
+        }
+    }
+    if !petri.parameters.is_empty() {
+        out.push('\n');
+    }
+    for p in petri.parameters.iter() {
+        out.push_str(&format!("param {} = {}", quote(&p.name), p.value));
+        let default = Parameter::new(&p.name, p.value);
+        // The default range is left implicit
+        if (p.min, p.max) != (default.min, default.max) {
+            out.push_str(&format!(" in [{}, {}]", p.min, p.max));
+        }
+        if !p.unit.is_empty() {
+            out.push_str(&format!(" unit \"{}\"", p.unit.replace('"', "'")));
+        }
+        out.push('\n');
+    }
+    if !petri.transitions.is_empty() {
+        out.push('\n');
+    }
+    for (i, t) in petri.transitions.iter().enumerate() {
+        let side = |d: IO| {
+            let terms: Vec<String> = (0..petri.species.len())
+                .flat_map(|s| {
+                    let term = |k: i32| match k {
+                        1 => quote(&petri.species[s]),
+                        k => format!("{} {}", k, quote(&petri.species[s])),
+                    };
+                    let read = match d {
+                        IO::Input if t.arc(IO::Read, s) > 0 => Some(format!("[{}]", term(t.arc(IO::Read, s)))),
+                        _otherwise => None,
+                    };
+                    Some(t.arc(d, s)).filter(|k| *k > 0).map(term).into_iter().chain(read)
+                })
+                .collect();
+            if terms.is_empty() { "0".to_string() } else { terms.join(" + ") }
+        };
+        out.push_str(&format!("{}: {} -> {}", quote(&t.name), side(IO::Input), side(IO::Output)));
+        let rate = match t.rate.as_ref().filter(|p| petri.parameters.iter().any(|q| &&q.name == p)) {
+            Some(p) => quote(p),
+            None => controls.rates.get(i).cloned().unwrap_or(0.).to_string(),
+        };
+        let law = match &t.rate_law {
+            RateLaw::MassAction => String::new(),
+            RateLaw::FrequencyDependent => " frequency".to_string(),
+            RateLaw::MichaelisMenten { substrate, km } =>
+                format!(" michaelis({}, {})", quote(&petri.species[*substrate]), km),
+            RateLaw::Hill { substrate, k, n } =>
+                format!(" hill({}, {}, {})", quote(&petri.species[*substrate]), k, n),
+            RateLaw::Expression(e) => format!(" {{{}}}", e),
+        };
+        out.push_str(&format!(" @ {}{}", rate, law));
+        let inhibitors: Vec<String> = (0..petri.species.len()).filter(|s| t.arc(IO::Inhibit, *s) > 0)
+            .map(|s| format!("{} >= {}", quote(&petri.species[s]), t.arc(IO::Inhibit, s)))
+            .collect();
+        if !inhibitors.is_empty() {
+            out.push_str(&format!(" unless {}", inhibitors.join(", ")));
+        }
+        if t.delay > 0. {
+            out.push_str(&format!(" after {}", t.delay));
+        }
+        if t.stages > 1 {
+            out.push_str(&format!(" stages {}", t.stages));
+        }
+        out.push('\n');
+
+        warnings.extend(timing_warnings(t, "not written"));
+    }
+    if !petri.observables.is_empty() {
+        out.push('\n');
+    }
+    for o in petri.observables.iter() {
+        out.push_str(&format!("observe {} = {}\n", quote(&o.name), o.expression));
+    }
+    if !petri.events.is_empty() {
+        warnings.push(format!("{} event(s) are not written", petri.events.len()));
+    }
+    (out, warnings)
+}
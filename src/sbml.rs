@@ -0,0 +1,768 @@
+use std::collections::HashMap;
+use ndarray::prelude::*;
+use crate::petri::*;
+use crate::kinetics::*;
+use crate::expr::*;
+use crate::xml::*;
+use crate::interchange::{delay_warnings, inline_rules, timing_warnings, Import};
+
+// SBML Level 3 core import and export. A net is written as a model with a
+// single compartment of size 1, in which species hold amounts:
+//
+// - species become `<species>` with their initial values as `initialAmount`
+// - transitions become irreversible `<reaction>`s, with the input and output
+//   arcs as reactants and products, and read and inhibitor arcs as modifiers
+// - the rate of each transition becomes a global parameter `k_<transition>`,
+//   unless it is taken from a parameter of the net, and its rate law a
+//   `<kineticLaw>` in MathML, times a step function for each inhibitor arc
+// - observables become assignment rules for parameters of the same name
+//
+// Constructs with no SBML counterpart here (events, schedules, firing time
+// distributions, delays, stages) are left out with a warning. On import,
+// kinetic laws of the form k * S^a * ... matching the reactants are read as
+// mass action, and others as expressions, with the parameters assigned by
+// rules replaced by their expressions. Reactants that are boundary or
+// constant species become read arcs, and such products are left out.
+// Constructs that cannot be represented are reported.
+
+const SBML_NAMESPACE: &str = "http://www.sbml.org/sbml/level3/version1/core";
+const MATHML_NAMESPACE: &str = "http://www.w3.org/1998/Math/MathML";
+const TIME_SYMBOL: &str = "http://www.sbml.org/sbml/symbols/time";
+const COMPARTMENT: &str = "default_compartment";
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && name != "t"
+}
+
+fn ci(id: &str) -> Element {
+    Element::new("ci").with_text(id)
+}
+
+fn cn(x: f64) -> Element {
+    Element::new("cn").with_text(&x.to_string())
+}
+
+fn apply(op: &str, args: Vec<Element>) -> Element {
+    args.into_iter().fold(Element::new("apply").with_child(Element::new(op)), |a, x| a.with_child(x))
+}
+
+// `value` where `condition` holds and `otherwise` elsewhere
+fn piecewise(value: Element, condition: Element, otherwise: Element) -> Element {
+    Element::new("piecewise")
+        .with_child(Element::new("piece").with_child(value).with_child(condition))
+        .with_child(Element::new("otherwise").with_child(otherwise))
+}
+
+// MathML for an expression, with variables translated by `var`
+fn mathml(e: &Expr, var: &dyn Fn(&str) -> Element) -> Element {
+    match e {
+        Expr::Num(x) => cn(*x),
+        Expr::Var(v, _) => var(v),
+        Expr::Neg(a) => apply("minus", vec![mathml(a, var)]),
+        Expr::Bin(op, a, b) => {
+            let (a, b) = (mathml(a, var), mathml(b, var));
+            let name = match op {
+                BinOp::Add => "plus",
+                BinOp::Sub => "minus",
+                BinOp::Mul => "times",
+                BinOp::Div => "divide",
+                BinOp::Pow => "power",
+                BinOp::Lt => "lt",
+                BinOp::Gt => "gt",
+                BinOp::Le => "leq",
+                BinOp::Ge => "geq",
+                BinOp::Eq => "eq",
+                BinOp::Ne => "neq",
+            };
+            match op {
+                BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Pow => apply(name, vec![a, b]),
+                // Comparisons are 1 or 0, as in the expression language
+                _otherwise => piecewise(cn(1.), apply(name, vec![a, b]), cn(0.)),
+            }
+        }
+        Expr::Call(name, _, args) => {
+            let mut args: Vec<Element> = args.iter().map(|a| mathml(a, var)).collect();
+            match name.as_str() {
+                "log" => apply("ln", args),
+                "sqrt" => apply("root", args),
+                "min" | "max" if args.len() == 2 => {
+                    let (a, b) = (args.remove(0), args.remove(0));
+                    let op = if name == "min" { "leq" } else { "geq" };
+                    piecewise(a.clone(), apply(op, vec![a, b.clone()]), b)
+                }
+                _otherwise => apply(name, args),
+            }
+        }
+    }
+}
+
+fn math(content: Element) -> Element {
+    Element::new("math").with_attr("xmlns", MATHML_NAMESPACE).with_child(content)
+}
+
+// Writes `petri` as an SBML document, with the initial values and rates of
+// `controls`, and warnings for what could not be written
+pub fn write_sbml(petri: &PetriNet, controls: &PlotControls) -> (String, Vec<String>) {
+    let mut warnings = vec![];
+    let mut used = vec![COMPARTMENT.to_string(), "t".to_string()];
+    let species_ids: Vec<String> = petri.species.iter().map(|s| unique_id(s, &mut used)).collect();
+    let parameter_ids: Vec<String> = petri.parameters.iter().map(|p| unique_id(&p.name, &mut used)).collect();
+    let observable_ids: Vec<String> = petri.observables.iter().map(|o| unique_id(&o.name, &mut used)).collect();
+    let reaction_ids: Vec<String> = petri.transitions.iter().map(|t| unique_id(&t.name, &mut used)).collect();
+    let rates = petri.effective_rates(controls);
+
+    // Transitions whose rate is a parameter of the net use it, and the others
+    // get a parameter of their own
+    let mut rate_parameters = vec![];
+    let rate_ids: Vec<String> = petri.transitions.iter().enumerate()
+        .map(|(i, t)| {
+            match t.rate.as_ref().and_then(|n| petri.parameters.iter().position(|p| &p.name == n)) {
+                Some(p) => parameter_ids[p].clone(),
+                None => {
+                    let id = unique_id(&format!("k_{}", reaction_ids[i]), &mut used);
+                    rate_parameters.push((id.clone(), rates[i]));
+                    id
+                }
+            }
+        })
+        .collect();
+    let id = |v: &str| -> String {
+        if let Some(s) = petri.species.iter().position(|n| n == v) {
+            species_ids[s].clone()
+        } else if let Some(p) = petri.parameters.iter().position(|p| p.name == v) {
+            parameter_ids[p].clone()
+        } else if let Some(t) = petri.transitions.iter().position(|t| t.name == v) {
+            rate_ids[t].clone()
+        } else {
+            v.to_string()
+        }
+    };
+    // Identifiers, where `t` is never the id of anything else
+    let ci_or_time = |id: &str| -> Element {
+        if id == "t" {
+            Element::new("csymbol").with_attr("encoding", "text").with_attr("definitionURL", TIME_SYMBOL).with_text("t")
+        } else {
+            ci(id)
+        }
+    };
+    let var = |v: &str| ci_or_time(&id(v));
+
+    let mut model = Element::new("model")
+        .with_attr("id", unique_id(&petri.name, &mut vec![]))
+        .with_attr("name", &petri.name);
+
+    model = model.with_child(Element::new("listOfCompartments").with_child(
+        Element::new("compartment")
+            .with_attr("id", COMPARTMENT)
+            .with_attr("spatialDimensions", 3)
+            .with_attr("size", 1)
+            .with_attr("constant", true)));
+
+    let mut species = Element::new("listOfSpecies");
+    for (s, name) in petri.species.iter().enumerate() {
+        species = species.with_child(Element::new("species")
+            .with_attr("id", &species_ids[s])
+            .with_attr("name", name)
+            .with_attr("compartment", COMPARTMENT)
+            .with_attr("initialAmount", controls.init_vals.get(s).cloned().unwrap_or(0.))
+            .with_attr("hasOnlySubstanceUnits", true)
+            .with_attr("boundaryCondition", false)
+            .with_attr("constant", false));
+    }
+    model = model.with_child(species);
+
+    let mut parameters = Element::new("listOfParameters");
+    for (p, param) in petri.parameters.iter().enumerate() {
+        parameters = parameters.with_child(Element::new("parameter")
+            .with_attr("id", &parameter_ids[p])
+            .with_attr("name", &param.name)
+            .with_attr("value", param.value)
+            .with_attr("constant", true));
+    }
+    for (id, value) in rate_parameters.iter() {
+        parameters = parameters.with_child(Element::new("parameter")
+            .with_attr("id", id)
+            .with_attr("value", value)
+            .with_attr("constant", true));
+    }
+    for (o, obs) in petri.observables.iter().enumerate() {
+        parameters = parameters.with_child(Element::new("parameter")
+            .with_attr("id", &observable_ids[o])
+            .with_attr("name", &obs.name)
+            .with_attr("constant", false));
+    }
+    model = model.with_child(parameters);
+
+    if !petri.observables.is_empty() {
+        let mut rules = Element::new("listOfRules");
+        for (o, obs) in petri.observables.iter().enumerate() {
+            let content = match Expr::parse(&obs.expression) {
+                Ok(e) => mathml(&e, &var),
+                Err(e) => {
+                    warnings.push(format!("observable {}: {}; written as 0", obs.name, e));
+                    cn(0.)
+                }
+            };
+            rules = rules.with_child(Element::new("assignmentRule")
+                .with_attr("variable", &observable_ids[o])
+                .with_child(math(content)));
+        }
+        model = model.with_child(rules);
+    }
+
+    let species: Vec<Expr> = species_ids.iter().map(|s| Expr::Var(s.clone(), 0)).collect();
+    let mut reactions = Element::new("listOfReactions");
+    for (i, t) in petri.transitions.iter().enumerate() {
+        let references = |d: IO, tag: &str| {
+            (0..petri.species.len()).filter(|s| t.arc(d, *s) > 0)
+                .map(|s| Element::new(tag)
+                     .with_attr("species", &species_ids[s])
+                     .with_attr("stoichiometry", t.arc(d, s))
+                     .with_attr("constant", true))
+                .collect::<Vec<_>>()
+        };
+        let mut reaction = Element::new("reaction")
+            .with_attr("id", &reaction_ids[i])
+            .with_attr("name", &t.name)
+            .with_attr("reversible", false);
+        for (d, list) in [(IO::Input, "listOfReactants"), (IO::Output, "listOfProducts")].iter() {
+            let refs = references(*d, "speciesReference");
+            if !refs.is_empty() {
+                reaction = reaction.with_child(refs.into_iter().fold(Element::new(list), |l, r| l.with_child(r)));
+            }
+        }
+        let modifiers: Vec<usize> = (0..petri.species.len())
+            .filter(|s| t.arc(IO::Read, *s) > 0 || t.arc(IO::Inhibit, *s) > 0)
+            .collect();
+        if !modifiers.is_empty() {
+            reaction = reaction.with_child(modifiers.iter().fold(Element::new("listOfModifiers"), |l, s| {
+                l.with_child(Element::new("modifierSpeciesReference").with_attr("species", &species_ids[*s]))
+            }));
+        }
+
+        let participants: Array1<i32> = (0..petri.species.len()).map(|s| t.arc(IO::Input, s) + t.arc(IO::Read, s)).collect();
+        let inhibit: Array1<i32> = (0..petri.species.len()).map(|s| t.arc(IO::Inhibit, s)).collect();
+        let law = match t.rate_law.symbolic(participants.view(), inhibit.view(), &species, Expr::Var(rate_ids[i].clone(), 0),
+                                            &|v| Expr::Var(id(v), 0)) {
+            Ok(law) => mathml(&law, &ci_or_time),
+            Err(e) => {
+                warnings.push(format!("transition {}: {}; written with rate law 0", t.name, e));
+                cn(0.)
+            }
+        };
+        reaction = reaction.with_child(Element::new("kineticLaw").with_child(math(law)));
+        reactions = reactions.with_child(reaction);
+
+        warnings.extend(timing_warnings(t, "not exported"));
+        warnings.extend(delay_warnings(petri, i, "not exported"));
+    }
+    model = model.with_child(reactions);
+
+    if !petri.events.is_empty() {
+        warnings.push(format!("{} event(s) are not exported", petri.events.len()));
+    }
+
+    let sbml = Element::new("sbml")
+        .with_attr("xmlns", SBML_NAMESPACE)
+        .with_attr("level", 3)
+        .with_attr("version", 1)
+        .with_child(model);
+    (sbml.to_document(), warnings)
+}
+
+// An expression from MathML content, with identifiers translated by `name`
+fn expression(e: &Element, name: &dyn Fn(&str) -> String) -> Result<Expr, String> {
+    let num = |x: f64| Ok(Expr::Num(x));
+    let bin = |op: BinOp, a: Expr, b: Expr| Expr::Bin(op, Box::new(a), Box::new(b));
+    match e.local_name() {
+        "ci" => Ok(Expr::Var(name(&e.text()), 0)),
+        "cn" => {
+            let parts: Vec<String> = e.children.iter()
+                .filter_map(|c| match c {
+                    Node::Text(t) => Some(t.trim().to_string()),
+                    Node::Element(_) => None,
+                })
+                .filter(|t| !t.is_empty())
+                .collect();
+            let parse = |s: &str| s.parse::<f64>().map_err(|_| format!("invalid number {}", s));
+            match (e.attr("type"), parts.as_slice()) {
+                (Some("e-notation"), [m, x]) => num(parse(m)? * 10f64.powf(parse(x)?)),
+                (Some("rational"), [p, q]) => num(parse(p)? / parse(q)?),
+                (_, [x]) => num(parse(x)?),
+                _otherwise => Err("invalid <cn>".to_string()),
+            }
+        }
+        "csymbol" => match e.attr("definitionURL") {
+            Some(TIME_SYMBOL) => Ok(Expr::Var("t".to_string(), 0)),
+            Some(url) if url.ends_with("avogadro") => num(6.02214076e23),
+            Some(url) => Err(format!("the symbol {} is not supported", url)),
+            None => Err("a <csymbol> has no definitionURL".to_string()),
+        },
+        "true" => num(1.),
+        "false" => num(0.),
+        "pi" => num(std::f64::consts::PI),
+        "exponentiale" => num(std::f64::consts::E),
+        "semantics" => match e.elements().next() {
+            Some(c) => expression(c, name),
+            None => Err("empty <semantics>".to_string()),
+        },
+        "piecewise" => {
+            let otherwise = match e.child("otherwise").and_then(|o| o.elements().next()) {
+                Some(o) => expression(o, name)?,
+                None => num(0.)?,
+            };
+            // c1 * v1 + (1 - c1) * (...), where the conditions are 1 or 0
+            let pieces: Vec<&Element> = e.children_named("piece").collect();
+            pieces.iter().rev().try_fold(otherwise, |rest, piece| {
+                let args: Vec<&Element> = piece.elements().collect();
+                if args.len() != 2 {
+                    return Err("a <piece> needs a value and a condition".to_string());
+                }
+                let (value, condition) = (expression(args[0], name)?, expression(args[1], name)?);
+                Ok(bin(BinOp::Add, bin(BinOp::Mul, condition.clone(), value),
+                       bin(BinOp::Mul, bin(BinOp::Sub, Expr::Num(1.), condition), rest)))
+            })
+        }
+        "apply" => {
+            let children: Vec<&Element> = e.elements().collect();
+            let (op, children) = match children.split_first() {
+                Some((op, children)) => (*op, children),
+                None => return Err("empty <apply>".to_string()),
+            };
+            let qualifiers = ["degree", "logbase"];
+            let args = children.iter()
+                .filter(|c| !qualifiers.contains(&c.local_name()))
+                .map(|c| expression(c, name))
+                .collect::<Result<Vec<_>, _>>()?;
+            let qualifier = |q: &str| children.iter().find(|c| c.local_name() == q)
+                .and_then(|c| c.elements().next())
+                .map(|c| expression(c, name));
+            let call = |f: &str, args: Vec<Expr>| Expr::Call(f.to_string(), 0, args);
+            let fold = |op: BinOp, unit: f64, args: Vec<Expr>| {
+                args.into_iter().fold(None, |a: Option<Expr>, x| Some(match a {
+                    Some(a) => bin(op, a, x),
+                    None => x,
+                })).unwrap_or(Expr::Num(unit))
+            };
+            let one = |args: &[Expr]| -> Result<Expr, String> {
+                match args {
+                    [a] => Ok(a.clone()),
+                    _otherwise => Err(format!("<{}> takes one argument", op.local_name())),
+                }
+            };
+            let two = |args: &[Expr]| -> Result<(Expr, Expr), String> {
+                match args {
+                    [a, b] => Ok((a.clone(), b.clone())),
+                    _otherwise => Err(format!("<{}> takes two arguments", op.local_name())),
+                }
+            };
+            let compare = |cmp: BinOp, args: &[Expr]| -> Result<Expr, String> {
+                let (a, b) = two(args)?;
+                Ok(bin(cmp, a, b))
+            };
+            match op.local_name() {
+                "plus" => Ok(fold(BinOp::Add, 0., args)),
+                "times" => Ok(fold(BinOp::Mul, 1., args)),
+                "and" => Ok(fold(BinOp::Mul, 1., args)),
+                "or" => Ok(call("min", vec![Expr::Num(1.), fold(BinOp::Add, 0., args)])),
+                "not" => Ok(bin(BinOp::Sub, Expr::Num(1.), one(&args)?)),
+                "minus" if args.len() == 1 => Ok(Expr::Neg(Box::new(one(&args)?))),
+                "minus" => two(&args).map(|(a, b)| bin(BinOp::Sub, a, b)),
+                "divide" => two(&args).map(|(a, b)| bin(BinOp::Div, a, b)),
+                "power" => two(&args).map(|(a, b)| bin(BinOp::Pow, a, b)),
+                "root" => {
+                    let x = one(&args)?;
+                    match qualifier("degree").transpose()? {
+                        Some(n) => Ok(bin(BinOp::Pow, x, bin(BinOp::Div, Expr::Num(1.), n))),
+                        None => Ok(call("sqrt", vec![x])),
+                    }
+                }
+                "exp" => Ok(call("exp", vec![one(&args)?])),
+                "ln" => Ok(call("log", vec![one(&args)?])),
+                "log" => {
+                    let base = qualifier("logbase").transpose()?.unwrap_or(Expr::Num(10.));
+                    Ok(bin(BinOp::Div, call("log", vec![one(&args)?]), call("log", vec![base])))
+                }
+                "abs" => Ok(call("abs", vec![one(&args)?])),
+                "min" | "max" => {
+                    let f = op.local_name().to_string();
+                    args.into_iter().fold(None, |a: Option<Expr>, x| Some(match a {
+                        Some(a) => call(&f, vec![a, x]),
+                        None => x,
+                    })).ok_or_else(|| format!("<{}> needs arguments", f))
+                }
+                "lt" => compare(BinOp::Lt, &args),
+                "gt" => compare(BinOp::Gt, &args),
+                "leq" => compare(BinOp::Le, &args),
+                "geq" => compare(BinOp::Ge, &args),
+                "eq" => compare(BinOp::Eq, &args),
+                "neq" => compare(BinOp::Ne, &args),
+                "ci" => Err(format!("the function {} is not supported", op.text())),
+                other => Err(format!("the MathML operator <{}> is not supported", other)),
+            }
+        }
+        other => Err(format!("the MathML element <{}> is not supported", other)),
+    }
+}
+
+// The content of a `<math>` element
+fn math_content(e: &Element, name: &dyn Fn(&str) -> String) -> Result<Expr, String> {
+    match e.child("math").and_then(|m| m.elements().next()) {
+        Some(c) => expression(c, name),
+        None => Err("no <math>".to_string()),
+    }
+}
+
+// The factors of a product
+fn factors(e: &Expr) -> Vec<&Expr> {
+    match e {
+        Expr::Bin(BinOp::Mul, a, b) => factors(a).into_iter().chain(factors(b)).collect(),
+        e => vec![e],
+    }
+}
+
+// If `law` is a product of numbers and powers of variables, the product of
+// the numbers and the power of each variable
+fn mass_action_form(law: &Expr) -> Option<(f64, Vec<(String, i32)>)> {
+    let mut coefficient = 1.;
+    let mut powers: Vec<(String, i32)> = vec![];
+    for f in factors(law) {
+        let (v, k) = match f {
+            Expr::Num(x) => {
+                coefficient *= x;
+                continue;
+            }
+            Expr::Var(v, _) => (v, 1),
+            Expr::Bin(BinOp::Pow, a, b) => match (&**a, &**b) {
+                (Expr::Var(v, _), Expr::Num(k)) if k.fract() == 0. && *k >= 1. => (v, *k as i32),
+                _otherwise => return None,
+            },
+            _otherwise => return None,
+        };
+        match powers.iter_mut().find(|(w, _)| w == v) {
+            Some(p) => p.1 += k,
+            None => powers.push((v.clone(), k)),
+        }
+    }
+    Some((coefficient, powers))
+}
+
+// The rate of a kinetic law read as mass action
+pub(crate) enum MassActionRate {
+    Parameter(String),
+    Value(f32),
+}
+
+// If `law` is a rate, either one of `parameters` or a number, times a mass
+// action term in the participants of `t`, the rate. Extra powers of species
+// are catalysts, and are added to `t` as read arcs.
+pub(crate) fn read_mass_action(law: &Expr, species: &[String], parameters: &[Parameter], t: &mut Transition) -> Option<MassActionRate> {
+    let (coefficient, powers) = mass_action_form(law)?;
+    let (rate, powers): (Vec<_>, Vec<_>) = powers.into_iter().partition(|(v, _)| !species.contains(v));
+    let rate = match rate.as_slice() {
+        [(p, 1)] if coefficient == 1. && parameters.iter().any(|q| &q.name == p) => MassActionRate::Parameter(p.clone()),
+        [] => MassActionRate::Value(coefficient as f32),
+        _otherwise => return None,
+    };
+    let participants = |s: usize| t.arc(IO::Input, s) + t.arc(IO::Read, s);
+    let index = |v: &str| species.iter().position(|x| x == v).unwrap();
+    let matched = powers.iter().all(|(v, k)| *k >= participants(index(v)))
+        && (0..species.len()).all(|s| participants(s) == 0 || powers.iter().any(|(v, _)| *v == species[s]));
+    if !matched {
+        return None;
+    }
+    let extras: Vec<(usize, i32)> = powers.iter().map(|(v, k)| (index(v), k - participants(index(v)))).collect();
+    for (s, extra) in extras {
+        if extra > 0 {
+            t.set_arc(IO::Read, s, t.arc(IO::Read, s) + extra);
+        }
+    }
+    Some(rate)
+}
+
+fn attr_f32(e: &Element, name: &str) -> Option<f32> {
+    e.attr(name).and_then(|v| v.trim().parse().ok())
+}
+
+// Reads an SBML document, reporting the constructs that could not be
+// represented in the warnings
+pub fn read_sbml(src: &str) -> Result<Import, String> {
+    let root = parse(src).map_err(|e| e.to_string())?;
+    if root.local_name() != "sbml" {
+        return Err(format!("expected an <sbml> document, found <{}>", root.name));
+    }
+    let mut warnings = vec![];
+    if root.attr("level") != Some("3") {
+        warnings.push(format!("this is SBML level {}, read as level 3", root.attr("level").unwrap_or("?")));
+    }
+    let model = root.child("model").ok_or("the document has no <model>")?;
+    let list = |name: &str, item: &str| -> Vec<&Element> {
+        model.child(name).map(|l| l.elements().filter(|e| e.local_name() == item).collect()).unwrap_or_default()
+    };
+
+    let compartments: HashMap<String, f32> = list("listOfCompartments", "compartment").iter()
+        .filter_map(|c| c.attr("id").map(|id| (id.to_string(), attr_f32(c, "size").unwrap_or(1.))))
+        .collect();
+    if compartments.len() > 1 || compartments.values().any(|v| *v != 1.) {
+        warnings.push("compartments are not represented: species are read as amounts, and compartment sizes are kept as parameters".to_string());
+    }
+
+    // Names are the SBML names where they are usable in expressions, and the ids otherwise
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut taken: Vec<String> = vec![];
+    let mut name_of = |e: &Element, names: &mut HashMap<String, String>| -> Option<String> {
+        let id = e.attr("id")?.to_string();
+        let name = match e.attr("name") {
+            Some(n) if is_identifier(n) && !taken.iter().any(|t| t == n) => n.to_string(),
+            _otherwise => id.clone(),
+        };
+        taken.push(name.clone());
+        names.insert(id, name.clone());
+        Some(name)
+    };
+
+    let mut species = vec![];
+    let mut init_vals = vec![];
+    let mut boundary = vec![];
+    for s in list("listOfSpecies", "species") {
+        let name = match name_of(s, &mut names) {
+            Some(n) => n,
+            None => {
+                warnings.push("a species without an id was skipped".to_string());
+                continue;
+            }
+        };
+        let size = s.attr("compartment").and_then(|c| compartments.get(c)).cloned().unwrap_or(1.);
+        let initial = match (attr_f32(s, "initialAmount"), attr_f32(s, "initialConcentration")) {
+            (Some(x), _) => x,
+            (None, Some(c)) => c * size,
+            (None, None) => {
+                warnings.push(format!("species {} has no initial amount; starting at 0", name));
+                0.
+            }
+        };
+        boundary.push(s.attr("boundaryCondition") == Some("true") || s.attr("constant") == Some("true"));
+        species.push(name);
+        init_vals.push(initial);
+    }
+
+    let mut parameters = vec![];
+    for p in list("listOfParameters", "parameter") {
+        let name = match name_of(p, &mut names) {
+            Some(n) => n,
+            None => continue,
+        };
+        let value = attr_f32(p, "value").unwrap_or_else(|| {
+            if p.attr("constant") != Some("false") {
+                warnings.push(format!("parameter {} has no value; using 0", name));
+            }
+            0.
+        });
+        parameters.push(Parameter::new(&name, value));
+    }
+    // Compartments are parameters if they are referenced, which is known
+    // once the kinetic laws and rules are read
+    for id in compartments.keys() {
+        if !names.contains_key(id) {
+            names.insert(id.clone(), id.clone());
+        }
+    }
+
+    let mut transitions = vec![];
+    let mut local_names: Vec<Vec<(String, String)>> = vec![];
+    for r in list("listOfReactions", "reaction") {
+        let name = match name_of(r, &mut names) {
+            Some(n) => n,
+            None => {
+                warnings.push("a reaction without an id was skipped".to_string());
+                continue;
+            }
+        };
+        // Local parameters become global parameters named `reaction_parameter`
+        let mut locals = vec![];
+        if let Some(law) = r.child("kineticLaw") {
+            let items = ["listOfLocalParameters", "listOfParameters"].iter()
+                .filter_map(|l| law.child(l))
+                .flat_map(|l| l.elements())
+                .collect::<Vec<_>>();
+            for p in items {
+                if let Some(id) = p.attr("id") {
+                    let global = format!("{}_{}", name, id);
+                    parameters.push(Parameter::new(&global, attr_f32(p, "value").unwrap_or(0.)));
+                    locals.push((id.to_string(), global));
+                }
+            }
+        }
+        local_names.push(locals);
+        transitions.push((name, r));
+    }
+
+    let mut petri = PetriNet::new(model.attr("name").or_else(|| model.attr("id")).unwrap_or(""), species.clone(), vec![]);
+    let n = species.len();
+    let mut rates = vec![];
+    let mut laws = vec![];
+    for ((name, r), locals) in transitions.iter().zip(local_names.iter()) {
+        if r.attr("reversible") == Some("true") {
+            warnings.push(format!("reaction {} is reversible; its kinetic law is read as the rate of the forward direction", name));
+        }
+        if r.attr("fast") == Some("true") {
+            warnings.push(format!("reaction {} is fast, which is not supported", name));
+        }
+        let mut t = Transition::new(name, vec![0; n], vec![0; n]);
+        for (list, d) in [("listOfReactants", IO::Input), ("listOfProducts", IO::Output)].iter() {
+            for sr in r.child(list).map(|l| l.children_named("speciesReference").collect()).unwrap_or_else(Vec::new) {
+                let s = match sr.attr("species").and_then(|s| names.get(s)).and_then(|s| species.iter().position(|x| x == s)) {
+                    Some(s) => s,
+                    None => {
+                        warnings.push(format!("reaction {} refers to an unknown species", name));
+                        continue;
+                    }
+                };
+                let k = attr_f32(sr, "stoichiometry").unwrap_or(1.);
+                if k.fract() != 0. || k < 0. {
+                    warnings.push(format!("reaction {}: the stoichiometry {} of {} is rounded to a whole number", name, k, species[s]));
+                }
+                if sr.child("stoichiometryMath").is_some() {
+                    warnings.push(format!("reaction {}: stoichiometry given by math is not supported; using 1", name));
+                }
+                let k = k.round().max(0.) as i32;
+                match (d, boundary[s]) {
+                    // Boundary species take part in the rate without being changed
+                    (IO::Input, true) => { t.set_arc(IO::Read, s, t.arc(IO::Read, s) + k); }
+                    (IO::Output, true) => { }
+                    (d, _) => { t.set_arc(*d, s, t.arc(*d, s) + k); }
+                }
+            }
+        }
+        let rename = |v: &str| -> String {
+            locals.iter().find(|(l, _)| l == v).map(|(_, g)| g.clone())
+                .or_else(|| names.get(v).cloned())
+                .unwrap_or_else(|| v.to_string())
+        };
+        let law = match r.child("kineticLaw").map(|l| math_content(l, &rename)) {
+            Some(Ok(e)) => Some(e),
+            Some(Err(e)) => {
+                warnings.push(format!("reaction {}: {}; using mass action with rate 0", name, e));
+                None
+            }
+            None => {
+                warnings.push(format!("reaction {} has no kinetic law; using mass action with rate 0", name));
+                None
+            }
+        };
+        laws.push(law);
+        rates.push(0.);
+        petri.transitions.push(t);
+    }
+    petri.parameters = parameters;
+
+    let mut observables = vec![];
+    let mut rule_kinds: Vec<String> = vec![];
+    if let Some(rules) = model.child("listOfRules") {
+        for rule in rules.elements() {
+            let variable = rule.attr("variable").and_then(|v| names.get(v)).cloned();
+            match (rule.local_name(), variable) {
+                ("assignmentRule", Some(v)) if petri.parameters.iter().any(|p| p.name == v) => {
+                    match math_content(rule, &|x: &str| names.get(x).cloned().unwrap_or_else(|| x.to_string())) {
+                        Ok(e) => observables.push((v, e)),
+                        Err(e) => warnings.push(format!("the rule for {}: {}", v, e)),
+                    }
+                }
+                (kind, v) => {
+                    let what = v.map(|v| format!("{} for {}", kind, v)).unwrap_or_else(|| kind.to_string());
+                    rule_kinds.push(what);
+                }
+            }
+        }
+    }
+    if !rule_kinds.is_empty() {
+        warnings.push(format!("rules other than assignments to parameters are not supported: {}", rule_kinds.join(", ")));
+    }
+    // Parameters assigned by rules are observables. Expressions of the net
+    // cannot refer to observables, so the rules are inlined where they are used.
+    petri.parameters.retain(|p| !observables.iter().any(|(v, _)| *v == p.name));
+    petri.observables = vec![];
+    for (v, e) in observables.iter() {
+        match inline_rules(e, &observables) {
+            Ok(e) => petri.observables.push(Observable { name: v.clone(), expression: e.to_string() }),
+            Err(e) => warnings.push(format!("the rule for {}: {}", v, e)),
+        }
+    }
+    let laws: Vec<Option<Expr>> = laws.into_iter().zip(petri.transitions.iter())
+        .map(|(law, t)| match law.map(|e| inline_rules(&e, &observables)) {
+            Some(Ok(e)) => Some(e),
+            Some(Err(e)) => {
+                warnings.push(format!("reaction {}: {}; using mass action with rate 0", t.name, e));
+                None
+            }
+            None => None,
+        })
+        .collect();
+
+    for (id, size) in compartments.iter() {
+        let name = &names[id];
+        let referenced = laws.iter().flatten().chain(observables.iter().map(|(_, e)| e))
+            .any(|e| e.variables().contains(name));
+        if referenced && !petri.parameters.iter().any(|p| &p.name == name) {
+            petri.parameters.push(Parameter::new(name, *size));
+        }
+    }
+
+    // Mass action laws with a rate parameter of their own, local to the
+    // reaction or named `k_<reaction>` as written by `write_sbml`, take the
+    // rate from the controls when it is used nowhere else, and the parameter
+    // is dropped
+    let uses = |v: &str| -> usize {
+        laws.iter().flatten().chain(observables.iter().map(|(_, e)| e))
+            .map(|e| e.variables().iter().filter(|x| *x == v).count())
+            .sum()
+    };
+    let mut dropped = vec![];
+    for (i, law) in laws.iter().enumerate() {
+        let parameters = &petri.parameters;
+        let t = &mut petri.transitions[i];
+        let law = match law {
+            Some(law) => law,
+            None => continue,
+        };
+        match read_mass_action(law, &species, parameters, t) {
+            Some(MassActionRate::Value(x)) => {
+                rates[i] = x;
+            }
+            Some(MassActionRate::Parameter(p)) => {
+                rates[i] = parameters.iter().find(|q| q.name == p).unwrap().value;
+                let own = local_names[i].iter().any(|(_, g)| *g == p) || p == format!("k_{}", t.name);
+                if own && uses(&p) == 1 {
+                    dropped.push(p);
+                } else {
+                    t.rate = Some(p);
+                }
+            }
+            None => {
+                t.rate_law = RateLaw::Expression(law.to_string());
+            }
+        }
+    }
+    petri.parameters.retain(|p| !dropped.contains(&p.name));
+
+    let skipped = [("listOfEvents", "events"), ("listOfFunctionDefinitions", "function definitions"),
+                   ("listOfInitialAssignments", "initial assignments"), ("listOfConstraints", "constraints")];
+    for (list, what) in skipped.iter() {
+        if let Some(l) = model.child(list) {
+            warnings.push(format!("{} {} are not supported and were skipped", l.elements().count(), what));
+        }
+    }
+    let known = ["listOfCompartments", "listOfSpecies", "listOfParameters", "listOfReactions", "listOfRules",
+                 "listOfUnitDefinitions", "notes", "annotation", "listOfEvents", "listOfFunctionDefinitions",
+                 "listOfInitialAssignments", "listOfConstraints"];
+    for e in model.elements().filter(|e| !known.contains(&e.local_name())) {
+        warnings.push(format!("<{}> is not supported and was skipped", e.local_name()));
+    }
+
+    let mut controls = PlotControls::empty();
+    controls.rates = rates;
+    controls.init_vals = init_vals;
+    Ok(Import::new(petri, controls, warnings))
+}
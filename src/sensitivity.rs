@@ -0,0 +1,239 @@
+use rand::Rng;
+use rand::seq::SliceRandom;
+use ndarray::prelude::*;
+use crate::petri::*;
+#[cfg(target_arch = "wasm32")]
+use plotters::prelude::*;
+
+// Global sensitivity of the final species values (at t = xmax) with respect to
+// the inputs of the model: the rates of the transitions not bound to a
+// parameter, sampled over `PlotControls::rate_ranges`, and the parameters,
+// sampled between their min and max. Each sample is solved as in the plot,
+// with rates taken from the parameters they reference.
+
+pub struct SobolIndices {
+    pub factors: Vec<String>,
+    // Indexed by (factor, species)
+    pub first_order: Array2<f64>,
+    pub total: Array2<f64>,
+}
+
+pub struct MorrisEffects {
+    pub factors: Vec<String>,
+    // Indexed by (factor, species)
+    pub mu_star: Array2<f64>,
+    pub sigma: Array2<f64>,
+}
+
+#[derive(Clone, Copy)]
+enum Factor {
+    Rate(usize),
+    Parameter(usize),
+}
+
+struct Problem {
+    petri: PetriNet,
+    controls: PlotControls,
+    factors: Vec<(Factor, (f32, f32))>,
+}
+
+impl Problem {
+    fn new(petri: &PetriNet, controls: &PlotControls) -> Self {
+        let bound = |t: &Transition| t.rate.as_ref().is_some_and(|r| petri.parameters.iter().any(|p| &p.name == r));
+        let rates = petri.transitions.iter().enumerate()
+            .filter(|(_, t)| !bound(t))
+            .map(|(i, _)| (Factor::Rate(i), controls.rate_range(i)));
+        let parameters = petri.parameters.iter().enumerate()
+            .map(|(i, p)| (Factor::Parameter(i), (p.min, p.max)));
+        let mut controls = controls.clone();
+        controls.rates.resize(petri.transitions.len(), 0.);
+        Problem {
+            petri: petri.clone(),
+            controls,
+            factors: rates.chain(parameters).collect(),
+        }
+    }
+
+    fn num_params(&self) -> usize {
+        self.factors.len()
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.factors.iter()
+            .map(|(f, _)| match f {
+                Factor::Rate(i) => self.petri.transitions[*i].name.clone(),
+                Factor::Parameter(i) => self.petri.parameters[*i].name.clone(),
+            })
+            .collect()
+    }
+
+    // Maps a point of the unit hypercube onto the factor ranges and solves
+    fn eval(&self, u: ArrayView1<f64>) -> Array1<f64> {
+        let (mut petri, mut controls) = (self.petri.clone(), self.controls.clone());
+        for (x, (f, (lo, hi))) in u.iter().zip(self.factors.iter()) {
+            let v = lo + (hi - lo) * (*x as f32);
+            match f {
+                Factor::Rate(i) => controls.rates[*i] = v,
+                Factor::Parameter(i) => petri.parameters[*i].value = v,
+            }
+        }
+        let yvals = petri.simulate(&controls, STEPS);
+        yvals.row(STEPS - 1).mapv(|y| y as f64)
+    }
+
+    fn eval_rows(&self, us: &Array2<f64>) -> Array2<f64> {
+        let n = self.petri.species.len();
+        let mut out = Array::zeros((us.nrows(), n));
+        for (u, mut row) in us.outer_iter().zip(out.outer_iter_mut()) {
+            row.assign(&self.eval(u));
+        }
+        out
+    }
+}
+
+fn uniform_matrix<R: Rng + ?Sized>(rng: &mut R, n: usize, d: usize) -> Array2<f64> {
+    Array::from_shape_fn((n, d), |_| rng.gen::<f64>())
+}
+
+// Saltelli sampling with the Saltelli (2010) first-order and Jansen total-order
+// estimators. Costs `samples * (d + 2)` ODE solves.
+pub fn sobol_indices<R: Rng + ?Sized>(petri: &PetriNet, controls: &PlotControls, samples: usize, rng: &mut R) -> SobolIndices {
+    let problem = Problem::new(petri, controls);
+    let (d, n) = (problem.num_params(), petri.species.len());
+    let a = uniform_matrix(rng, samples, d);
+    let b = uniform_matrix(rng, samples, d);
+    let fa = problem.eval_rows(&a);
+    let fb = problem.eval_rows(&b);
+    let variance = ndarray::stack(Axis(0), &[fa.view(), fb.view()]).unwrap().var_axis(Axis(0), 0.);
+
+    let mut first_order = Array::zeros((d, n));
+    let mut total = Array::zeros((d, n));
+    for i in 0..d {
+        let mut ab = a.clone();
+        ab.column_mut(i).assign(&b.column(i));
+        let fab = problem.eval_rows(&ab);
+        let s = (&fb * &(&fab - &fa)).mean_axis(Axis(0)).unwrap();
+        let st = (&fa - &fab).mapv(|x| x * x).mean_axis(Axis(0)).unwrap() * 0.5;
+        for j in 0..n {
+            if variance[j] > 0. {
+                first_order[(i, j)] = s[j] / variance[j];
+                total[(i, j)] = st[j] / variance[j];
+            }
+        }
+    }
+    SobolIndices { factors: problem.names(), first_order, total }
+}
+
+// Morris elementary effects on a `levels`-level grid, one-at-a-time
+// trajectories. Effects are reported in units of the full range of each factor.
+pub fn morris_effects<R: Rng + ?Sized>(petri: &PetriNet, controls: &PlotControls, trajectories: usize, levels: usize, rng: &mut R) -> MorrisEffects {
+    let problem = Problem::new(petri, controls);
+    let (d, n) = (problem.num_params(), petri.species.len());
+    let p = levels.max(2);
+    let delta = p as f64 / (2. * (p - 1) as f64);
+    let base_levels: Vec<f64> = (0..p)
+        .map(|l| l as f64 / (p - 1) as f64)
+        .filter(|x| *x + delta <= 1. + 1e-9)
+        .collect();
+
+    let mut effects = Array3::<f64>::zeros((trajectories, d, n));
+    let mut order: Vec<usize> = (0..d).collect();
+    for r in 0..trajectories {
+        let mut x: Array1<f64> = (0..d).map(|_| *base_levels.choose(rng).unwrap()).collect();
+        let mut fx = problem.eval(x.view());
+        order.shuffle(rng);
+        for &i in order.iter() {
+            x[i] += delta;
+            let fnext = problem.eval(x.view());
+            effects.slice_mut(s![r, i, ..]).assign(&((&fnext - &fx) / delta));
+            fx = fnext;
+        }
+    }
+
+    MorrisEffects {
+        factors: problem.names(),
+        mu_star: effects.mapv(f64::abs).mean_axis(Axis(0)).unwrap(),
+        sigma: effects.std_axis(Axis(0), if trajectories > 1 { 1. } else { 0. }),
+    }
+}
+
+fn indices_csv(factors: &[String], columns: &[(&str, &Array2<f64>)], petri: &PetriNet) -> String {
+    let mut out = String::from("factor,species");
+    for (name, _) in columns.iter() {
+        out.push(',');
+        out.push_str(name);
+    }
+    out.push('\n');
+    for (i, f) in factors.iter().enumerate() {
+        for (j, s) in petri.species.iter().enumerate() {
+            out.push_str(&format!("{},{}", f, s));
+            for (_, m) in columns.iter() {
+                out.push_str(&format!(",{}", m[(i, j)]));
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+impl SobolIndices {
+    pub fn to_csv(&self, petri: &PetriNet) -> String {
+        indices_csv(&self.factors, &[("first_order", &self.first_order), ("total", &self.total)], petri)
+    }
+}
+
+impl MorrisEffects {
+    pub fn to_csv(&self, petri: &PetriNet) -> String {
+        indices_csv(&self.factors, &[("mu_star", &self.mu_star), ("sigma", &self.sigma)], petri)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn plot_bars(petri: &PetriNet, factors: &[String], series: &[(&str, &Array2<f64>)], species: usize, canvas_id: &str)
+        -> DrawResult<(), CanvasBackend> {
+    let d = factors.len();
+    let ymax = series.iter()
+        .flat_map(|(_, m)| m.column(species).to_vec())
+        .fold(1e-6f64, f64::max);
+
+    let backend = CanvasBackend::new(canvas_id).expect("cannot find canvas");
+    let root = backend.into_drawing_area();
+    let font: FontDesc = ("sans-serif", 20.0).into();
+
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&petri.species[species], font)
+        .x_label_area_size(30)
+        .y_label_area_size(30)
+        .build_ranged(0.0..(d as f64), 0.0..ymax)?;
+
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_labels(d.max(1))
+        .x_label_formatter(&|x| {
+            factors.get(x.floor() as usize).cloned().unwrap_or_default()
+        })
+        .draw()?;
+
+    let width = 0.8 / series.len() as f64;
+    for (k, (name, m)) in series.iter().enumerate() {
+        let c = get_color(k);
+        chart
+            .draw_series((0..d).map(|i| {
+                let x0 = i as f64 + 0.1 + width * k as f64;
+                Rectangle::new([(x0, 0.0), (x0 + width, m[(i, species)].max(0.))], c.filled())
+            }))?
+            .label(*name)
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], c.filled()));
+    }
+
+    chart
+        .configure_series_labels()
+        .border_style(&BLACK)
+        .draw()?;
+
+    root.present()?;
+    Ok(())
+}
@@ -3,12 +3,20 @@ use rand::distributions::{Distribution};
 use statrs::distribution::{Exponential, Categorical};
 use ndarray::prelude::*;
 use crate::math::*;
+use crate::events::*;
+use crate::kinetics::Propensity;
 use std::fmt;
 
+// Pieces of a step over which time varying rates and conditions are sampled
+const SUBSTEPS: usize = 16;
+
 #[derive(Clone)]
 pub struct PetriState {
-    state: Array<i32, Ix1>,
-    time_elapsed: f64
+    pub(crate) state: Array<i32, Ix1>,
+    pub(crate) time_elapsed: f64,
+    // Outputs of delayed transitions still to appear, as (time, transition)
+    // sorted by time
+    pub(crate) pending: Vec<(f64, usize)>
 }
 
 impl fmt::Debug for PetriState {
@@ -28,42 +36,194 @@ fn falling_exp(n: i32, k: i32) -> i32 {
 }
 
 impl PetriState {
-    fn transition_rate(&self, petri: &PetriData, rates: &Array<f64, Ix1>, t: usize) -> f64 {
-        let n = petri.num_species();
-        let combinations: i32 = (0..n)
-            .map(|s| { falling_exp(self.state[s],petri.data()[(t,0,s)]) })
-            .product();
-        (combinations as f64) * rates[t]
+    pub(crate) fn new(state: Array<i32, Ix1>) -> Self {
+        PetriState { state, time_elapsed: 0.0, pending: vec![] }
+    }
+
+    pub(crate) fn pops(&self) -> Vec<f64> {
+        self.state.iter().map(|x| *x as f64).collect()
+    }
+
+    pub(crate) fn transition_rate(&self, petri: &PetriData, rates: &[f64], parameters: &[f64], t: usize) -> f64 {
+        self.transition_rate_at(petri, rates, parameters, self.time_elapsed, t)
+    }
+
+    // The rate of transition `t` at `time`, with the current state
+    fn transition_rate_at(&self, petri: &PetriData, rates: &[f64], parameters: &[f64], time: f64, t: usize) -> f64 {
+        let pops = self.pops();
+        let env = [&pops[..], rates, parameters, &[time]];
+        let needed = &petri.data().slice(s![t,0,..]) + &petri.data().slice(s![t,2,..]);
+        // Rates given by expressions need not vanish when tokens run out
+        if self.state.iter().zip(needed.iter()).any(|(m, k)| m < k) {
+            return 0.;
+        }
+        petri.propensity(t, &env, |pop, k| falling_exp(pop as i32, k) as f64)
+    }
+
+    fn flow_out(&self, petri: &PetriData, rates: &[f64], parameters: &[f64]) -> Array<f64,Ix1> {
+        self.flow_out_at(petri, rates, parameters, self.time_elapsed)
     }
 
-    fn apply_transition(&mut self, petri: &PetriData, t: usize) {
+    fn flow_out_at(&self, petri: &PetriData, rates: &[f64], parameters: &[f64], time: f64) -> Array<f64,Ix1> {
+        (0..rates.len()).map(|t| self.transition_rate_at(petri,rates,parameters,time,t)).collect()
+    }
+
+    // Fires transition `t` at the current time. The outputs of a delayed
+    // transition are queued until the delay has passed.
+    pub(crate) fn apply_transition(&mut self, petri: &PetriData, t: usize) {
         let d = petri.data();
+        let delay = petri.delays().get(t).cloned().unwrap_or(0.);
         for s in 0..petri.num_species() {
-            self.state[s] += - d[(t,0,s)] + d[(t,1,s)];
+            self.state[s] -= d[(t,0,s)];
+            if delay <= 0. {
+                self.state[s] += d[(t,1,s)];
+            }
+        }
+        if delay > 0. {
+            let at = self.time_elapsed + delay as f64;
+            let i = self.pending.partition_point(|(s, _)| *s <= at);
+            self.pending.insert(i, (at, t));
+        }
+    }
+
+    pub(crate) fn next_delivery(&self) -> Option<f64> {
+        self.pending.first().map(|(s, _)| *s)
+    }
+
+    // Adds the queued outputs that are due by the current time
+    pub(crate) fn deliver(&mut self, petri: &PetriData) {
+        let due = self.pending.partition_point(|(s, _)| *s <= self.time_elapsed);
+        for (_, t) in self.pending.drain(..due) {
+            for s in 0..petri.num_species() {
+                self.state[s] += petri.data()[(t,1,s)];
+            }
         }
     }
 
 
     fn step<R: Rng + ?Sized>(&mut self, petri: &PetriData, rates: &Array<f64,Ix1>, rng: &mut R) {
-        let m = rates.len();
-        let flow_out: Array<f64,Ix1> = (0..m).map(|t| self.transition_rate(petri,rates,t)).collect();
+        let flow_out = self.flow_out(petri, rates.as_slice().unwrap(), petri.parameters());
         let total_flow_out = flow_out.sum();
         let probabilities = flow_out / total_flow_out;
-        let time_dist = Exponential::new(total_flow_out).unwrap();
-        let dt = time_dist.sample(rng);
-        let transition_dist = Categorical::new(probabilities.as_slice().unwrap()).unwrap();
-        let transition: f64 = transition_dist.sample(rng);
-        self.apply_transition(petri,transition as usize);
-        self.time_elapsed += dt;
+        // With nothing enabled, only the queued outputs can change the state
+        let dt = if total_flow_out > 0. || self.pending.is_empty() {
+            Exponential::new(total_flow_out).unwrap().sample(rng)
+        } else {
+            f64::INFINITY
+        };
+        match self.next_delivery() {
+            Some(s) if s < self.time_elapsed + dt => {
+                self.time_elapsed = s;
+            }
+            _otherwise => {
+                let transition_dist = Categorical::new(probabilities.as_slice().unwrap()).unwrap();
+                let transition: f64 = transition_dist.sample(rng);
+                self.time_elapsed += dt;
+                self.apply_transition(petri,transition as usize);
+            }
+        }
+        self.deliver(petri);
+    }
+
+    pub(crate) fn poll(&mut self, dynamics: &Dynamics, st: &mut EventState) {
+        let mut pops = self.pops();
+        dynamics.poll(self.time_elapsed, &mut pops, st);
+        for (s, p) in self.state.iter_mut().zip(pops.iter()) {
+            *s = p.round() as i32;
+        }
+    }
+
+    // Like `step`, but with time varying rates, and stopping at `horizon`, or
+    // earlier at the next scheduled event, rate breakpoint or delayed output.
+    // Since waiting times are memoryless, stopping early and resampling is
+    // exact for piecewise constant rates. The state is constant between
+    // firings, but rates given by expressions and conditions may still depend
+    // on `t`: then the firing time is where the total rate, integrated by the
+    // midpoint rule over `SUBSTEPS` pieces, reaches an exponential sample, and
+    // the step stops early where a condition becomes true, located by
+    // bisection as in `solve_with_events`.
+    fn step_with_events<R: Rng + ?Sized>(&mut self, petri: &PetriData, dynamics: &Dynamics,
+                                         st: &mut EventState, horizon: f64, rng: &mut R) {
+        let t = self.time_elapsed;
+        let stop = dynamics.next_stop(t, st).into_iter().chain(self.next_delivery())
+            .fold(horizon, f64::min);
+        let pops = self.pops();
+        let flow_out = |s: f64| {
+            let rates = dynamics.rates_at(s, &pops, st);
+            self.flow_out_at(petri, &rates, &st.parameters, s)
+        };
+        let time_varying = dynamics.varies_in_time()
+            || petri.propensities().iter().any(|p| matches!(p, Propensity::Expression(_)));
+        let pieces = if time_varying { SUBSTEPS } else { 1 };
+        let target: f64 = Exponential::new(1.).unwrap().sample(rng);
+        let mut hazard = 0.;
+        let (mut end, mut fires) = (stop, false);
+        for k in 0..pieces {
+            let a = t + (stop - t) * k as f64 / pieces as f64;
+            let b = if k + 1 == pieces { stop } else { t + (stop - t) * (k + 1) as f64 / pieces as f64 };
+            let total = flow_out(0.5 * (a + b)).sum();
+            let firing = if total > 0. && hazard + total * (b - a) >= target {
+                Some(a + (target - hazard) / total)
+            } else {
+                None
+            };
+            let piece_end = firing.unwrap_or(b);
+            if dynamics.crossing(piece_end, &pops, st) {
+                let (mut lo, mut hi) = (a, piece_end);
+                for _ in 0..50 {
+                    let mid = 0.5 * (lo + hi);
+                    if dynamics.crossing(mid, &pops, st) {
+                        hi = mid;
+                    } else {
+                        lo = mid;
+                    }
+                }
+                end = hi;
+                break;
+            }
+            if let Some(s) = firing {
+                end = s;
+                fires = true;
+                break;
+            }
+            hazard += total * (b - a);
+        }
+        let flows = if fires { Some(flow_out(end)) } else { None };
+        self.time_elapsed = end;
+        if let Some(flows) = flows {
+            let total_flow_out = flows.sum();
+            if total_flow_out > 0. {
+                let probabilities = flows / total_flow_out;
+                let transition: f64 = Categorical::new(probabilities.as_slice().unwrap()).unwrap().sample(rng);
+                self.apply_transition(petri,transition as usize);
+            }
+        }
+        self.deliver(petri);
+        self.poll(dynamics, st);
+    }
+}
+
+// A stochastic trajectory on [0, t1], sampled on the same grid as `rk_solve`,
+// with events handled exactly
+pub fn simulate<R: Rng + ?Sized>(petri: &PetriData, dynamics: &Dynamics, initial_state: &Array<i32,Ix1>,
+                                 t1: f64, steps: usize, rng: &mut R) -> YVals {
+    let mut yvals = Array::zeros((steps, initial_state.len()));
+    let mut st = dynamics.initial_state(petri.parameters());
+    let mut ps = PetriState::new(initial_state.clone());
+    ps.poll(dynamics, &mut st);
+    for (i, mut cell) in yvals.axis_iter_mut(Axis(0)).enumerate() {
+        let t_out = (i + 1) as f64 / (steps as f64) * t1;
+        while ps.time_elapsed < t_out {
+            ps.step_with_events(petri, dynamics, &mut st, t_out, rng);
+        }
+        cell.assign(&ps.state.mapv(|x| x as f32));
     }
+    yvals
 }
 
 pub fn sample_extinction_time(initial_state: &Array<i32,Ix1>, petri: &PetriData, rates: &Array<f64,Ix1>, species: &[usize]) -> f64 {
     let mut rng = rand::thread_rng();
-    let mut ps = PetriState {
-        state: initial_state.clone(),
-        time_elapsed: 0.0
-    };
+    let mut ps = PetriState::new(initial_state.clone());
     'outer: loop {
         for s in species.iter() {
             if ps.state[*s] <= 0 {
@@ -0,0 +1,161 @@
+use serde::{Serialize, Deserialize};
+use ndarray::prelude::*;
+use plotters::prelude::*;
+use plotters::coord::Shift;
+use crate::math::*;
+use crate::petri::*;
+use crate::kinetics::*;
+use crate::patches::draw_lines;
+
+// Linear chain expansion of k-stage transitions. A transition with rate r and
+// k stages becomes a chain of k transitions, each with rate k r, through k - 1
+// intermediate species, so the time from entering the chain to leaving it is
+// Erlang distributed with mean 1 / r.
+//
+// The first stage keeps the name, inputs, read and inhibitor arcs, rate law
+// and schedule of the transition, and its rate is k times the original rate.
+// The later stages are mass action transitions named `t_stage2` to
+// `t_stagek`, the last of which produces the outputs, after the output delay
+// if any. The intermediate species `t_stage1` to `t_stage{k-1}` are added
+// after the species of the net, and the later stages after its transitions.
+//
+// Only mass action and frequency-dependent transitions are expanded. The
+// other rate laws have no rate per token to scale the stages by, so their
+// stages are ignored and reported by `PetriNet::check_stages`.
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Staged {
+    pub net: PetriNet,
+    // For each k-stage transition of the original net, its index and the
+    // number of stages
+    pub chains: Vec<(usize, usize)>,
+    // The curves of the collapsed plot: a name and the species of the
+    // expanded net summed into it. The intermediate species of a chain are
+    // summed into the species it starts from if it has a single input
+    // species, and into a curve of their own otherwise.
+    pub groups: Vec<(String, Vec<usize>)>,
+}
+
+impl Transition {
+    // The number of stages of the chain this transition expands to, 1 for none
+    pub fn stage_count(&self) -> usize {
+        match &self.rate_law {
+            RateLaw::MassAction | RateLaw::FrequencyDependent => self.stages.max(1) as usize,
+            _otherwise => 1,
+        }
+    }
+}
+
+impl PetriNet {
+    pub fn check_stages(&self, t: usize) -> Result<(), String> {
+        let t = &self.transitions[t];
+        if t.stages > 1 && t.stage_count() == 1 {
+            Err(format!("stages are ignored with {} kinetics, which has no rate per token", t.rate_law))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn has_stages(&self) -> bool {
+        self.transitions.iter().any(|t| t.stage_count() > 1)
+    }
+
+    pub fn expand_stages(&self) -> Staged {
+        let mut net = self.clone();
+        let mut chains = vec![];
+        let mut groups: Vec<(String, Vec<usize>)> = self.species.iter().enumerate()
+            .map(|(s, name)| (name.clone(), vec![s]))
+            .collect();
+        for (i, original) in self.transitions.iter().enumerate() {
+            let k = original.stage_count();
+            if k == 1 {
+                continue;
+            }
+            chains.push((i, k));
+            // The intermediate species, with the arcs of the net extended to them
+            let first = net.species.len();
+            for j in 1..k {
+                net.species.push(format!("{}_stage{}", original.name, j));
+                for t in net.transitions.iter_mut() {
+                    t.add_species();
+                }
+            }
+            let inputs: Vec<usize> = (0..self.species.len()).filter(|s| original.arc(IO::Input, *s) > 0).collect();
+            match inputs.as_slice() {
+                [s] => groups[*s].1.extend(first..first + k - 1),
+                _otherwise => groups.push((format!("{} stages", original.name), (first..first + k - 1).collect())),
+            }
+
+            let n = net.species.len();
+            let entry = &mut net.transitions[i];
+            entry.output = vec![0; n];
+            entry.output[first] = 1;
+            entry.stages = 0;
+            entry.delay = 0.;
+            entry.rate = None;
+            for j in 1..k {
+                let (mut input, mut output) = (vec![0; n], vec![0; n]);
+                input[first + j - 1] = 1;
+                if j + 1 < k {
+                    output[first + j] = 1;
+                } else {
+                    for (s, w) in output.iter_mut().enumerate().take(self.species.len()) {
+                        *w = original.arc(IO::Output, s);
+                    }
+                }
+                let mut stage = Transition::new(&format!("{}_stage{}", original.name, j + 1), input, output);
+                stage.rate_law = RateLaw::MassAction;
+                if j + 1 == k {
+                    stage.delay = original.delay;
+                }
+                net.transitions.push(stage);
+            }
+        }
+        Staged { net, chains, groups }
+    }
+}
+
+impl Staged {
+    // Controls for the expanded net, with the rates of the chains scaled by
+    // their number of stages
+    pub fn expand_controls(&self, petri: &PetriNet, controls: &PlotControls) -> PlotControls {
+        let mut out = controls.clone();
+        out.rates = petri.effective_rates(controls);
+        out.rate_ranges = vec![];
+        for (i, k) in self.chains.iter() {
+            out.rates[*i] *= *k as f32;
+        }
+        for (i, k) in self.chains.iter() {
+            let rate = out.rates[*i];
+            // `repeat_n` needs Rust 1.82
+            #[allow(clippy::manual_repeat_n)]
+            out.rates.extend(std::iter::repeat(rate).take(k - 1));
+        }
+        out.init_vals.resize(self.net.species.len(), 0.);
+        out
+    }
+
+    pub fn group_names(&self) -> Vec<String> {
+        self.groups.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    // A trajectory of the expanded net with the stages of each chain summed
+    // together, one column per group
+    pub fn collapse(&self, yvals: &YVals) -> YVals {
+        let mut out = Array::zeros((yvals.nrows(), self.groups.len()));
+        for (g, (_, species)) in self.groups.iter().enumerate() {
+            for s in species.iter() {
+                let mut column = out.column_mut(g);
+                column += &yvals.column(*s);
+            }
+        }
+        out
+    }
+
+    // Plots a trajectory of the expanded net with the stages collapsed
+    pub fn draw<DB: DrawingBackend>(&self, controls: &PlotControls, root: &DrawingArea<DB, Shift>) -> DrawResult<(), DB> {
+        let yvals = self.net.simulate(controls, STEPS);
+        root.fill(&WHITE)?;
+        draw_lines(root, &self.net.name, &self.group_names(), &self.collapse(&yvals), controls, 20.0)
+    }
+}
@@ -0,0 +1,123 @@
+use rand::Rng;
+use serde_json::{json, Map, Value};
+use ndarray::prelude::*;
+use crate::math::*;
+use crate::petri::*;
+use crate::events::*;
+use crate::gsmp;
+
+// Simulation results as tables, for export: a time column and a column per
+// species, or per statistic of a species for ensembles. Row i holds the
+// values at time (i + 1) t1 / steps, the grid of `rk_solve` and the SSA.
+
+pub struct Trajectory {
+    pub columns: Vec<String>,
+    pub t1: f32,
+    pub values: YVals,
+}
+
+// Quantiles reported by ensemble summaries, besides the mean and deviation
+pub static QUANTILES: [f32; 3] = [0.05, 0.5, 0.95];
+
+// A field of a CSV record, quoted if it has a separator, quote or newline
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl Trajectory {
+    pub fn new(columns: Vec<String>, t1: f32, values: YVals) -> Self {
+        Trajectory { columns, t1, values }
+    }
+
+    pub fn times(&self) -> Vec<f32> {
+        let steps = self.values.nrows();
+        (0..steps).map(|i| (i + 1) as f32 * self.t1 / steps as f32).collect()
+    }
+
+    // A header `t,A,B,...` and a record per time
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        let header: Vec<String> = std::iter::once("t").chain(self.columns.iter().map(|c| c.as_str()))
+            .map(csv_field)
+            .collect();
+        out.push_str(&header.join(","));
+        out.push('\n');
+        for (t, row) in self.times().iter().zip(self.values.outer_iter()) {
+            let record: Vec<String> = std::iter::once(*t).chain(row.iter().cloned())
+                .map(|x| x.to_string())
+                .collect();
+            out.push_str(&record.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    // `{"t": [...], "columns": {"A": [...], ...}}`
+    pub fn to_json(&self) -> String {
+        let columns: Map<String, Value> = self.columns.iter().zip(self.values.axis_iter(Axis(1)))
+            .map(|(c, v)| (c.clone(), json!(v.to_vec())))
+            .collect();
+        serde_json::to_string_pretty(&json!({ "t": self.times(), "columns": columns })).unwrap()
+    }
+}
+
+// Initial values as token counts, for the stochastic simulations
+fn initial_state(controls: &PlotControls, n: usize) -> Array1<i32> {
+    (0..n).map(|s| controls.init_vals.get(s).cloned().unwrap_or(0.).round().max(0.) as i32).collect()
+}
+
+impl PetriNet {
+    // The ODE solution on [0, xmax]
+    pub fn trajectory(&self, controls: &PlotControls, steps: usize) -> Trajectory {
+        Trajectory::new(self.species.clone(), controls.xmax, self.simulate(controls, steps))
+    }
+
+    // A stochastic trajectory on [0, xmax], starting from the initial values
+    // rounded to whole numbers of tokens
+    pub fn stochastic_trajectory<R: Rng + ?Sized>(&self, controls: &PlotControls, steps: usize, rng: &mut R) -> Trajectory {
+        let dynamics = Dynamics::new(self, controls);
+        let init = initial_state(controls, self.species.len());
+        Trajectory::new(self.species.clone(), controls.xmax,
+                        gsmp::simulate(self, &dynamics, &init, controls.xmax as f64, steps, rng))
+    }
+
+    // The mean, standard deviation and `QUANTILES` of each species over `runs`
+    // stochastic trajectories, in columns `A mean`, `A sd`, `A q0.05`, ...
+    pub fn ensemble_summary<R: Rng + ?Sized>(&self, controls: &PlotControls, steps: usize, runs: usize, rng: &mut R) -> Trajectory {
+        let dynamics = Dynamics::new(self, controls);
+        let init = initial_state(controls, self.species.len());
+        let samples: Vec<YVals> = (0..runs)
+            .map(|_| gsmp::simulate(self, &dynamics, &init, controls.xmax as f64, steps, rng))
+            .collect();
+        let stats = 2 + QUANTILES.len();
+        let n = self.species.len();
+        let mut values = Array::zeros((steps, n * stats));
+        for i in 0..steps {
+            for s in 0..n {
+                let mut xs: Vec<f32> = samples.iter().map(|y| y[(i, s)]).collect();
+                if xs.is_empty() {
+                    continue;
+                }
+                xs.sort_by(|a, b| a.total_cmp(b));
+                let mean = xs.iter().sum::<f32>() / xs.len() as f32;
+                let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / (xs.len().max(2) - 1) as f32;
+                values[(i, s * stats)] = mean;
+                values[(i, s * stats + 1)] = var.sqrt();
+                for (k, q) in QUANTILES.iter().enumerate() {
+                    values[(i, s * stats + 2 + k)] = xs[((xs.len() - 1) as f32 * q).round() as usize];
+                }
+            }
+        }
+        let columns = self.species.iter()
+            .flat_map(|s| {
+                vec![format!("{} mean", s), format!("{} sd", s)].into_iter()
+                    .chain(QUANTILES.iter().map(move |q| format!("{} q{}", s, q)))
+            })
+            .collect();
+        Trajectory::new(columns, controls.xmax, values)
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Serialize, Deserialize};
+use crate::petri::*;
+
+// Petri nets typed over a type net, and stratification by pullback, following
+// the typed product of AlgebraicJulia. A typing sends each species and each
+// transition to one of the type net, and each arc of a transition to the arc
+// in the same position of its type transition. Arcs are listed one per token,
+// so a transition with input [1, 2] has the input arcs [0, 1, 1].
+//
+// The pullback of two nets typed over the same type net has a species for each
+// pair of species of the same type, and a transition for each pair of
+// transitions of the same type, whose arcs pair up the arcs of the two
+// transitions position by position. Transitions that should leave the other
+// net's species unchanged, like aging in a disease model, are included by
+// giving each net reflexive transitions `X -> X` of the corresponding types.
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TypedPetriNet {
+    pub net: PetriNet,
+    // Index of the type of each species and transition in the type net
+    pub species_types: Vec<usize>,
+    pub transition_types: Vec<usize>,
+    // Ordered input and output arcs of each transition, as species indices.
+    // Empty to list them in species order.
+    #[serde(default)]
+    pub arcs: Vec<(Vec<usize>, Vec<usize>)>,
+}
+
+// Arcs from a count vector, in species order. `repeat_n` needs Rust 1.82.
+#[allow(clippy::manual_repeat_n)]
+fn arcs_of(counts: &[i32]) -> Vec<usize> {
+    counts.iter().enumerate()
+        .flat_map(|(s, k)| std::iter::repeat(s).take((*k).max(0) as usize))
+        .collect()
+}
+
+fn counts_of(arcs: &[usize], n: usize) -> Vec<i32> {
+    let mut counts = vec![0; n];
+    for s in arcs.iter() {
+        counts[*s] += 1;
+    }
+    counts
+}
+
+impl TypedPetriNet {
+    pub fn new(net: PetriNet, species_types: Vec<usize>, transition_types: Vec<usize>) -> Self {
+        TypedPetriNet { net, species_types, transition_types, arcs: vec![] }
+    }
+
+    pub fn transition_arcs(&self, t: usize) -> (Vec<usize>, Vec<usize>) {
+        match self.arcs.get(t) {
+            Some(a) => a.clone(),
+            None => {
+                let trans = &self.net.transitions[t];
+                (arcs_of(&trans.input), arcs_of(&trans.output))
+            }
+        }
+    }
+
+    // Checks that the typing is a morphism into `type_net`
+    pub fn check(&self, type_net: &PetriNet) -> Result<(), String> {
+        let (n, m) = (self.net.species.len(), self.net.transitions.len());
+        if self.species_types.len() != n || self.transition_types.len() != m {
+            return Err(format!("{}: every species and transition needs a type", self.net.name));
+        }
+        if let Some(s) = self.species_types.iter().position(|k| *k >= type_net.species.len()) {
+            return Err(format!("{}: species {} has no type", self.net.name, self.net.species[s]));
+        }
+        for (t, trans) in self.net.transitions.iter().enumerate() {
+            let ty = match type_net.transitions.get(self.transition_types[t]) {
+                Some(ty) => ty,
+                None => return Err(format!("{}: transition {} has no type", self.net.name, trans.name)),
+            };
+            let (input, output) = self.transition_arcs(t);
+            if input.iter().chain(output.iter()).any(|s| *s >= n)
+                    || counts_of(&input, n) != trans.input || counts_of(&output, n) != trans.output {
+                return Err(format!("{}: the arcs of transition {} do not match its inputs and outputs",
+                                   self.net.name, trans.name));
+            }
+            let typed = |arcs: &[usize]| arcs.iter().map(|s| self.species_types[*s]).collect::<Vec<_>>();
+            if typed(&input) != arcs_of(&ty.input) || typed(&output) != arcs_of(&ty.output) {
+                return Err(format!("{}: the arcs of transition {} do not match those of its type {}",
+                                   self.net.name, trans.name, ty.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+// The typed product of `a` and `b`, with species and transitions named by
+// joining the names of the pairs with `_`. The result is typed over the same
+// type net, so it can be stratified further. Rates are left to be set on the
+// stratified net.
+pub fn pullback(a: &TypedPetriNet, b: &TypedPetriNet, type_net: &PetriNet) -> Result<TypedPetriNet, String> {
+    a.check(type_net)?;
+    b.check(type_net)?;
+    let pairs = |x: &[usize], y: &[usize]| -> Vec<(usize, usize)> {
+        x.iter().enumerate()
+            .flat_map(|(i, tx)| y.iter().enumerate().filter(move |(_, ty)| *ty == tx).map(move |(j, _)| (i, j)))
+            .collect()
+    };
+    let species = pairs(&a.species_types, &b.species_types);
+    let transitions = pairs(&a.transition_types, &b.transition_types);
+    let n = species.len();
+
+    let mut net = PetriNet::empty();
+    net.name = format!("{} x {}", a.net.name, b.net.name);
+    net.species = species.iter()
+        .map(|(i, j)| format!("{}_{}", a.net.species[*i], b.net.species[*j]))
+        .collect();
+    let mut arcs = vec![];
+    for (i, j) in transitions.iter() {
+        let (ai, ao) = a.transition_arcs(*i);
+        let (bi, bo) = b.transition_arcs(*j);
+        // The types of the arcs agree position by position, so the pairs are species
+        let zip = |x: &[usize], y: &[usize]| -> Vec<usize> {
+            x.iter().zip(y.iter())
+                .map(|(s, r)| species.iter().position(|p| *p == (*s, *r)).unwrap())
+                .collect()
+        };
+        let (input, output) = (zip(&ai, &bi), zip(&ao, &bo));
+        let name = format!("{}_{}", a.net.transitions[*i].name, b.net.transitions[*j].name);
+        net.transitions.push(Transition::new(&name, counts_of(&input, n), counts_of(&output, n)));
+        arcs.push((input, output));
+    }
+    Ok(TypedPetriNet {
+        net,
+        species_types: species.iter().map(|(i, _)| a.species_types[*i]).collect(),
+        transition_types: transitions.iter().map(|(i, _)| a.transition_types[*i]).collect(),
+        arcs,
+    })
+}
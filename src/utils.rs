@@ -8,3 +8,25 @@ pub fn set_panic_hook() {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
 }
+
+// Offers `contents` for download as a file, through a link to a data URL
+pub fn download(filename: &str, mime: &str, contents: &str) {
+    download_url(filename, &format!("data:{};base64,{}", mime, base64::encode(contents)));
+}
+
+pub fn download_url(filename: &str, url: &str) {
+    use wasm_bindgen::JsCast;
+    let document = web_sys::window().unwrap().document().unwrap();
+    let link = document.create_element("a").unwrap();
+    link.set_attribute("href", url).unwrap();
+    link.set_attribute("download", filename).unwrap();
+    link.dyn_into::<web_sys::HtmlElement>().unwrap().click();
+}
+
+// The picture on a canvas, as a PNG data URL
+pub fn canvas_png(canvas_id: &str) -> Option<String> {
+    use wasm_bindgen::JsCast;
+    let document = web_sys::window()?.document()?;
+    let canvas = document.get_element_by_id(canvas_id)?.dyn_into::<web_sys::HtmlCanvasElement>().ok()?;
+    canvas.to_data_url_with_type("image/png").ok()
+}
@@ -0,0 +1,335 @@
+use std::fmt;
+
+// Just enough XML for exchanging models: elements, attributes and text, with
+// comments, processing instructions and the document type skipped. Namespace
+// prefixes are kept as part of the names, and `Element::local_name` strips
+// them. There is no validation beyond matching tags.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct XmlError {
+    // One-based line and column in the source
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub fn new(name: &str) -> Self {
+        Element { name: name.to_string(), ..Default::default() }
+    }
+
+    pub fn local_name(&self) -> &str {
+        self.name.rsplit(':').next().unwrap_or(&self.name)
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn with_attr(mut self, name: &str, value: impl ToString) -> Self {
+        self.attributes.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_child(mut self, child: Element) -> Self {
+        self.children.push(Node::Element(child));
+        self
+    }
+
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.children.push(Node::Text(text.to_string()));
+        self
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|c| match c {
+            Node::Element(e) => Some(e),
+            Node::Text(_) => None,
+        })
+    }
+
+    // The first child element with the given local name
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.elements().find(|e| e.local_name() == name)
+    }
+
+    // The child elements with the given local name
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.elements().filter(move |e| e.local_name() == name)
+    }
+
+    // The text directly inside the element, trimmed
+    pub fn text(&self) -> String {
+        let text: String = self.children.iter()
+            .filter_map(|c| match c {
+                Node::Text(t) => Some(t.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect();
+        text.trim().to_string()
+    }
+
+    // Indented XML, with elements holding only text kept on one line
+    pub fn write(&self, out: &mut String, indent: usize) {
+        let pad = "  ".repeat(indent);
+        out.push_str(&pad);
+        out.push('<');
+        out.push_str(&self.name);
+        for (k, v) in self.attributes.iter() {
+            out.push_str(&format!(" {}=\"{}\"", k, escape(v)));
+        }
+        if self.children.is_empty() {
+            out.push_str("/>\n");
+        } else if self.children.iter().all(|c| matches!(c, Node::Text(_))) {
+            out.push('>');
+            out.push_str(&escape(&self.text()));
+            out.push_str(&format!("</{}>\n", self.name));
+        } else {
+            out.push_str(">\n");
+            for c in self.children.iter() {
+                match c {
+                    Node::Element(e) => e.write(out, indent + 1),
+                    Node::Text(t) if t.trim().is_empty() => { }
+                    Node::Text(t) => {
+                        out.push_str(&"  ".repeat(indent + 1));
+                        out.push_str(&escape(t.trim()));
+                        out.push('\n');
+                    }
+                }
+            }
+            out.push_str(&format!("{}</{}>\n", pad, self.name));
+        }
+    }
+
+    // A document with this element as the root
+    pub fn to_document(&self) -> String {
+        let mut out = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+        self.write(&mut out, 0);
+        out
+    }
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// An identifier for `name`, made of ASCII letters, digits and underscores
+// and distinct from those in `used`, to which it is added
+pub fn unique_id(name: &str, used: &mut Vec<String>) -> String {
+    let mut id: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect();
+    if !id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        id.insert(0, '_');
+    }
+    let mut unique = id.clone();
+    let mut k = 2;
+    while used.contains(&unique) {
+        unique = format!("{}_{}", id, k);
+        k += 1;
+    }
+    used.push(unique.clone());
+    unique
+}
+
+struct Reader<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error<T>(&self, pos: usize, message: impl Into<String>) -> Result<T, XmlError> {
+        let before = &self.src[..pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+        Err(XmlError { line, column, message: message.into() })
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // Skips past `end`, which must follow
+    fn skip_past(&mut self, end: &str, what: &str) -> Result<(), XmlError> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => self.error(self.pos, format!("unterminated {}", what)),
+        }
+    }
+
+    // Skips comments, processing instructions and the document type
+    fn skip_misc(&mut self) -> Result<(), XmlError> {
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("<!--") {
+                self.skip_past("-->", "comment")?;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>", "processing instruction")?;
+            } else if rest.starts_with("<!DOCTYPE") {
+                self.skip_past(">", "document type")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, XmlError> {
+        let rest = self.rest();
+        let len = rest.find(|c: char| c.is_whitespace() || "/>=".contains(c)).unwrap_or(rest.len());
+        if len == 0 {
+            return self.error(self.pos, "expected a name");
+        }
+        self.pos += len;
+        Ok(rest[..len].to_string())
+    }
+
+    fn element(&mut self) -> Result<Element, XmlError> {
+        let start = self.pos;
+        if !self.rest().starts_with('<') {
+            return self.error(self.pos, "expected an element");
+        }
+        self.pos += 1;
+        let mut element = Element::new(&self.name()?);
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                return Ok(element);
+            } else if rest.starts_with('>') {
+                self.pos += 1;
+                break;
+            } else if rest.is_empty() {
+                return self.error(start, format!("unterminated tag <{}>", element.name));
+            }
+            let key = self.name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return self.error(self.pos, format!("expected = after attribute {}", key));
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(q) if q == '"' || q == '\'' => q,
+                _otherwise => return self.error(self.pos, format!("expected a quoted value for attribute {}", key)),
+            };
+            self.pos += 1;
+            let value_start = self.pos;
+            let len = match self.rest().find(quote) {
+                Some(len) => len,
+                None => return self.error(value_start, "unterminated attribute value"),
+            };
+            let value = self.unescape(value_start, &self.src[value_start..value_start + len])?;
+            self.pos += len + 1;
+            element.attributes.push((key, value));
+        }
+        // Content up to the closing tag
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.pos += 2;
+                let close = self.pos;
+                let name = self.name()?;
+                if name != element.name {
+                    return self.error(close, format!("expected </{}>, found </{}>", element.name, name));
+                }
+                self.skip_whitespace();
+                if !self.rest().starts_with('>') {
+                    return self.error(self.pos, "expected >");
+                }
+                self.pos += 1;
+                return Ok(element);
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->", "comment")?;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>", "processing instruction")?;
+            } else if rest.starts_with("<![CDATA[") {
+                let text_start = self.pos + 9;
+                self.skip_past("]]>", "CDATA section")?;
+                element.children.push(Node::Text(self.src[text_start..self.pos - 3].to_string()));
+            } else if rest.starts_with('<') {
+                element.children.push(Node::Element(self.element()?));
+            } else if rest.is_empty() {
+                return self.error(start, format!("<{}> is never closed", element.name));
+            } else {
+                let len = rest.find('<').unwrap_or(rest.len());
+                let text = self.unescape(self.pos, &rest[..len])?;
+                self.pos += len;
+                element.children.push(Node::Text(text));
+            }
+        }
+    }
+
+    fn unescape(&self, pos: usize, s: &str) -> Result<String, XmlError> {
+        let mut out = String::new();
+        let mut rest = s;
+        while let Some(i) = rest.find('&') {
+            out.push_str(&rest[..i]);
+            let end = match rest[i..].find(';') {
+                Some(end) => i + end,
+                None => return self.error(pos + (s.len() - rest.len()) + i, "unterminated entity"),
+            };
+            let entity = &rest[i + 1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(std::char::from_u32),
+                _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(std::char::from_u32),
+                _otherwise => None,
+            };
+            match c {
+                Some(c) => out.push(c),
+                None => return self.error(pos + (s.len() - rest.len()) + i, format!("unknown entity &{};", entity)),
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+// Parses a document, returning its root element
+pub fn parse(src: &str) -> Result<Element, XmlError> {
+    let src = src.trim_start_matches('\u{feff}');
+    let mut reader = Reader { src, pos: 0 };
+    reader.skip_misc()?;
+    let root = reader.element()?;
+    reader.skip_misc()?;
+    if !reader.rest().is_empty() {
+        return reader.error(reader.pos, "unexpected content after the root element");
+    }
+    Ok(root)
+}
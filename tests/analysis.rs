@@ -0,0 +1,101 @@
+//! Native tests for the model analyses.
+
+use ez_petri::petri::*;
+use ez_petri::sensitivity::*;
+use ez_petri::identifiability::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+fn decay() -> (PetriNet, PlotControls) {
+    let petri = PetriNet::new("decay", vec!["A".to_string()], vec![
+        Transition::new("fast", vec![1], vec![0]),
+        Transition::new("fixed", vec![1], vec![0]),
+    ]);
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![1.0];
+    controls.rates = vec![0.5, 0.5];
+    controls.xmax = 2.0;
+    controls.set_rate_range(0, (0.0, 1.0));
+    controls.set_rate_range(1, (0.5, 0.5));
+    (petri, controls)
+}
+
+#[test]
+fn sensitivity_ignores_fixed_rates() {
+    let (petri, controls) = decay();
+    let mut rng = StdRng::seed_from_u64(0);
+    let sobol = sobol_indices(&petri, &controls, 64, &mut rng);
+    assert!(sobol.total[(0, 0)] > 0.5);
+    assert!(sobol.total[(1, 0)].abs() < 1e-6);
+
+    let morris = morris_effects(&petri, &controls, 8, 4, &mut rng);
+    assert!(morris.mu_star[(0, 0)] > 0.0);
+    assert!(morris.mu_star[(1, 0)].abs() < 1e-6);
+}
+
+#[test]
+fn sensitivity_samples_parameters_within_their_bounds() {
+    let (mut petri, controls) = decay();
+    petri.parameters = vec![Parameter::new("k", 0.5)];
+    petri.transitions[0].rate = Some("k".to_string());
+    let mut rng = StdRng::seed_from_u64(0);
+    let sobol = sobol_indices(&petri, &controls, 64, &mut rng);
+    // The rate range of the bound transition is not sampled
+    assert_eq!(sobol.factors, vec!["fixed".to_string(), "k".to_string()]);
+    assert!(sobol.total[(0, 0)].abs() < 1e-6);
+    assert!(sobol.total[(1, 0)] > 0.5);
+
+    petri.parameters[0].max = 0.0;
+    let morris = morris_effects(&petri, &controls, 8, 4, &mut rng);
+    assert!(morris.mu_star[(1, 0)].abs() < 1e-6);
+}
+
+#[test]
+fn parallel_decay_rates_identifiable_only_in_sum() {
+    let (petri, controls) = decay();
+    let mut rng = StdRng::seed_from_u64(0);
+    let report = identifiability(&petri, &controls.init_vals, &[0], &mut rng).unwrap();
+    assert_eq!(report.parameters[0].1, Identifiable::Not);
+    assert_eq!(report.parameters[1].1, Identifiable::Not);
+    assert_eq!(report.combinations, vec!["fast + fixed".to_string()]);
+}
+
+#[test]
+fn identifiability_reports_expansions_too_large_to_check() {
+    // A cycle of bimolecular reactions, whose Taylor coefficients grow quickly
+    let n = 5;
+    let species = (0..n).map(|i| format!("X{}", i)).collect();
+    let transitions = (0..n)
+        .map(|i| {
+            let (mut input, mut output) = (vec![0; n], vec![0; n]);
+            input[i] += 1;
+            input[(i + 1) % n] += 1;
+            output[(i + 2) % n] += 2;
+            Transition::new(&format!("t{}", i), input, output)
+        })
+        .collect();
+    let petri = PetriNet::new("cycle", species, transitions);
+    let mut rng = StdRng::seed_from_u64(0);
+    let err = identifiability(&petri, &[1.0; 5], &[0], &mut rng).err().unwrap();
+    assert!(err.contains(&format!("more than {} terms", MAX_TERMS)));
+}
+
+#[test]
+fn parameters_drive_rates_and_observables() {
+    let (mut petri, controls) = decay();
+    petri.parameters = vec![Parameter::new("beta", 0.3), Parameter::new("gamma", 0.1)];
+    petri.transitions[0].rate = Some("beta".to_string());
+    petri.observables = vec![
+        Observable { name: "R0".to_string(), expression: "beta / gamma * A".to_string() },
+        Observable { name: "time".to_string(), expression: "t".to_string() },
+    ];
+    let rates = petri.effective_rates(&controls);
+    assert_eq!(rates, vec![0.3, 0.5]);
+
+    let yvals = ndarray::arr2(&[[1.0], [0.5]]);
+    let r0 = petri.observable_values(&yvals, &rates, 2.0);
+    assert!((r0[(0, 0)] - 3.0).abs() < 1e-5);
+    assert!((r0[(1, 0)] - 1.5).abs() < 1e-5);
+    // Rows are at the end of each step
+    assert_eq!(r0.column(1).to_vec(), vec![1.0, 2.0]);
+}
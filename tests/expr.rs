@@ -0,0 +1,43 @@
+//! Native tests for the expression language.
+
+use ez_petri::expr::*;
+
+fn resolve(v: &str) -> Option<(usize, usize)> {
+    ["S", "I", "R"].iter().position(|s| *s == v).map(|i| (0, i))
+        .or_else(|| if v == "k1" { Some((1, 0)) } else { None })
+}
+
+#[test]
+fn evaluates_frequency_dependent_propensity() {
+    let f = compile("k1 * S * I / (S + I + R)", &resolve).unwrap();
+    assert_eq!(f(&[&[90., 10., 0.], &[0.5]]), 0.5 * 90. * 10. / 100.);
+
+    let g = compile("-2^2 + max(S, 3e1) - exp(0)", &resolve).unwrap();
+    assert_eq!(g(&[&[1., 0., 0.], &[]]), -4. + 30. - 1.);
+}
+
+#[test]
+fn reports_error_columns() {
+    assert_eq!(compile("k1 * (S + ", &resolve).err().unwrap().to_string(),
+               "column 11: unexpected end of expression");
+    assert_eq!(compile("k1 * X", &resolve).err().unwrap().to_string(),
+               "column 6: unknown variable X");
+}
+
+#[test]
+fn renames_variables_but_not_functions() {
+    let rename = |v: &str| match v { "S" => Some("S_young".to_string()), "exp" => Some("x".to_string()), _ => None };
+    assert_eq!(rename_variables("k1*S + exp(S)", &rename).unwrap(), "k1*S_young + exp(S_young)");
+}
+
+#[test]
+fn prints_with_minimal_parentheses() {
+    for (src, printed) in [("(k1 * S) * (I / (S + I))", "k1 * S * (I / (S + I))"),
+                           ("-(2^2) + (a - b) - (c - d)", "-2^2 + (a - b) - (c - d)"),
+                           ("(-2)^-x^2 + max(S, 1e-3)", "(-2)^-x^2 + max(S, 0.001)"),
+                           ("(a < b) * (c >= (d == e))", "(a < b) * (c >= (d == e))")].iter() {
+        let e = Expr::parse(src).unwrap();
+        assert_eq!(e.to_string(), *printed);
+        assert_eq!(Expr::parse(printed).unwrap().to_string(), *printed);
+    }
+}
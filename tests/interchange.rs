@@ -0,0 +1,412 @@
+//! Native tests for reading and writing nets in other formats.
+
+use ez_petri::petri::*;
+use ez_petri::events::*;
+use ez_petri::kinetics::*;
+use ez_petri::gsmp::Timing;
+use ez_petri::sbml::*;
+use ez_petri::pnml::*;
+use ez_petri::acset::*;
+use ez_petri::reactions::*;
+use ez_petri::antimony::*;
+use ez_petri::figure::*;
+use ez_petri::diagram::*;
+use ez_petri::codegen::*;
+
+fn sir() -> (PetriNet, PlotControls) {
+    let species = vec!["S".to_string(), "I".to_string(), "R".to_string(), "E".to_string()];
+    let mut treatment = Transition::new("treatment", vec![0, 1, 0, 0], vec![0, 0, 1, 0]);
+    treatment.rate_law = RateLaw::MichaelisMenten { substrate: 1, km: 20. };
+    treatment.read = vec![0, 0, 0, 1];
+    treatment.inhibit = vec![0, 0, 30, 0];
+    let mut petri = PetriNet::new("SIR model", species, vec![
+        Transition::new("infection", vec![1, 1, 0, 0], vec![0, 2, 0, 0]),
+        Transition::new("recovery", vec![0, 1, 0, 0], vec![0, 0, 1, 0]),
+        treatment,
+    ]);
+    petri.parameters = vec![Parameter::new("beta", 0.003)];
+    petri.transitions[0].rate = Some("beta".to_string());
+    petri.observables = vec![Observable { name: "prevalence".to_string(), expression: "I / (S + I + R)".to_string() }];
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![99., 1., 0., 2.];
+    controls.rates = vec![0., 0.1, 0.05];
+    controls.xmax = 50.;
+    (petri, controls)
+}
+
+#[test]
+fn sbml_round_trip_preserves_dynamics() {
+    let (mut petri, controls) = sir();
+    petri.events = vec![Event { trigger: Trigger::At(5.0), actions: vec![Action::AddTokens("S".to_string(), 10.0)] }];
+    let (document, warnings) = write_sbml(&petri, &controls);
+    assert_eq!(warnings, vec!["1 event(s) are not exported".to_string()]);
+
+    let mut import = read_sbml(&document).unwrap();
+    assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+    assert_eq!(import.petri.species, petri.species);
+    assert_eq!(import.controls.init_vals, controls.init_vals);
+    // Mass action is recognised, and rates of their own go back into the controls
+    assert!(matches!(import.petri.transitions[0].rate_law, RateLaw::MassAction));
+    assert_eq!(import.petri.transitions[0].rate, Some("beta".to_string()));
+    assert!(matches!(import.petri.transitions[1].rate_law, RateLaw::MassAction));
+    assert_eq!(import.controls.rates[1], 0.1);
+    assert_eq!(import.petri.observables[0].name, "prevalence");
+
+    petri.events = vec![];
+    import.controls.xmax = controls.xmax;
+    let expected = petri.simulate(&controls, 100);
+    let actual = import.petri.simulate(&import.controls, 100);
+    for (a, b) in expected.iter().zip(actual.iter()) {
+        assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+}
+
+#[test]
+fn sbml_import_reports_unsupported_constructs() {
+    let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sbml xmlns="http://www.sbml.org/sbml/level3/version1/core" level="3" version="1">
+  <model id="dimerisation">
+    <listOfCompartments>
+      <compartment id="cell" size="1" constant="true"/>
+    </listOfCompartments>
+    <listOfSpecies>
+      <species id="M" compartment="cell" initialAmount="50" boundaryCondition="false" constant="false"/>
+      <species id="D" compartment="cell" initialAmount="0" boundaryCondition="false" constant="false"/>
+    </listOfSpecies>
+    <listOfReactions>
+      <reaction id="dimerise" reversible="true">
+        <listOfReactants>
+          <speciesReference species="M" stoichiometry="2" constant="true"/>
+        </listOfReactants>
+        <listOfProducts>
+          <speciesReference species="D" stoichiometry="1" constant="true"/>
+        </listOfProducts>
+        <kineticLaw>
+          <math xmlns="http://www.w3.org/1998/Math/MathML">
+            <apply><times/><ci> k </ci><apply><power/><ci> M </ci><cn> 2 </cn></apply></apply>
+          </math>
+          <listOfLocalParameters>
+            <localParameter id="k" value="0.01"/>
+          </listOfLocalParameters>
+        </kineticLaw>
+      </reaction>
+    </listOfReactions>
+    <listOfEvents>
+      <event id="pulse"/>
+    </listOfEvents>
+  </model>
+</sbml>"#;
+    let import = read_sbml(document).unwrap();
+    assert_eq!(import.petri.transitions[0].input, vec![2, 0]);
+    assert!(matches!(import.petri.transitions[0].rate_law, RateLaw::MassAction));
+    assert_eq!(import.controls.rates, vec![0.01]);
+    assert!(import.petri.parameters.is_empty());
+    assert_eq!(import.warnings.len(), 2);
+    assert!(import.warnings[0].contains("reversible"));
+    assert!(import.warnings[1].contains("events"));
+
+    let error = read_sbml("<sbml>\n  <model>\n</sbml>").err().unwrap();
+    assert!(error.starts_with("line 3, column 3"), "{}", error);
+}
+
+#[test]
+fn sbml_import_inlines_assignment_rules_into_kinetic_laws() {
+    let document = r#"<sbml xmlns="http://www.sbml.org/sbml/level3/version1/core" level="3" version="1">
+  <model id="si">
+    <listOfSpecies>
+      <species id="S" initialAmount="99.5"/>
+      <species id="I" initialConcentration="0.5"/>
+    </listOfSpecies>
+    <listOfParameters>
+      <parameter id="beta" value="1" constant="true"/>
+      <parameter id="N" constant="false"/>
+    </listOfParameters>
+    <listOfRules>
+      <assignmentRule variable="N">
+        <math xmlns="http://www.w3.org/1998/Math/MathML"><apply><plus/><ci>S</ci><ci>I</ci></apply></math>
+      </assignmentRule>
+    </listOfRules>
+    <listOfReactions>
+      <reaction id="infection">
+        <listOfReactants>
+          <speciesReference species="S" stoichiometry="1"/>
+          <speciesReference species="I" stoichiometry="1"/>
+        </listOfReactants>
+        <listOfProducts>
+          <speciesReference species="I" stoichiometry="2"/>
+        </listOfProducts>
+        <kineticLaw>
+          <math xmlns="http://www.w3.org/1998/Math/MathML">
+            <apply><divide/><apply><times/><ci>beta</ci><ci>S</ci><ci>I</ci></apply><ci>N</ci></apply>
+          </math>
+        </kineticLaw>
+      </reaction>
+    </listOfReactions>
+  </model>
+</sbml>"#;
+    let mut import = read_sbml(document).unwrap();
+    assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+    // Initial values need not be whole numbers
+    assert_eq!(import.controls.init_vals, vec![99.5, 0.5]);
+    assert_eq!(import.petri.observables[0].name, "N");
+    assert_eq!(import.petri.transitions[0].rate_law, RateLaw::Expression("beta * S * I / (S + I)".to_string()));
+    // Infection at a frequency-dependent rate is logistic
+    import.controls.xmax = 5.;
+    let yvals = import.petri.simulate(&import.controls, 100);
+    let e = 5f32.exp();
+    assert!((yvals[(99, 1)] - 100. * 0.5 * e / (100. + 0.5 * (e - 1.))).abs() < 1e-2);
+}
+
+#[test]
+fn pnml_round_trip_preserves_arcs_markings_and_layout() {
+    let (mut petri, controls) = sir();
+    petri.transitions[2].read = vec![0; 4];
+    petri.transitions[2].rate_law = RateLaw::MassAction;
+    petri.parameters = vec![];
+    petri.observables = vec![];
+    petri.layout.species = vec![("S".to_string(), (10., 20.)), ("I".to_string(), (110., 20.))];
+    petri.layout.transitions = vec![("infection".to_string(), (60., 0.))];
+    let (document, warnings) = write_pnml(&petri, &controls);
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    let mut delayed = petri.clone();
+    delayed.transitions[1].delay = 2.;
+    assert_eq!(write_pnml(&delayed, &controls).1, vec!["transition recovery: the output delay is not exported".to_string()]);
+
+    let import = read_pnml(&document).unwrap();
+    assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+    assert_eq!(import.petri.name, petri.name);
+    assert_eq!(import.petri.species, petri.species);
+    for (a, b) in import.petri.transitions.iter().zip(petri.transitions.iter()) {
+        assert_eq!((&a.name, &a.input, &a.output, &a.inhibit), (&b.name, &b.input, &b.output, &b.inhibit));
+    }
+    assert_eq!(import.petri.layout, petri.layout);
+    assert_eq!(import.controls.init_vals, controls.init_vals);
+    assert_eq!(import.controls.rates, petri.effective_rates(&controls));
+}
+
+#[test]
+fn pnml_import_reads_nested_pages_and_pipe_labels() {
+    let document = r#"<pnml>
+  <net id="net1" type="http://www.pnml.org/version-2009/grammar/ptnet">
+    <page id="top">
+      <place id="p1"><initialMarking><value>Default,3</value></initialMarking></place>
+      <page id="inner">
+        <transition id="t1"><name><text>fire</text></name></transition>
+        <place id="p2"><name><value>out</value></name></place>
+      </page>
+      <arc id="a1" source="p1" target="t1"><inscription><value>Default,2</value></inscription></arc>
+      <arc id="a2" source="t1" target="p2"/>
+      <arc id="a3" source="p1" target="p2"/>
+    </page>
+  </net>
+</pnml>"#;
+    let import = read_pnml(document).unwrap();
+    assert_eq!(import.petri.species, vec!["p1".to_string(), "out".to_string()]);
+    assert_eq!(import.petri.transitions[0].name, "fire");
+    assert_eq!(import.petri.transitions[0].input, vec![2, 0]);
+    assert_eq!(import.petri.transitions[0].output, vec![0, 1]);
+    assert_eq!(import.controls.init_vals, vec![3., 0.]);
+    assert_eq!(import.warnings, vec!["arc a3 does not connect a place and a transition, and was skipped".to_string()]);
+}
+
+#[test]
+fn acset_matches_algebraic_petri() {
+    // SIR as written by AlgebraicPetri's write_json_acset
+    let document = r#"{
+        "T": [{"tname": "inf", "rate": 0.005}, {"tname": "rec", "rate": 0.25}],
+        "S": [{"sname": "S", "concentration": 990.0}, {"sname": "I", "concentration": 10.0}, {"sname": "R", "concentration": 0.0}],
+        "I": [{"it": 1, "is": 1}, {"it": 1, "is": 2}, {"it": 2, "is": 2}],
+        "O": [{"ot": 1, "os": 2}, {"ot": 1, "os": 2}, {"ot": 2, "os": 3}]
+    }"#;
+    let import = read_acset(document).unwrap();
+    assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+    assert_eq!(import.petri.species, vec!["S".to_string(), "I".to_string(), "R".to_string()]);
+    assert_eq!(import.petri.transitions[0].input, vec![1, 1, 0]);
+    assert_eq!(import.petri.transitions[0].output, vec![0, 2, 0]);
+    assert_eq!(import.controls.rates, vec![0.005, 0.25]);
+    assert_eq!(import.controls.init_vals, vec![990., 10., 0.]);
+
+    let (written, warnings) = write_acset(&import.petri, &import.controls);
+    assert!(warnings.is_empty());
+    let mut timed = import.petri.clone();
+    timed.transitions[1].timing = Timing::Deterministic(4.);
+    timed.parameters = vec![Parameter::new("gamma", 0.25)];
+    assert_eq!(write_acset(&timed, &import.controls).1, vec![
+        "transition rec: the Deterministic firing time is not exported, only the rate is used".to_string(),
+        "1 parameter(s) are not exported".to_string(),
+    ]);
+    let again = read_acset(&written).unwrap();
+    for (a, b) in again.petri.transitions.iter().zip(import.petri.transitions.iter()) {
+        assert_eq!((&a.name, &a.input, &a.output), (&b.name, &b.input, &b.output));
+    }
+    assert_eq!(again.controls.rates, import.controls.rates);
+
+    let error = read_acset(r#"{"S": [], "T": [{"tname": "t"}], "I": [{"is": 1, "it": 1}]}"#).err().unwrap();
+    assert_eq!(error, "row 1 of table I: is = 1 is out of range");
+}
+
+#[test]
+fn reaction_text_round_trips_and_reports_positions() {
+    let src = "model SIR with treatment
+# species in plot order
+species S, I, R
+S = 99
+I = 1
+param beta = 0.003 in [0, 0.01] unit \"1/day\"
+param gamma = 0.1
+param shift = -0.5 in [-1, 0]
+
+infection: S + I -> 2 I @ beta
+recovery: I -> R @ 0.1   # no treatment needed
+treatment: I + [E] -> R @ 0.05 michaelis(I, 20) unless R >= 30 after 1.5
+import: 0 -> S @ 0.5 {t < 10}
+observe prevalence = I / (S + I + R)
+";
+    let (petri, controls) = read_reactions(src).unwrap();
+    assert_eq!(petri.name, "SIR with treatment");
+    assert_eq!(petri.species, vec!["S".to_string(), "I".to_string(), "R".to_string(), "E".to_string()]);
+    assert_eq!(controls.init_vals, vec![99., 1., 0., 0.]);
+    assert_eq!(controls.rates, vec![0.003, 0.1, 0.05, 0.5]);
+    let treatment = &petri.transitions[2];
+    assert_eq!((&treatment.input, &treatment.read, &treatment.inhibit), (&vec![0, 1, 0, 0], &vec![0, 0, 0, 1], &vec![0, 0, 30, 0]));
+    assert_eq!(treatment.rate_law, RateLaw::MichaelisMenten { substrate: 1, km: 20. });
+    assert_eq!(treatment.delay, 1.5);
+    assert_eq!(petri.transitions[3].rate_law, RateLaw::Expression("t < 10".to_string()));
+    assert_eq!(petri.observables[0].expression, "I / (S + I + R)");
+    assert_eq!((petri.parameters[0].min, petri.parameters[0].max, petri.parameters[0].unit.as_str()), (0., 0.01, "1/day"));
+    assert_eq!(petri.parameters[1], Parameter::new("gamma", 0.1));
+
+    let (written, warnings) = write_reactions(&petri, &controls);
+    assert!(warnings.is_empty());
+    assert!(written.contains("param beta = 0.003 in [0, 0.01] unit \"1/day\"\nparam gamma = 0.1\nparam shift = -0.5 in [-1, 0]\n"), "{}", written);
+    assert!(written.contains("treatment: I + [E] -> R @ 0.05 michaelis(I, 20) unless R >= 30 after 1.5\n"), "{}", written);
+    let (again, again_controls) = read_reactions(&written).unwrap();
+    assert_eq!(write_reactions(&again, &again_controls).0, written);
+
+    // Negative numbers round trip
+    let (negative, negative_controls) = read_reactions("A = -2\ndecay: A -> 0 @ -.5").unwrap();
+    assert_eq!((negative_controls.init_vals[0], negative_controls.rates[0]), (-2., -0.5));
+    let again = read_reactions(&write_reactions(&negative, &negative_controls).0).unwrap().1;
+    assert_eq!((again.init_vals, again.rates), (negative_controls.init_vals, negative_controls.rates));
+    let error = |src: &str| read_reactions(src).err().unwrap().to_string();
+    assert_eq!(error("S = 1\ninfection: S + -> I"), "line 2, column 16: expected a name, found ->");
+    assert_eq!(error("decay: A -> 0 @ k"), "line 1, column 17: unknown parameter k");
+    assert_eq!(error("param k = 1 in [2, 1]"), "line 1, column 20: the range of k is empty");
+    assert_eq!(error("observe x = (S + "), "line 1, column 18: unexpected end of expression");
+    assert_eq!(error("growth: A -> 2 A @ {A * }"), "line 1, column 25: unexpected end of expression");
+}
+
+#[test]
+fn antimony_import_maps_mass_action_and_reports_the_rest() {
+    let src = "
+// Helper module, skipped
+model infection_only()
+  J0: S + I -> 2 I; k * S * I
+end
+
+/* The main model */
+model *SIR()
+  compartment cell = 1;
+  species S in cell, I in cell, R in cell, $V
+  J1: S + I => 2I; cell * beta * S * I
+  J2: I -> R; gamma*I; gamma = 0.1
+  vaccination: S + $V => R; 0.01 * S * V
+  waning: R => S; omega * R / (1 + R)
+  J3: S + I => 2I; 0.5 * S * I / N
+  S = 990; I = 10; R = 0; V = 1
+  beta = gamma * 0.05; omega = 0.02
+  N := S + I + R
+  E1: at (time > 10): beta = 0
+  unit day = second * 86400
+end
+";
+    let import = read_antimony(src).unwrap();
+    let petri = &import.petri;
+    assert_eq!(petri.name, "SIR");
+    assert_eq!(petri.species, vec!["S".to_string(), "I".to_string(), "R".to_string(), "V".to_string()]);
+    assert_eq!(import.controls.init_vals, vec![990., 10., 0., 1.]);
+    let names: Vec<&str> = petri.parameters.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, vec!["gamma", "beta", "omega"]);
+    assert!((petri.parameters[1].value - 0.005).abs() < 1e-7);
+
+    assert_eq!(petri.transitions[0].rate, Some("beta".to_string()));
+    assert_eq!(petri.transitions[1].rate, Some("gamma".to_string()));
+    // The boundary species V is read, not consumed
+    let vaccination = &petri.transitions[2];
+    assert_eq!((&vaccination.input, &vaccination.read), (&vec![1, 0, 0, 0], &vec![0, 0, 0, 1]));
+    assert_eq!(import.controls.rates[2], 0.01);
+    assert_eq!(petri.transitions[3].rate_law, RateLaw::Expression("omega * R / (1 + R)".to_string()));
+    assert_eq!(petri.observables[0].name, "N");
+    // Rules are inlined into the rate laws using them
+    assert_eq!(petri.transitions[4].rate_law, RateLaw::Expression("0.5 * S * I / (S + I + R)".to_string()));
+    let mut controls = import.controls.clone();
+    controls.xmax = 20.;
+    let yvals = petri.simulate(&controls, 100);
+    assert!(yvals.iter().all(|x| x.is_finite()));
+    assert!((yvals.row(99).sum() - 1001.).abs() < 1e-1);
+
+    assert_eq!(import.warnings.len(), 4, "{:?}", import.warnings);
+    assert!(import.warnings[0].contains("only the model SIR"));
+    // Only J2 is reversible
+    assert!(import.warnings[1].starts_with("line 12: reaction J2 is reversible"));
+    assert!(import.warnings[2].starts_with("line 19: event E1"));
+    assert!(import.warnings[3].starts_with("line 20: the unit definition day"));
+
+    assert_eq!(read_antimony("A -> B; k * (A").err().unwrap(), "line 1: expected ), at column 7 of k * (A");
+}
+
+#[test]
+fn figures_render_as_svg_and_png() {
+    let (petri, controls) = sir();
+    let figure = Figure::new(&petri, &controls);
+    let svg = figure.to_svg(FIGURE_SIZE).unwrap();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains("SIR model") && svg.contains("prevalence"));
+
+    let path = std::env::temp_dir().join("ez-petri-figure.png");
+    figure.save_png(&path, FIGURE_SIZE).unwrap();
+    let png = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(&png[1..4], b"PNG");
+}
+
+#[test]
+fn diagrams_draw_species_transitions_and_weights() {
+    let (mut petri, _) = sir();
+    petri.layout.species.push(("S".to_string(), (100., 50.)));
+    let dot = write_dot(&petri);
+    assert!(dot.starts_with("digraph \"SIR model\" {"));
+    assert!(dot.contains("s0 [label=\"S\", shape=circle") && dot.contains("pos=\"0.79,-0.39!\""));
+    assert!(dot.contains("t0 [label=\"infection\", shape=box];"));
+    assert!(dot.contains("t0 -> s1 [label=\"2\"];"));
+    assert!(dot.contains("s3 -> t2 [style=dashed, dir=both];"));
+    assert!(dot.contains("s2 -> t2 [label=\"30\", arrowhead=odot];"));
+
+    let tikz = write_tikz(&petri);
+    assert!(tikz.contains("\\node[species, draw=species0, fill=species0!20] (s0) at (2.00, -1.00) {S};"));
+    assert!(tikz.contains("\\node[transition] (t1) at (3.00, -2.00) {recovery};"));
+    assert!(tikz.contains("\\draw[arc] (t0) -- node[weight] {2} (s1);"));
+    assert_eq!(latex_text("k_1 & 50%"), "k\\_1 \\& 50\\%");
+}
+
+#[test]
+fn equations_are_generated_from_the_stoichiometry() {
+    let (mut petri, controls) = sir();
+    petri.transitions[1].delay = 2.0;
+    let (latex, warnings) = write_latex(&petri, &controls);
+    assert_eq!(warnings, vec!["transition recovery: the output delay is left out".to_string()]);
+    assert!(latex.contains("\\frac{dS}{dt} &= -\\beta S I \\\\"));
+    assert!(latex.contains("\\frac{dR}{dt} &= k_{\\mathrm{recovery}} I + k_{\\mathrm{treatment}} E \\frac{I}{20 + I} [R < 30] \\\\"));
+    assert!(latex.contains("\\frac{dE}{dt} &= 0\n"));
+
+    let (python, _) = write_scipy(&petri, &controls);
+    assert!(python.contains("    [S, I, R, E] = y\n    infection = beta * S * I\n"));
+    assert!(python.contains("        infection - recovery - treatment,  # dI/dt\n"));
+    let (julia, _) = write_julia(&petri, &controls);
+    assert!(julia.contains("    beta, k_recovery, k_treatment = p\n"));
+    assert!(julia.contains("    du[3] = recovery + treatment\n"));
+    let (rust, _) = write_rust(&petri, &controls);
+    assert!(rust.contains("pub fn sir_model(t: f64, y: &[f64; 4]) -> [f64; 4] {"));
+    assert!(rust.contains("(I / (20.0 + I)) * (if R < 30.0 { 1.0 } else { 0.0 });"));
+    assert_eq!(latex_symbol("k2"), "k_{2}");
+}
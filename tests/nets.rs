@@ -0,0 +1,285 @@
+//! Native tests for transformations of Petri nets.
+
+use ez_petri::petri::*;
+use ez_petri::kinetics::*;
+use ez_petri::colored::*;
+use ez_petri::typed::*;
+use ez_petri::open::*;
+use ez_petri::patches::*;
+
+fn arc(place: &str, color: &str) -> ColoredArc {
+    ColoredArc { place: place.to_string(), weight: 1, color: color.to_string() }
+}
+
+fn age_structured_sir() -> ColoredNet {
+    let age = |v: &str| (v.to_string(), "Age".to_string());
+    let transition = |name: &str, variables, input, output| ColoredTransition {
+        name: name.to_string(), variables, guard: "".to_string(), input, output, rate: Some(name.to_string())
+    };
+    ColoredNet {
+        name: "age structured SIR".to_string(),
+        color_sets: vec![ColorSet { name: "Age".to_string(), colors: vec!["young".to_string(), "old".to_string()] }],
+        places: vec![
+            Place { name: "S".to_string(), color_set: Some("Age".to_string()), initial: vec![50., 49.] },
+            Place { name: "I".to_string(), color_set: Some("Age".to_string()), initial: vec![1.] },
+            Place { name: "R".to_string(), color_set: None, initial: vec![] },
+        ],
+        transitions: vec![
+            transition("infection", vec![age("a"), age("b")], vec![arc("S", "a"), arc("I", "b")], vec![arc("I", "a"), arc("I", "b")]),
+            transition("recovery", vec![age("a")], vec![arc("I", "a")], vec![arc("R", "")]),
+            // Old susceptibles have nowhere to age to, so only the young age
+            transition("aging", vec![age("a")], vec![arc("S", "a")], vec![arc("S", "a + 1")]),
+        ],
+        parameters: vec![Parameter::new("infection", 0.003), Parameter::new("recovery", 0.1), Parameter::new("aging", 0.05)],
+    }
+}
+
+#[test]
+fn colored_net_unfolds() {
+    let mut net = age_structured_sir();
+    let petri = net.unfold().unwrap();
+    assert_eq!(petri.species, vec!["S_young", "S_old", "I_young", "I_old", "R"]);
+    assert_eq!(petri.transition_names(), vec![
+        "infection_young_young", "infection_young_old", "infection_old_young", "infection_old_old",
+        "recovery_young", "recovery_old", "aging_young",
+    ]);
+    let infection = &petri.transitions[1];
+    assert_eq!((infection.input.clone(), infection.output.clone()), (vec![1, 0, 0, 1, 0], vec![0, 0, 1, 1, 0]));
+    assert_eq!(petri.transitions[6].output, vec![0, 1, 0, 0, 0]);
+    assert_eq!(net.initial_values().unwrap(), vec![50., 49., 1., 0., 0.]);
+
+    // The unfolded net simulates as usual, conserving the population
+    let mut controls = PlotControls::empty();
+    controls.init_vals = net.initial_values().unwrap();
+    controls.rates = vec![0.; petri.transitions.len()];
+    let yvals = petri.simulate(&controls, 100);
+    assert!((yvals.row(99).sum() - 100.).abs() < 1e-2);
+    assert!(yvals[(99, 4)] > 1.);
+
+    net.transitions[0].guard = "a == b".to_string();
+    assert_eq!(net.unfold().unwrap().transitions.len(), 5);
+    net.transitions[0].guard = "a == middle".to_string();
+    assert_eq!(net.unfold().err().unwrap(), "transition infection: column 6: unknown variable middle");
+
+    // A color shared by two sets must have one index
+    net.transitions[0].guard = "".to_string();
+    net.color_sets.push(ColorSet { name: "Risk".to_string(), colors: vec!["low".to_string(), "young".to_string()] });
+    assert_eq!(net.unfold().err().unwrap(), "color young is at different positions in color sets Age and Risk");
+    net.color_sets[1].colors.swap(0, 1);
+    assert!(net.unfold().is_ok());
+}
+
+#[test]
+fn pullback_stratifies_sir_by_age() {
+    let pop = |n: usize| vec![n as i32];
+    let names = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let type_net = PetriNet::new("types", names(&["Pop"]), vec![
+        Transition::new("infect", pop(2), pop(2)),
+        Transition::new("disease", pop(1), pop(1)),
+        Transition::new("strata", pop(1), pop(1)),
+    ]);
+    let sir = TypedPetriNet::new(PetriNet::new("SIR", names(&["S", "I", "R"]), vec![
+        Transition::new("inf", vec![1, 1, 0], vec![0, 2, 0]),
+        Transition::new("rec", vec![0, 1, 0], vec![0, 0, 1]),
+        Transition::new("S", vec![1, 0, 0], vec![1, 0, 0]),
+        Transition::new("I", vec![0, 1, 0], vec![0, 1, 0]),
+        Transition::new("R", vec![0, 0, 1], vec![0, 0, 1]),
+    ]), vec![0, 0, 0], vec![0, 1, 2, 2, 2]);
+    let mut age = TypedPetriNet::new(PetriNet::new("age", names(&["young", "old"]), vec![
+        Transition::new("yy", vec![2, 0], vec![2, 0]),
+        Transition::new("yo", vec![1, 1], vec![1, 1]),
+        Transition::new("oy", vec![1, 1], vec![1, 1]),
+        Transition::new("oo", vec![0, 2], vec![0, 2]),
+        Transition::new("young", vec![1, 0], vec![1, 0]),
+        Transition::new("old", vec![0, 1], vec![0, 1]),
+        Transition::new("aging", vec![1, 0], vec![0, 1]),
+    ]), vec![0, 0], vec![0, 0, 0, 0, 1, 1, 2]);
+    // An old susceptible meeting a young infected
+    age.arcs = (0..7).map(|t| age.transition_arcs(t)).collect();
+    age.arcs[2] = (vec![1, 0], vec![1, 0]);
+
+    let stratified = pullback(&sir, &age, &type_net).unwrap();
+    let petri = &stratified.net;
+    assert_eq!(petri.species, vec!["S_young", "S_old", "I_young", "I_old", "R_young", "R_old"]);
+    assert_eq!(petri.transition_names(), vec![
+        "inf_yy", "inf_yo", "inf_oy", "inf_oo", "rec_young", "rec_old", "S_aging", "I_aging", "R_aging",
+    ]);
+    let oy = &petri.transitions[2];
+    assert_eq!((oy.input.clone(), oy.output.clone()), (vec![0, 1, 1, 0, 0, 0], vec![0, 0, 1, 1, 0, 0]));
+    assert!(stratified.check(&type_net).is_ok());
+
+    let mut bad = sir.clone();
+    bad.transition_types[1] = 0;
+    assert_eq!(pullback(&bad, &age, &type_net).err().unwrap(),
+               "SIR: the arcs of transition rec do not match those of its type infect");
+}
+
+#[test]
+fn open_nets_compose_by_gluing_species() {
+    let names = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let mut infection = PetriNet::new("infection", names(&["S", "I"]), vec![
+        Transition::new("inf", vec![1, 1], vec![0, 2]),
+    ]);
+    infection.transitions[0].rate_law = ez_petri::kinetics::RateLaw::Expression("beta * S * I".to_string());
+    infection.parameters = vec![Parameter::new("beta", 0.3)];
+    let recovery = PetriNet::new("recovery", names(&["I", "R"]), vec![
+        Transition::new("rec", vec![1, 0], vec![0, 1]),
+    ]);
+    // Waning keeps an internal species W between recovered and susceptible
+    let waning = PetriNet::new("waning", names(&["R", "W", "S"]), vec![
+        Transition::new("lose", vec![1, 0, 0], vec![0, 1, 0]),
+        Transition::new("return", vec![0, 1, 0], vec![0, 0, 1]),
+    ]);
+    let instance = |name: &str, net: PetriNet, boundary: Vec<usize>, wires: Vec<usize>| Instance {
+        name: name.to_string(), module: OpenPetriNet { net, boundary }, wires
+    };
+    let composition = Composition {
+        name: "SIRS".to_string(),
+        junctions: names(&["S", "I", "R"]),
+        instances: vec![
+            instance("infection", infection, vec![0, 1], vec![0, 1]),
+            instance("recovery", recovery, vec![0, 1], vec![1, 2]),
+            instance("waning", waning, vec![0, 2], vec![2, 0]),
+        ],
+        outer: vec![],
+    };
+    let flat = composition.flatten().unwrap();
+    let petri = &flat.net;
+    assert_eq!(petri.species, vec!["S", "I", "R", "waning_W"]);
+    assert_eq!(petri.transition_names(), vec!["infection_inf", "recovery_rec", "waning_lose", "waning_return"]);
+    assert_eq!(petri.transitions[3].input, vec![0, 0, 0, 1]);
+    assert_eq!(petri.transitions[3].output, vec![1, 0, 0, 0]);
+    assert_eq!(petri.transitions[0].rate_law, ez_petri::kinetics::RateLaw::Expression("beta * S * I".to_string()));
+    assert_eq!(flat.species_origins[0], vec![
+        ("infection".to_string(), "S".to_string()), ("waning".to_string(), "S".to_string()),
+    ]);
+    assert_eq!(flat.transition_origins[2], ("waning".to_string(), "lose".to_string()));
+    assert!(petri.check_rate_law(0).is_ok());
+}
+
+#[test]
+fn submodules_embed_into_parent() {
+    let names = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let parent = PetriNet::new("SIR", names(&["S", "I", "R"]), vec![
+        Transition::new("inf", vec![1, 1, 0], vec![0, 2, 0]),
+    ]);
+    let recovery = PetriNet::new("recovery", names(&["X", "Q", "Y"]), vec![
+        Transition::new("quarantine", vec![1, 0, 0], vec![0, 1, 0]),
+        Transition::new("release", vec![0, 1, 0], vec![0, 0, 1]),
+    ]);
+    let sub = Submodule { name: "rec".to_string(), module: 0, species_map: vec![Some(1), None, Some(2)], expanded: false };
+    let flat = embed(&parent, &[(&sub, &recovery)]).unwrap();
+    assert_eq!(flat.net.species, vec!["S", "I", "R", "rec_Q"]);
+    assert_eq!(flat.net.transition_names(), vec!["inf", "rec_quarantine", "rec_release"]);
+    assert_eq!(flat.net.transitions[0].input, vec![1, 1, 0, 0]);
+    assert_eq!(flat.net.transitions[2].output, vec![0, 0, 1, 0]);
+    assert_eq!(flat.species_origins[1], vec![
+        ("".to_string(), "I".to_string()), ("rec".to_string(), "X".to_string()),
+    ]);
+
+    // A substrate out of range, as in a hand-edited file, is an error
+    let mut broken = recovery.clone();
+    broken.transitions[0].rate_law = RateLaw::MichaelisMenten { substrate: 3, km: 1. };
+    assert!(embed(&parent, &[(&sub, &broken)]).is_err());
+}
+
+#[test]
+fn metapopulation_migrates_between_patches() {
+    let mut petri = PetriNet::new("mobile", vec!["X".to_string(), "Y".to_string()], vec![
+        Transition::new("death", vec![1, 0], vec![0, 0]),
+    ]);
+    petri.transitions[0].rate_law = ez_petri::kinetics::RateLaw::Expression("0 * X".to_string());
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![10., 5.];
+    controls.rates = vec![0.];
+
+    let mut meta = Metapopulation::new(vec!["home".to_string(), "away".to_string()]);
+    meta.migration[0][1] = 1.;
+    // Y stays at home
+    meta.mobility = vec![1., 0.];
+    let expanded = meta.expand(&petri);
+    assert_eq!(expanded.species, vec!["home_X", "home_Y", "away_X", "away_Y"]);
+    assert_eq!(expanded.transition_names(), vec!["home_death", "away_death", "migrate_X_home_away"]);
+    assert_eq!(expanded.transitions[1].rate_law, ez_petri::kinetics::RateLaw::Expression("0 * away_X".to_string()));
+
+    let expanded_controls = meta.expand_controls(&petri, &controls);
+    assert_eq!(expanded_controls.init_vals, vec![10., 5., 0., 0.]);
+    assert_eq!(expanded_controls.rates, vec![0., 0., 1.]);
+    let yvals = expanded.simulate(&expanded_controls, 100);
+    assert!((yvals[(99, 0)] - 10. * (-10f32).exp()).abs() < 1e-3);
+    let total = meta.aggregate(&yvals);
+    assert!((total[(99, 0)] - 10.).abs() < 1e-3);
+    assert_eq!(meta.patch(&yvals, 1)[(99, 1)], 0.);
+}
+
+#[test]
+fn stage_expansion_gives_erlang_dwell_times() {
+    let mut petri = PetriNet::new("incubation", vec!["E".to_string(), "I".to_string()], vec![
+        Transition::new("onset", vec![1, 0], vec![0, 1]),
+    ]);
+    petri.transitions[0].stages = 3;
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![1., 0.];
+    controls.rates = vec![1.];
+    controls.xmax = 1.;
+
+    let staged = petri.expand_stages();
+    assert_eq!(staged.net.species, vec!["E", "I", "onset_stage1", "onset_stage2"]);
+    assert_eq!(staged.net.transition_names(), vec!["onset", "onset_stage2", "onset_stage3"]);
+    let staged_controls = staged.expand_controls(&petri, &controls);
+    assert_eq!(staged_controls.rates, vec![3., 3., 3.]);
+
+    // I(t) is the Erlang(3, 3) distribution function
+    let yvals = staged.net.simulate(&staged_controls, 100);
+    let expected = 1. - 8.5 * (-3f32).exp();
+    assert!((yvals[(99, 1)] - expected).abs() < 1e-3);
+    let collapsed = staged.collapse(&yvals);
+    assert_eq!(staged.group_names(), vec!["E", "I"]);
+    assert!((collapsed[(99, 0)] + collapsed[(99, 1)] - 1.).abs() < 1e-4);
+
+    // Expression propensities have no rate per token to divide into stages
+    petri.transitions[0].rate_law = RateLaw::Expression("0.5 * E".to_string());
+    assert!(!petri.has_stages());
+    assert!(petri.check_stages(0).is_err());
+    let staged = petri.expand_stages();
+    assert_eq!(staged.net.species, petri.species);
+    controls.init_vals = vec![10., 0.];
+    controls.xmax = 20.;
+    let yvals = staged.net.simulate(&staged.expand_controls(&petri, &controls), 100);
+    assert!((yvals[(99, 1)] - 10.).abs() < 1e-3);
+}
+
+#[test]
+fn renaming_a_parameter_follows_its_references() {
+    use ez_petri::events::*;
+    let mut petri = PetriNet::new("SIR", vec!["S".to_string(), "I".to_string()], vec![
+        Transition::new("inf", vec![1, 1], vec![0, 2]),
+        Transition::new("rec", vec![0, 1], vec![0, 0]),
+    ]);
+    petri.parameters = vec![Parameter::new("beta", 0.1), Parameter::new("gamma", 0.2)];
+    petri.transitions[0].rate = Some("beta".to_string());
+    petri.transitions[1].rate_law = RateLaw::Expression("gamma * I + beta_2".to_string());
+    petri.observables = vec![Observable { name: "R0".to_string(), expression: "beta / gamma".to_string() }];
+    petri.events = vec![Event {
+        trigger: Trigger::When("I > gamma".to_string()),
+        actions: vec![Action::Scale("gamma".to_string(), 0.5)],
+    }];
+
+    petri.rename_parameter("gamma", Some("recovery"));
+    petri.parameters[1].name = "recovery".to_string();
+    assert!(petri.check_observable(0).is_ok());
+    assert_eq!(petri.transitions[1].rate_law, RateLaw::Expression("recovery * I + beta_2".to_string()));
+    assert_eq!(petri.observables[0].expression, "beta / recovery");
+    assert_eq!(petri.events[0].trigger, Trigger::When("I > recovery".to_string()));
+    assert_eq!(petri.events[0].actions[0], Action::Scale("recovery".to_string(), 0.5));
+
+    // A name being typed in leaves the expressions alone
+    petri.rename_parameter("beta", Some(""));
+    assert_eq!(petri.transitions[0].rate, Some("".to_string()));
+    assert_eq!(petri.observables[0].expression, "beta / recovery");
+
+    // Removed parameters unbind the rates, and are reported where still used
+    petri.rename_parameter("recovery", None);
+    petri.parameters.remove(1);
+    assert!(petri.check_observable(0).is_err());
+}
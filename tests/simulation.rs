@@ -0,0 +1,307 @@
+//! Native tests for the ODE and SSA simulations.
+
+use ez_petri::petri::*;
+use ez_petri::kinetics::*;
+use ez_petri::events::*;
+use ez_petri::expr::Expr;
+use ez_petri::{ssa, gsmp, reachability};
+use ez_petri::gsmp::{Timing, Policy};
+use ez_petri::diffusion::*;
+use ndarray::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+fn decay(rate: f32) -> (PetriNet, PlotControls) {
+    let petri = PetriNet::new("decay", vec!["A".to_string()], vec![
+        Transition::new("death", vec![1], vec![0]),
+    ]);
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![1.0];
+    controls.rates = vec![rate];
+    controls.xmax = 10.0;
+    (petri, controls)
+}
+
+#[test]
+fn events_dose_and_stop_decay() {
+    let (mut petri, controls) = decay(1.0);
+    petri.events = vec![
+        Event { trigger: Trigger::At(5.0), actions: vec![Action::AddTokens("A".to_string(), 2.0)] },
+        Event { trigger: Trigger::When("A < 0.5".to_string()), actions: vec![Action::Scale("death".to_string(), 0.0)] },
+    ];
+    let yvals = petri.simulate(&controls, 100);
+    // Decay stops once A reaches 0.5, at t = ln 2
+    assert!((yvals[(29, 0)] - 0.5).abs() < 1e-3);
+    // The dose at t = 5 is not undone since the rate is now zero
+    assert!((yvals[(99, 0)] - 2.5).abs() < 1e-3);
+}
+
+#[test]
+fn ssa_events_and_rates_follow_time_between_firings() {
+    // Nothing can fire before the dose, so only the time moves
+    let (mut petri, mut controls) = decay(4.0);
+    controls.init_vals = vec![0.0];
+    controls.xmax = 1.0;
+    petri.events = vec![Event { trigger: Trigger::When("t > 0.25".to_string()), actions: vec![Action::AddTokens("A".to_string(), 1000.0)] }];
+    let mut rng = StdRng::seed_from_u64(0);
+    let dynamics = Dynamics::new(&petri, &controls);
+    let traj = ssa::simulate(&petri.get_petri_data(), &dynamics, &arr1(&[0]), 1.0, 2, &mut rng);
+    // The dose at t = 0.25 has decayed by e^-1 at t = 0.5
+    assert!((traj[(0, 0)] - 368.0).abs() < 60.0, "{}", traj[(0, 0)]);
+
+    // A rate that is zero at the start of the step and switches on later
+    let mut petri = PetriNet::new("switch", vec!["A".to_string()], vec![Transition::new("make", vec![0], vec![1])]);
+    petri.transitions[0].schedule = Schedule::Expression("100 * (t > 0.5)".to_string());
+    controls.rates = vec![1.0];
+    let dynamics = Dynamics::new(&petri, &controls);
+    let traj = ssa::simulate(&petri.get_petri_data(), &dynamics, &arr1(&[0]), 1.0, 1, &mut rng);
+    assert!((traj[(0, 0)] - 50.0).abs() < 25.0, "{}", traj[(0, 0)]);
+}
+
+#[test]
+fn scaling_a_parameter_scales_the_rates_bound_to_it() {
+    let (mut petri, controls) = decay(0.0);
+    petri.parameters = vec![Parameter::new("beta", 1.0)];
+    petri.transitions[0].rate = Some("beta".to_string());
+    petri.events = vec![Event { trigger: Trigger::At(0.5), actions: vec![Action::Scale("beta".to_string(), 0.0)] }];
+    let yvals = petri.simulate(&controls, 100);
+    assert!((yvals[(19, 0)] - (-0.5f32).exp()).abs() < 1e-3);
+}
+
+#[test]
+fn rate_laws_match_their_closed_forms() {
+    let power = |x: f64, k: i32| x.powi(k);
+    let falling = |x: f64, k: i32| (0..k).map(|j| x - j as f64).product::<f64>();
+    let input = arr1(&[1, 2]);
+    let pops = [3.0, 4.0];
+    assert_eq!(RateLaw::MassAction.term(input.view(), &pops, power), 3.0 * 16.0);
+    assert_eq!(RateLaw::MassAction.term(input.view(), &pops, falling), 3.0 * 12.0);
+    // The substrate enters through the saturation only
+    let mm = RateLaw::MichaelisMenten { substrate: 0, km: 2.0 };
+    assert!((mm.term(input.view(), &pops, power) - 16.0 * 3.0 / 5.0).abs() < 1e-12);
+    let hill = RateLaw::Hill { substrate: 1, k: 2.0, n: 3.0 };
+    assert!((hill.term(input.view(), &pops, power) - 3.0 * 64.0 / 72.0).abs() < 1e-12);
+    let fd = RateLaw::FrequencyDependent;
+    assert!((fd.term(input.view(), &pops, power) - 48.0 / 49.0).abs() < 1e-12);
+    assert_eq!(fd.term(input.view(), &[0.0, 0.0], power), 0.0);
+}
+
+#[test]
+fn symbolic_rate_laws_agree_with_term() {
+    let input = arr1(&[1, 2]);
+    let pops = [3.0, 4.0];
+    let species = vec![Expr::Var("A".to_string(), 0), Expr::Var("B".to_string(), 0)];
+    let resolve = |v: &str| match v {
+        "A" => Some((SPECIES_TABLE, 0)),
+        "B" => Some((SPECIES_TABLE, 1)),
+        "k" => Some((RATES_TABLE, 0)),
+        _otherwise => None,
+    };
+    let eval = |law: &RateLaw, inhibit: &Array1<i32>| {
+        let e = law.symbolic(input.view(), inhibit.view(), &species, Expr::Var("k".to_string(), 0), &|v| Expr::Var(v.to_string(), 0)).unwrap();
+        e.compile(&resolve).unwrap()(&[&pops, &[0.5]])
+    };
+    let laws = [RateLaw::MassAction, RateLaw::MichaelisMenten { substrate: 0, km: 2.0 },
+                RateLaw::Hill { substrate: 1, k: 2.0, n: 3.0 }, RateLaw::FrequencyDependent];
+    for law in laws.iter() {
+        let term = 0.5 * law.term(input.view(), &pops, |x, k| x.powi(k));
+        assert!((eval(law, &arr1(&[0, 0])) - term).abs() < 1e-12, "{:?}", law);
+        // Inhibitor arcs switch the transition off at the threshold
+        assert!((eval(law, &arr1(&[5, 0])) - term).abs() < 1e-12, "{:?}", law);
+        assert_eq!(eval(law, &arr1(&[0, 4])), 0.0, "{:?}", law);
+    }
+    assert_eq!(eval(&RateLaw::Expression("k * A".to_string()), &arr1(&[0, 0])), 1.5);
+}
+
+#[test]
+fn negative_expression_propensities_count_as_zero() {
+    let mut make = Transition::new("make", vec![1, 0], vec![0, 1]);
+    make.rate_law = RateLaw::Expression("S - 10".to_string());
+    let petri = PetriNet::new("make", vec!["S".to_string(), "P".to_string()], vec![make]);
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![5.0, 20.0];
+    controls.rates = vec![1.0];
+    controls.xmax = 2.0;
+    let mut rng = StdRng::seed_from_u64(0);
+    let traj = petri.stochastic_trajectory(&controls, 10, &mut rng);
+    assert_eq!(traj.values[(9, 0)], 5.0);
+    assert!(petri.check_rate_law(0).unwrap().is_some());
+}
+
+#[test]
+fn rate_laws_drive_the_ode() {
+    let conversion = |law: RateLaw, s0: f32| {
+        let mut convert = Transition::new("convert", vec![1, 0], vec![0, 1]);
+        convert.rate_law = law;
+        let petri = PetriNet::new("conversion", vec!["S".to_string(), "P".to_string()], vec![convert]);
+        let mut controls = PlotControls::empty();
+        controls.init_vals = vec![s0, 0.0];
+        controls.rates = vec![1.0];
+        controls.xmax = 2.0;
+        petri.simulate(&controls, 100)[(99, 0)]
+    };
+    // S' = -S / (km + S), so (S0 - S) + km ln(S0 / S) = t
+    let s = conversion(RateLaw::MichaelisMenten { substrate: 0, km: 2.0 }, 5.0);
+    assert!(((5.0 - s) + 2.0 * (5.0 / s).ln() - 2.0).abs() < 1e-3);
+    // S' = -S^2 / (1 + S^2), so (S0 - S) + (1 / S - 1 / S0) = t
+    let s = conversion(RateLaw::Hill { substrate: 0, k: 1.0, n: 2.0 }, 5.0);
+    assert!(((5.0 - s) + (1.0 / s - 0.2) - 2.0).abs() < 1e-3);
+
+    // Infection without recovery at a frequency-dependent rate is logistic:
+    // I(t) = N I0 e^t / (N + I0 (e^t - 1))
+    let mut infect = Transition::new("infect", vec![1, 1], vec![0, 2]);
+    infect.rate_law = RateLaw::FrequencyDependent;
+    let petri = PetriNet::new("si", vec!["S".to_string(), "I".to_string()], vec![infect]);
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![99.0, 1.0];
+    controls.rates = vec![1.0];
+    controls.xmax = 5.0;
+    let yvals = petri.simulate(&controls, 100);
+    let e = 5f32.exp();
+    assert!((yvals[(99, 1)] - 100.0 * e / (100.0 + (e - 1.0))).abs() < 1e-2);
+}
+
+#[test]
+fn piecewise_schedule_switches_rate_off() {
+    let (mut petri, controls) = decay(1.0);
+    petri.transitions[0].schedule = Schedule::Piecewise(vec![(2.0, 0.0)]);
+    let yvals = petri.simulate(&controls, 100);
+    assert!((yvals[(99, 0)] - (-2.0f32).exp()).abs() < 1e-3);
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let dynamics = Dynamics::new(&petri, &controls);
+    let traj = ssa::simulate(&petri.get_petri_data(), &dynamics, &arr1(&[100]), 10.0, 100, &mut rng);
+    assert_eq!(traj[(99, 0)], traj[(20, 0)]);
+
+    assert_eq!(parse_breakpoints("2:0, 5:1"), Some(vec![(2.0, 0.0), (5.0, 1.0)]));
+    assert_eq!(parse_breakpoints("NaN:1, 2:3"), None);
+    assert_eq!(parse_breakpoints("2:inf"), None);
+}
+
+#[test]
+fn inhibitor_and_read_arcs() {
+    // A catalysed by E converts to B, until B reaches 3
+    let mut convert = Transition::new("convert", vec![1, 0, 0], vec![0, 1, 0]);
+    convert.read = vec![0, 0, 1];
+    convert.inhibit = vec![0, 3, 0];
+    let petri = PetriNet::new("switch", vec!["A".to_string(), "B".to_string(), "E".to_string()], vec![convert]);
+
+    let graph = reachability::reachability_graph(&petri, &[5, 0, 1], 100);
+    assert!(graph.complete);
+    assert_eq!(graph.markings.len(), 4);
+    assert_eq!(graph.deadlocks(&petri), vec![&[2, 3, 1][..]]);
+    // Without the catalyst nothing fires
+    assert_eq!(reachability::reachability_graph(&petri, &[5, 0, 0], 100).markings.len(), 1);
+
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![5.0, 0.0, 1.0];
+    controls.rates = vec![1.0];
+    let yvals = petri.simulate(&controls, 100);
+    assert!((yvals[(99, 1)] - 3.0).abs() < 0.1);
+    assert_eq!(yvals[(99, 2)], 1.0);
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let dynamics = Dynamics::new(&petri, &controls);
+    let traj = ssa::simulate(&petri.get_petri_data(), &dynamics, &arr1(&[5, 0, 1]), 10.0, 100, &mut rng);
+    assert_eq!(traj.row(99).to_vec(), vec![2.0, 3.0, 1.0]);
+}
+
+#[test]
+fn deterministic_delays_and_memory_policies() {
+    // A machine turning A into B every two time units, switched off by X on [1, 1.5]
+    let mut work = Transition::new("work", vec![1, 0, 0], vec![0, 1, 0]);
+    work.timing = Timing::Deterministic(2.0);
+    work.inhibit = vec![0, 0, 1];
+    let mut petri = PetriNet::new("machine", vec!["A".to_string(), "B".to_string(), "X".to_string()], vec![work]);
+    petri.events = vec![
+        Event { trigger: Trigger::At(1.0), actions: vec![Action::AddTokens("X".to_string(), 1.0)] },
+        Event { trigger: Trigger::At(1.5), actions: vec![Action::AddTokens("X".to_string(), -1.0)] },
+    ];
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![3.0, 0.0, 0.0];
+    controls.rates = vec![1.0];
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let run = |petri: &PetriNet, rng: &mut StdRng| {
+        let dynamics = Dynamics::new(petri, &controls);
+        gsmp::simulate(petri, &dynamics, &arr1(&[3, 0, 0]), 10.0, 100, rng)
+    };
+    // The clock restarts at 1.5, so the first firing is at 3.5
+    let traj = run(&petri, &mut rng);
+    assert_eq!(traj[(29, 1)], 0.0);
+    assert_eq!(traj[(39, 1)], 1.0);
+    assert_eq!(traj[(99, 1)], 3.0);
+    // The clock resumes at 1.5 with 1 left, so the first firing is at 2.5
+    petri.transitions[0].policy = Policy::AgeMemory;
+    let traj = run(&petri, &mut rng);
+    assert_eq!(traj[(29, 1)], 1.0);
+    assert_eq!(traj[(49, 1)], 2.0);
+}
+
+#[test]
+fn reaction_diffusion_conserves_mass_without_flux() {
+    let (petri, _) = decay(0.5);
+    let mut diffusion = Diffusion::new(9, 5);
+    diffusion.coefficients = vec![2.0];
+    diffusion.seed = Some((0, 45.0));
+    let mut rng = StdRng::seed_from_u64(0);
+    let init = diffusion.initial_state(&[1.0], &mut rng);
+    let frames = diffusion.solve(&petri.get_petri_data(), &[0.5], &init, 2.0, 4);
+    // The spot spreads out symmetrically while the total decays
+    let total: f32 = frames.slice(s![3, .., 0]).sum();
+    assert!((total - 90.0 * (-1.0f32).exp()).abs() < 1e-2);
+    let center = 2 * 9 + 4;
+    assert!(frames[(3, center, 0)] < frames[(0, center, 0)]);
+    assert!((frames[(3, center - 1, 0)] - frames[(3, center + 1, 0)]).abs() < 1e-4);
+
+    // Absorbing boundaries lose mass
+    diffusion.boundary = Boundary::Dirichlet(0.0);
+    let frames = diffusion.solve(&petri.get_petri_data(), &[0.5], &init, 2.0, 4);
+    assert!(frames.slice(s![3, .., 0]).sum() < total - 1.0);
+}
+
+#[test]
+fn delayed_outputs_appear_after_the_delay() {
+    let mut petri = PetriNet::new("incubation", vec!["E".to_string(), "I".to_string()], vec![
+        Transition::new("onset", vec![1, 0], vec![0, 1]),
+    ]);
+    petri.transitions[0].delay = 1.0;
+    let mut controls = PlotControls::empty();
+    controls.init_vals = vec![1.0, 0.0];
+    controls.rates = vec![1.0];
+    controls.xmax = 4.0;
+    let yvals = petri.simulate(&controls, 100);
+    // E decays at once, and I(t) = 1 - exp(-(t - 1)) after the delay
+    assert!((yvals[(49, 0)] - (-2.0f32).exp()).abs() < 1e-3);
+    assert!(yvals[(19, 1)].abs() < 1e-6);
+    assert!((yvals[(99, 1)] - (1. - (-3.0f32).exp())).abs() < 1e-3);
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let dynamics = Dynamics::new(&petri, &controls);
+    let traj = ssa::simulate(&petri.get_petri_data(), &dynamics, &arr1(&[50, 0]), 4.0, 100, &mut rng);
+    assert!(traj.column(1).iter().take(24).all(|x| *x == 0.));
+    assert!(traj[(99, 1)] > 0.);
+    assert!(traj[(99, 0)] + traj[(99, 1)] <= 50.);
+}
+
+#[test]
+fn trajectories_export_as_csv_and_json() {
+    let (petri, mut controls) = decay(1.0);
+    controls.init_vals = vec![100.0];
+    let csv = petri.trajectory(&controls, 100).to_csv();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 101);
+    assert_eq!(lines[0], "t,A");
+    let last: Vec<f32> = lines[100].split(',').map(|x| x.parse().unwrap()).collect();
+    assert_eq!(last[0], 10.0);
+    assert!((last[1] - 100.0 * (-10f32).exp()).abs() < 1e-2);
+
+    let mut rng = StdRng::seed_from_u64(7);
+    let summary = petri.ensemble_summary(&controls, 100, 200, &mut rng);
+    assert_eq!(summary.columns, vec!["A mean", "A sd", "A q0.05", "A q0.5", "A q0.95"]);
+    let json: serde_json::Value = serde_json::from_str(&summary.to_json()).unwrap();
+    let mean = json["columns"]["A mean"][9].as_f64().unwrap();
+    assert_eq!(json["t"][9].as_f64().unwrap(), 1.0);
+    assert!((mean - 100.0 * (-1f64).exp()).abs() < 2.0);
+    assert!(json["columns"]["A q0.05"][9].as_f64().unwrap() <= mean);
+}